@@ -0,0 +1,167 @@
+// Constant expressions for immediates, displacements and directives, with C precedence:
+// unary - ~ +, then * / %, + -, << >>, &, ^, |
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Num(i64),
+    Ident(&'a str),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Num(n) => write!(f, "`{}`", n),
+            Token::Ident(name) => write!(f, "`{}`", name),
+            Token::Op(op) => write!(f, "`{}`", op),
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+        }
+    }
+}
+
+const OPS: [&str; 12] = ["<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "!"];
+
+fn tokenize(expr: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut tokens = Vec::new();
+    let bytes = expr.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if c == b'(' {
+            tokens.push(Token::Open);
+            i += 1;
+        } else if c == b')' {
+            tokens.push(Token::Close);
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < bytes.len() && bytes[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            tokens.push(Token::Num(parse_number(&expr[start..i])?));
+        } else if c.is_ascii_alphabetic() || c == b'_' || c == b'.' {
+            let start = i;
+            while i < bytes.len()
+                && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] == b'.')
+            {
+                i += 1;
+            }
+            tokens.push(Token::Ident(&expr[start..i]));
+        } else {
+            match OPS.iter().find(|op| expr[i..].starts_with(*op)) {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    i += op.len();
+                }
+                None => return Err(format!("unexpected character {:?}", c as char)),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+pub(crate) fn parse_number(s: &str) -> Result<i64, String> {
+    let parsed = if let Some(hex) = s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).map(|v| v as i64)
+    } else if let Some(bin) = s.strip_prefix("0b") {
+        u64::from_str_radix(bin, 2).map(|v| v as i64)
+    } else {
+        s.parse::<u64>().map(|v| v as i64)
+    };
+    parsed.map_err(|_| format!("bad number {:?}", s))
+}
+
+fn binding(op: &str) -> Option<u8> {
+    match op {
+        "|" => Some(1),
+        "^" => Some(2),
+        "&" => Some(3),
+        "<<" | ">>" => Some(4),
+        "+" | "-" => Some(5),
+        "*" | "/" | "%" => Some(6),
+        _ => None,
+    }
+}
+
+struct Parser<'a, 'b> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    lookup: &'b dyn Fn(&str) -> Option<i64>,
+}
+
+impl Parser<'_, '_> {
+    fn unary(&mut self) -> Result<i64, String> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        match token {
+            Some(Token::Num(n)) => Ok(*n),
+            Some(Token::Ident(name)) => {
+                (self.lookup)(name).ok_or_else(|| format!("undefined symbol `{}`", name))
+            }
+            Some(Token::Op("-")) => Ok(self.unary()?.wrapping_neg()),
+            Some(Token::Op("+")) => self.unary(),
+            Some(Token::Op("~")) => Ok(!self.unary()?),
+            Some(Token::Op("!")) => Ok((self.unary()? == 0) as i64),
+            Some(Token::Open) => {
+                let val = self.binary(0)?;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(val)
+                    }
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            Some(token) => Err(format!("unexpected {}", token)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+
+    fn binary(&mut self, min: u8) -> Result<i64, String> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Op(op)) = self.tokens.get(self.pos) {
+            let op = *op;
+            let prec = match binding(op) {
+                Some(prec) if prec > min => prec,
+                _ => break,
+            };
+            self.pos += 1;
+            let rhs = self.binary(prec)?;
+            lhs = match op {
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "<<" => lhs.wrapping_shl(rhs as u32),
+                ">>" => lhs.wrapping_shr(rhs as u32),
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                "/" | "%" if rhs == 0 => return Err("division by zero".to_string()),
+                "/" => lhs.wrapping_div(rhs),
+                _ => lhs.wrapping_rem(rhs),
+            };
+        }
+        Ok(lhs)
+    }
+}
+
+pub(crate) fn eval(expr: &str, lookup: &dyn Fn(&str) -> Option<i64>) -> Result<i64, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expr)?,
+        pos: 0,
+        lookup,
+    };
+    if parser.tokens.is_empty() {
+        return Err("missing expression".to_string());
+    }
+    let val = parser.binary(0)?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(val),
+        Some(token) => Err(format!("unexpected {}", token)),
+    }
+}
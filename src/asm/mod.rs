@@ -0,0 +1,606 @@
+use std::{collections::HashMap, fmt::Display, fs, path::Path};
+
+use crate::{linker::listing_line, LoadMode, Machine, SourceMap, REG_NAMES, RUNTIME};
+
+pub(crate) mod expr;
+mod preprocess;
+
+use preprocess::{Preprocessor, SrcLine};
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Form {
+    Bare,
+    RegReg,
+    ImmReg,
+    RegMem,
+    MemReg,
+    Dest,
+    Reg,
+}
+
+impl Form {
+    pub(crate) fn size(self) -> usize {
+        match self {
+            Form::Bare => 1,
+            Form::RegReg | Form::Reg => 2,
+            Form::ImmReg | Form::RegMem | Form::MemReg => 10,
+            Form::Dest => 9,
+        }
+    }
+
+    fn operands(self) -> usize {
+        match self {
+            Form::Bare => 0,
+            Form::Dest | Form::Reg => 1,
+            _ => 2,
+        }
+    }
+}
+
+pub(crate) const MNEMONICS: [(&str, u8, Form); 27] = [
+    ("halt", 0x00, Form::Bare),
+    ("nop", 0x10, Form::Bare),
+    ("rrmovq", 0x20, Form::RegReg),
+    ("cmovle", 0x21, Form::RegReg),
+    ("cmovl", 0x22, Form::RegReg),
+    ("cmove", 0x23, Form::RegReg),
+    ("cmovne", 0x24, Form::RegReg),
+    ("cmovge", 0x25, Form::RegReg),
+    ("cmovg", 0x26, Form::RegReg),
+    ("irmovq", 0x30, Form::ImmReg),
+    ("rmmovq", 0x40, Form::RegMem),
+    ("mrmovq", 0x50, Form::MemReg),
+    ("addq", 0x60, Form::RegReg),
+    ("subq", 0x61, Form::RegReg),
+    ("andq", 0x62, Form::RegReg),
+    ("xorq", 0x63, Form::RegReg),
+    ("jmp", 0x70, Form::Dest),
+    ("jle", 0x71, Form::Dest),
+    ("jl", 0x72, Form::Dest),
+    ("je", 0x73, Form::Dest),
+    ("jne", 0x74, Form::Dest),
+    ("jge", 0x75, Form::Dest),
+    ("jg", 0x76, Form::Dest),
+    ("call", 0x80, Form::Dest),
+    ("ret", 0x90, Form::Bare),
+    ("pushq", 0xa0, Form::Reg),
+    ("popq", 0xb0, Form::Reg),
+];
+
+pub(crate) fn mnemonic(name: &str) -> Option<(u8, Form)> {
+    MNEMONICS
+        .iter()
+        .find(|(m, _, _)| *m == name)
+        .map(|&(_, code, form)| (code, form))
+}
+
+pub(crate) fn register(name: &str) -> Result<u8, String> {
+    let name = name.trim();
+    let idx = match name {
+        "%r8" => Some(8),
+        "%r9" => Some(9),
+        _ => REG_NAMES.iter().position(|r| *r == name),
+    };
+    match idx {
+        Some(idx) => Ok(idx as u8),
+        None => Err(format!("unknown register `{}`", name)),
+    }
+}
+
+pub(crate) fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(i) => &line[..i],
+        None => line,
+    }
+}
+
+pub(crate) fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+// peels `name:` labels off the front of a line
+pub(crate) fn split_labels(code: &str) -> (Vec<&str>, &str) {
+    let mut labels = Vec::new();
+    let mut rest = code;
+    while let Some(colon) = rest.find(':') {
+        let label = rest[..colon].trim();
+        if !is_ident(label) {
+            break;
+        }
+        labels.push(label);
+        rest = &rest[colon + 1..];
+    }
+    (labels, rest)
+}
+
+// the column of the statement on a line, past any labels, or of a line with only labels
+pub(crate) fn statement_column(text: &str) -> usize {
+    let code = strip_comment(text);
+    let (_, rest) = split_labels(code);
+    let start = match rest.trim().is_empty() {
+        true => text.len() - text.trim_start().len(),
+        false => code.len() - rest.trim_start().len(),
+    };
+    text[..start].chars().count() + 1
+}
+
+// splits on commas outside of parentheses
+pub(crate) fn split_operands(s: &str) -> Vec<&str> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Vec::new();
+    }
+    let mut ops = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                ops.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    ops.push(s[start..].trim());
+    ops
+}
+
+enum Stmt<'a> {
+    Empty,
+    Instr(&'a str, Vec<&'a str>),
+    Directive(&'a str, &'a str),
+}
+
+fn parse_stmt(text: &str) -> (Vec<&str>, Stmt<'_>) {
+    let (labels, rest) = split_labels(strip_comment(text));
+    let rest = rest.trim();
+    if rest.is_empty() {
+        return (labels, Stmt::Empty);
+    }
+    let (word, args) = match rest.split_once(|c: char| c.is_whitespace()) {
+        Some((word, args)) => (word, args.trim()),
+        None => (rest, ""),
+    };
+    if word.starts_with('.') {
+        (labels, Stmt::Directive(word, args))
+    } else {
+        (labels, Stmt::Instr(word, split_operands(args)))
+    }
+}
+
+fn data_width(directive: &str) -> Option<usize> {
+    match directive {
+        ".quad" => Some(8),
+        ".long" => Some(4),
+        ".word" => Some(2),
+        ".byte" => Some(1),
+        _ => None,
+    }
+}
+
+// `D(%rB)`, `(%rB)` or a bare `D`, the last using F as the base register
+fn mem_operand(op: &str) -> Result<(Option<&str>, u8), String> {
+    let op = op.trim();
+    if let Some(inner) = op.strip_suffix(')') {
+        if let Some(open) = inner.rfind('(') {
+            if inner[open + 1..].trim_start().starts_with('%') {
+                let disp = op[..open].trim();
+                let disp = if disp.is_empty() { None } else { Some(disp) };
+                return Ok((disp, register(&inner[open + 1..])?));
+            }
+        }
+    }
+    Ok((Some(op), 0xf))
+}
+
+fn encode(
+    name: &str,
+    ops: &[&str],
+    eval: &dyn Fn(&str) -> Result<i64, String>,
+) -> Result<Vec<u8>, String> {
+    let (code, form) = match mnemonic(name) {
+        Some(found) => found,
+        None => return Err(format!("unknown instruction `{}`", name)),
+    };
+    if ops.len() != form.operands() {
+        return Err(format!(
+            "`{}` expects {} operand(s), found {}",
+            name,
+            form.operands(),
+            ops.len()
+        ));
+    }
+    let imm = |op: &str| eval(op.trim().strip_prefix('$').unwrap_or(op));
+
+    let mut bytes = vec![code];
+    match form {
+        Form::Bare => (),
+        Form::RegReg => bytes.push(register(ops[0])? << 4 | register(ops[1])?),
+        Form::Reg => bytes.push(register(ops[0])? << 4 | 0xf),
+        Form::ImmReg => {
+            bytes.push(0xf0 | register(ops[1])?);
+            bytes.extend(imm(ops[0])?.to_le_bytes());
+        }
+        Form::RegMem | Form::MemReg => {
+            let (reg, mem) = if form == Form::RegMem {
+                (ops[0], ops[1])
+            } else {
+                (ops[1], ops[0])
+            };
+            let (disp, base) = mem_operand(mem)?;
+            bytes.push(register(reg)? << 4 | base);
+            bytes.extend(disp.map_or(Ok(0), eval)?.to_le_bytes());
+        }
+        Form::Dest => bytes.extend(imm(ops[0])?.to_le_bytes()),
+    }
+
+    Ok(bytes)
+}
+
+#[derive(Debug)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    /// 1-based, in characters, where the statement at fault starts.
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+pub struct ProgramLine {
+    pub addr: Option<usize>,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    /// Index into `Program::files` and 1-based line of the text that produced this line.
+    pub file: usize,
+    pub line: usize,
+    root: usize,
+}
+
+/// An assembled `.ys` program, with every listing line traced back to its source.
+pub struct Program {
+    pub files: Vec<(String, String)>,
+    pub lines: Vec<ProgramLine>,
+    pub symbols: Vec<(String, usize)>,
+}
+
+impl Program {
+    pub fn to_listing(&self) -> String {
+        self.lines
+            .iter()
+            .map(|l| listing_line(l.addr, &l.bytes, &format!(" {}", l.text)))
+            .collect()
+    }
+}
+
+/// Assembles `.ys` source. Besides the instructions it understands `.pos`, `.align`,
+/// `.quad`/`.long`/`.word`/`.byte`, `.equ`/`.set` constants, `.include "file"` and
+/// `.macro name params`/`.endm` blocks whose bodies refer to parameters as `\param`
+/// (and to a per-expansion counter as `\@`). A file is only included once, and the
+/// bundled runtime is available as `y86/<routine>.ys`.
+pub struct Assembler {
+    files: HashMap<String, String>,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Assembler::new()
+    }
+}
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        let mut asm = Assembler {
+            files: HashMap::new(),
+        };
+        for (name, text) in RUNTIME {
+            asm.add_file(name, text);
+        }
+        asm
+    }
+
+    /// Makes `text` includable as `name` without touching the filesystem.
+    pub fn add_file(&mut self, name: &str, text: &str) {
+        self.files.insert(name.to_string(), text.to_string());
+    }
+
+    pub(crate) fn read_include(
+        &self,
+        from: &str,
+        path: &str,
+    ) -> Result<(String, String), std::io::Error> {
+        if let Some(text) = self.files.get(path) {
+            return Ok((path.to_string(), text.clone()));
+        }
+        let resolved = match Path::new(from).parent() {
+            Some(dir) => dir.join(path).to_string_lossy().into_owned(),
+            None => path.to_string(),
+        };
+        match self.files.get(&resolved) {
+            Some(text) => Ok((resolved, text.clone())),
+            None => Ok((resolved.clone(), fs::read_to_string(&resolved)?)),
+        }
+    }
+
+    pub fn assemble_file(&self, path: &str) -> Result<Program, Vec<AsmError>> {
+        match fs::read_to_string(path) {
+            Ok(text) => self.assemble(path, &text),
+            Err(e) => Err(vec![AsmError {
+                file: path.to_string(),
+                line: 0,
+                column: 0,
+                message: e.to_string(),
+            }]),
+        }
+    }
+
+    pub fn assemble(&self, name: &str, text: &str) -> Result<Program, Vec<AsmError>> {
+        let mut pre = Preprocessor::new(self);
+        pre.file(name, text, None, 0);
+        let mut errors = pre.errors;
+        let files = pre.files;
+
+        let mut pass = Pass {
+            files: &files,
+            labels: HashMap::new(),
+            equs: HashMap::new(),
+            sets: HashMap::new(),
+            errors: Vec::new(),
+        };
+        let addrs = pass.layout(&pre.lines);
+        let lines = pass.emit(&pre.lines, &addrs);
+        errors.append(&mut pass.errors);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut symbols: Vec<(String, usize)> = pass
+            .labels
+            .into_iter()
+            .map(|(name, addr)| (name, addr as usize))
+            .collect();
+        symbols.sort_by_key(|&(_, addr)| addr);
+
+        Ok(Program {
+            files,
+            lines,
+            symbols,
+        })
+    }
+}
+
+struct Pass<'a> {
+    files: &'a [(String, String)],
+    labels: HashMap<String, i64>,
+    equs: HashMap<String, i64>,
+    sets: HashMap<String, i64>,
+    errors: Vec<AsmError>,
+}
+
+impl Pass<'_> {
+    fn error(&mut self, line: &SrcLine, message: String) {
+        self.errors.push(AsmError {
+            file: self.files[line.loc.file].0.clone(),
+            line: line.loc.line,
+            column: statement_column(&line.text),
+            message,
+        });
+    }
+
+    fn eval(&self, expr: &str) -> Result<i64, String> {
+        expr::eval(expr, &|name| {
+            self.sets
+                .get(name)
+                .or(self.equs.get(name))
+                .or(self.labels.get(name))
+                .copied()
+        })
+    }
+
+    fn split_constant<'b>(&mut self, line: &SrcLine, args: &'b str) -> Option<(&'b str, &'b str)> {
+        match args.split_once(',') {
+            Some((name, value)) if is_ident(name.trim()) => Some((name.trim(), value)),
+            _ => {
+                self.error(line, "expected `name, value`".to_string());
+                None
+            }
+        }
+    }
+
+    // returns false when the value refers to something not defined yet
+    fn equ(&mut self, line: &SrcLine, args: &str) -> bool {
+        let (name, value) = match self.split_constant(line, args) {
+            Some(found) => found,
+            None => return true,
+        };
+        if self.labels.contains_key(name) || self.equs.contains_key(name) {
+            self.error(line, format!("`{}` is already defined", name));
+            return true;
+        }
+        match self.eval(value) {
+            Ok(val) => {
+                self.equs.insert(name.to_string(), val);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn set(&mut self, line: &SrcLine, args: &str) {
+        if let Some((name, value)) = self.split_constant(line, args) {
+            match self.eval(value) {
+                Ok(val) => self.sets.insert(name.to_string(), val),
+                Err(_) => self.sets.remove(name),
+            };
+        }
+    }
+
+    // first pass: sizes every line, defines labels and settles constants. Returns the
+    // listing address of each line.
+    fn layout(&mut self, lines: &[SrcLine]) -> Vec<Option<usize>> {
+        let mut addrs = Vec::new();
+        let mut addr: i64 = 0;
+        let mut pending = Vec::new();
+
+        for (i, line) in lines.iter().enumerate() {
+            let (labels, stmt) = parse_stmt(&line.text);
+            let mut line_addr = if labels.is_empty() { None } else { Some(addr) };
+            for label in labels {
+                if self.labels.contains_key(label) || self.equs.contains_key(label) {
+                    self.error(line, format!("`{}` is already defined", label));
+                }
+                self.labels.insert(label.to_string(), addr);
+            }
+
+            match stmt {
+                Stmt::Empty => (),
+                Stmt::Instr(name, _) => match mnemonic(name) {
+                    Some((_, form)) => {
+                        line_addr = Some(addr);
+                        addr += form.size() as i64;
+                    }
+                    None => self.error(line, format!("unknown instruction `{}`", name)),
+                },
+                Stmt::Directive(".pos", args) => match self.eval(args) {
+                    Ok(pos) if pos >= 0 => {
+                        addr = pos;
+                        line_addr = Some(addr);
+                    }
+                    Ok(_) => self.error(line, "negative `.pos`".to_string()),
+                    Err(e) => self.error(line, format!("bad `.pos`: {}", e)),
+                },
+                Stmt::Directive(".align", args) => match self.eval(args) {
+                    Ok(n) if n > 0 && n & (n - 1) == 0 => {
+                        addr = (addr + n - 1) & !(n - 1);
+                        line_addr = Some(addr);
+                    }
+                    Ok(_) => self.error(line, "`.align` needs a power of two".to_string()),
+                    Err(e) => self.error(line, format!("bad `.align`: {}", e)),
+                },
+                Stmt::Directive(".equ", args) => {
+                    if !self.equ(line, args) {
+                        pending.push(i);
+                    }
+                }
+                Stmt::Directive(".set", args) => self.set(line, args),
+                Stmt::Directive(name, args) => match data_width(name) {
+                    Some(width) => {
+                        line_addr = Some(addr);
+                        addr += (width * split_operands(args).len()) as i64;
+                    }
+                    None => self.error(line, format!("unknown directive `{}`", name)),
+                },
+            }
+            addrs.push(line_addr.map(|a| a as usize));
+        }
+
+        // constants that referred to labels further down
+        while !pending.is_empty() {
+            let before = pending.len();
+            pending.retain(|&i| {
+                let args = match parse_stmt(&lines[i].text).1 {
+                    Stmt::Directive(_, args) => args,
+                    _ => return false,
+                };
+                let (name, value) = args.split_once(',').unwrap();
+                match self.eval(value) {
+                    Ok(val) => {
+                        self.equs.insert(name.trim().to_string(), val);
+                        false
+                    }
+                    Err(_) => true,
+                }
+            });
+            if pending.len() == before {
+                break;
+            }
+        }
+        for i in pending {
+            if let Stmt::Directive(_, args) = parse_stmt(&lines[i].text).1 {
+                let (_, value) = args.split_once(',').unwrap();
+                let e = self.eval(value).unwrap_err();
+                self.error(&lines[i], format!("cannot evaluate constant: {}", e));
+            }
+        }
+        self.sets.clear();
+
+        addrs
+    }
+
+    fn emit(&mut self, lines: &[SrcLine], addrs: &[Option<usize>]) -> Vec<ProgramLine> {
+        let mut out = Vec::new();
+        for (line, &addr) in lines.iter().zip(addrs) {
+            let bytes = match parse_stmt(&line.text).1 {
+                // unknown instructions were already reported by the first pass
+                Stmt::Instr(name, _) if mnemonic(name).is_none() => Ok(Vec::new()),
+                Stmt::Instr(name, ops) => encode(name, &ops, &|e| self.eval(e)),
+                Stmt::Directive(".set", args) => {
+                    self.set(line, args);
+                    Ok(Vec::new())
+                }
+                Stmt::Directive(name, args) => match data_width(name) {
+                    Some(width) => split_operands(args)
+                        .into_iter()
+                        .map(|e| self.eval(e).map(|v| v.to_le_bytes()[..width].to_vec()))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map(|v| v.concat()),
+                    None => Ok(Vec::new()),
+                },
+                Stmt::Empty => Ok(Vec::new()),
+            };
+            let bytes = bytes.unwrap_or_else(|e| {
+                self.error(line, e);
+                Vec::new()
+            });
+
+            out.push(ProgramLine {
+                addr,
+                bytes,
+                text: line.text.clone(),
+                file: line.loc.file,
+                line: line.loc.line,
+                root: line.loc.root,
+            });
+        }
+        out
+    }
+}
+
+impl Machine {
+    /// Loads an assembled program, mapping addresses back to lines of the top level `.ys`.
+    pub fn load_program(&mut self, program: &Program) -> Result<(), anyhow::Error> {
+        self.load_with(&program.to_listing(), LoadMode::Fail)?;
+
+        self.source = SourceMap::new();
+        if let Some((_, text)) = program.files.first() {
+            for line in text.lines() {
+                self.source.push_line(line);
+            }
+        }
+        for line in program.lines.iter().filter(|l| !l.bytes.is_empty()) {
+            if let Some(addr) = line.addr {
+                self.source.map(addr, line.root);
+            }
+        }
+
+        Ok(())
+    }
+}
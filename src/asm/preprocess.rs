@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use super::{split_labels, split_operands, statement_column, strip_comment, AsmError, Assembler};
+
+const MAX_DEPTH: usize = 32;
+
+// where a line of preprocessed source came from. `root` is the line in the top level file
+// that produced it, so includes and macro expansions map back to something the user wrote.
+#[derive(Clone, Copy)]
+pub(crate) struct Loc {
+    pub(crate) file: usize,
+    pub(crate) line: usize,
+    pub(crate) root: usize,
+}
+
+pub(crate) struct SrcLine {
+    pub(crate) loc: Loc,
+    pub(crate) text: String,
+}
+
+struct Macro {
+    params: Vec<(String, Option<String>)>,
+    body: Vec<String>,
+}
+
+pub(crate) struct Preprocessor<'a> {
+    asm: &'a Assembler,
+    pub(crate) files: Vec<(String, String)>,
+    pub(crate) lines: Vec<SrcLine>,
+    pub(crate) errors: Vec<AsmError>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+}
+
+fn directive<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let code = strip_comment(text).trim_start();
+    let rest = code.strip_prefix(name)?;
+    match rest.chars().next() {
+        None => Some(rest),
+        Some(c) if c.is_whitespace() => Some(rest.trim()),
+        _ => None,
+    }
+}
+
+impl Preprocessor<'_> {
+    pub(crate) fn new(asm: &Assembler) -> Preprocessor<'_> {
+        Preprocessor {
+            asm,
+            files: Vec::new(),
+            lines: Vec::new(),
+            errors: Vec::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+        }
+    }
+
+    fn error(&mut self, loc: Loc, message: String) {
+        let (file, text) = &self.files[loc.file];
+        let text = text.lines().nth(loc.line - 1).unwrap_or_default();
+        self.errors.push(AsmError {
+            file: file.clone(),
+            line: loc.line,
+            column: statement_column(text),
+            message,
+        });
+    }
+
+    pub(crate) fn file(&mut self, name: &str, text: &str, root: Option<usize>, depth: usize) {
+        let file = self.files.len();
+        self.files.push((name.to_string(), text.to_string()));
+
+        let mut lines = text.lines().enumerate();
+        while let Some((i, line)) = lines.next() {
+            let loc = Loc {
+                file,
+                line: i + 1,
+                root: root.unwrap_or(i + 1),
+            };
+
+            if let Some(header) = directive(line, ".macro") {
+                let mut body = Vec::new();
+                let mut closed = false;
+                for (_, line) in lines.by_ref() {
+                    if directive(line, ".endm").is_some() {
+                        closed = true;
+                        break;
+                    }
+                    body.push(line.to_string());
+                }
+                if !closed {
+                    self.error(loc, "`.macro` without matching `.endm`".to_string());
+                }
+                self.define(loc, header, body);
+                continue;
+            }
+            if directive(line, ".endm").is_some() {
+                self.error(loc, "`.endm` without `.macro`".to_string());
+                continue;
+            }
+
+            self.line(line, loc, depth);
+        }
+    }
+
+    fn define(&mut self, loc: Loc, header: &str, body: Vec<String>) {
+        let (name, params) = match header.split_once(|c: char| c.is_whitespace()) {
+            Some((name, params)) => (name, params),
+            None => (header, ""),
+        };
+        if name.is_empty() {
+            return self.error(loc, "`.macro` needs a name".to_string());
+        }
+        let params = split_operands(params)
+            .into_iter()
+            .map(|p| match p.split_once('=') {
+                Some((p, default)) => (p.trim().to_string(), Some(default.trim().to_string())),
+                None => (p.to_string(), None),
+            })
+            .collect();
+        self.macros.insert(name.to_string(), Macro { params, body });
+    }
+
+    fn line(&mut self, line: &str, loc: Loc, depth: usize) {
+        let (labels, rest) = split_labels(strip_comment(line));
+        let (word, args) = match rest.trim().split_once(|c: char| c.is_whitespace()) {
+            Some((word, args)) => (word, args.trim()),
+            None => (rest.trim(), ""),
+        };
+
+        if word == ".include" {
+            let path = match args.strip_prefix('"').and_then(|a| a.strip_suffix('"')) {
+                Some(path) => path,
+                None => {
+                    return self.error(loc, "`.include` expects a quoted file name".to_string())
+                }
+            };
+            if depth >= MAX_DEPTH {
+                return self.error(loc, "includes nested too deeply".to_string());
+            }
+            self.labels_only(&labels, loc);
+            let from = self.files[loc.file].0.clone();
+            match self.asm.read_include(&from, path) {
+                Ok((name, _)) if self.files.iter().any(|(f, _)| *f == name) => (),
+                Ok((name, text)) => self.file(&name, &text, Some(loc.root), depth + 1),
+                Err(e) => self.error(loc, format!("cannot include {:?}: {}", path, e)),
+            }
+        } else if let Some((params, mac_body)) = self
+            .macros
+            .get(word)
+            .map(|m| (m.params.clone(), m.body.clone()))
+        {
+            if depth >= MAX_DEPTH {
+                return self.error(loc, format!("macro `{}` expands too deeply", word));
+            }
+            let args = split_operands(args);
+            if args.len() > params.len() {
+                let msg = format!(
+                    "macro `{}` takes {} arguments, found {}",
+                    word,
+                    params.len(),
+                    args.len()
+                );
+                return self.error(loc, msg);
+            }
+
+            let mut subst = Vec::new();
+            for (i, (param, default)) in params.iter().enumerate() {
+                match args.get(i).map(|a| a.to_string()).or(default.clone()) {
+                    Some(arg) => subst.push((format!("\\{}", param), arg)),
+                    None => {
+                        let msg = format!("macro `{}` is missing argument `{}`", word, param);
+                        return self.error(loc, msg);
+                    }
+                }
+            }
+            // longest names first so `\ab` is not replaced as `\a` followed by `b`
+            subst.sort_by_key(|(param, _)| std::cmp::Reverse(param.len()));
+            self.expansions += 1;
+            let body: Vec<String> = mac_body
+                .iter()
+                .map(|line| {
+                    let mut line = line.replace("\\@", &self.expansions.to_string());
+                    for (param, arg) in &subst {
+                        line = line.replace(param, arg);
+                    }
+                    line
+                })
+                .collect();
+
+            self.labels_only(&labels, loc);
+            for line in body {
+                self.line(&line, loc, depth + 1);
+            }
+        } else {
+            self.lines.push(SrcLine {
+                loc,
+                text: line.to_string(),
+            });
+        }
+    }
+
+    fn labels_only(&mut self, labels: &[&str], loc: Loc) {
+        if !labels.is_empty() {
+            self.lines.push(SrcLine {
+                loc,
+                text: labels.iter().map(|l| format!("{}:", l)).collect(),
+            });
+        }
+    }
+}
@@ -0,0 +1,7 @@
+use std::io;
+
+use y86_rs::LanguageServer;
+
+fn main() -> Result<(), anyhow::Error> {
+    LanguageServer::new().serve(&mut io::stdin().lock(), &mut io::stdout())
+}
@@ -0,0 +1,404 @@
+use std::{fmt::Display, io::Write, ops::Range};
+
+use crate::{CycleState, Machine, OpCode};
+
+/// Which line of a full set makes room for a new block.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Replacement {
+    Lru,
+    Fifo,
+    Random,
+}
+
+/// Write-back caches allocate a line on a write miss and write dirty lines out when they
+/// are evicted; write-through caches send every write on to memory and do not allocate.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WritePolicy {
+    WriteBack,
+    WriteThrough,
+}
+
+/// The geometry and policies of one cache.
+///
+/// Written as `<size>:<ways>:<block>[:<replacement>][:<write policy>]`, with sizes in bytes
+/// (or with a `k` suffix), replacement `lru`, `fifo` or `random` and write policy `wb` or
+/// `wt`, e.g. `1k:2:32:lru:wb`. LRU and write-back are the defaults.
+#[derive(Clone, Debug)]
+pub struct CacheConfig {
+    pub size: usize,
+    pub ways: usize,
+    pub block: usize,
+    pub replacement: Replacement,
+    pub write: WritePolicy,
+}
+
+fn parse_size(text: &str) -> Result<usize, String> {
+    let (digits, scale) = match text.strip_suffix(['k', 'K']) {
+        Some(digits) => (digits, 1024),
+        None => (text, 1),
+    };
+    match digits.parse::<usize>().map(|n| n.checked_mul(scale)) {
+        Ok(Some(n)) => Ok(n),
+        Ok(None) => Err(format!("size `{}` is too large", text)),
+        Err(_) => Err(format!("bad size `{}`", text)),
+    }
+}
+
+impl CacheConfig {
+    pub fn parse(spec: &str) -> Result<CacheConfig, String> {
+        let mut parts = spec.split(':');
+        let mut next = |what: &str| match parts.next() {
+            Some(part) => parse_size(part),
+            None => Err(format!("missing {} in `{}`", what, spec)),
+        };
+        let mut config = CacheConfig {
+            size: next("size")?,
+            ways: next("associativity")?,
+            block: next("block size")?,
+            replacement: Replacement::Lru,
+            write: WritePolicy::WriteBack,
+        };
+        for option in spec.split(':').skip(3) {
+            match option {
+                "lru" => config.replacement = Replacement::Lru,
+                "fifo" => config.replacement = Replacement::Fifo,
+                "random" => config.replacement = Replacement::Random,
+                "wb" => config.write = WritePolicy::WriteBack,
+                "wt" => config.write = WritePolicy::WriteThrough,
+                _ => return Err(format!("unknown cache option `{}`", option)),
+            }
+        }
+        config.check()?;
+        Ok(config)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if !self.block.is_power_of_two() || self.ways == 0 {
+            return Err("the block size must be a power of two and ways at least 1".to_string());
+        }
+        let set = self.ways.checked_mul(self.block);
+        if self.size == 0 || !set.is_some_and(|set| self.size.is_multiple_of(set)) {
+            return Err(format!(
+                "a size of {} bytes is not a whole number of {}-way sets of {}-byte blocks",
+                self.size, self.ways, self.block
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn sets(&self) -> usize {
+        self.size / (self.ways * self.block)
+    }
+}
+
+impl Display for CacheConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let replacement = match self.replacement {
+            Replacement::Lru => "LRU",
+            Replacement::Fifo => "FIFO",
+            Replacement::Random => "random",
+        };
+        let write = match self.write {
+            WritePolicy::WriteBack => "write-back",
+            WritePolicy::WriteThrough => "write-through",
+        };
+        write!(
+            f,
+            "{} bytes, {}-way, {} sets of {}-byte blocks, {} replacement, {}",
+            self.size,
+            self.ways,
+            self.sets(),
+            self.block,
+            replacement,
+            write
+        )
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct CacheStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Dirty lines written back on eviction.
+    pub writebacks: u64,
+    /// Writes sent straight on to memory by a write-through cache.
+    pub memory_writes: u64,
+}
+
+/// What one access did. An evicted block is given by its address and whether it was dirty.
+pub struct Outcome {
+    pub hit: bool,
+    pub set: usize,
+    pub evicted: Option<(usize, bool)>,
+}
+
+#[derive(Clone, Default)]
+struct Line {
+    valid: bool,
+    dirty: bool,
+    tag: usize,
+    // last use for LRU, fill time for FIFO
+    stamp: u64,
+}
+
+/// A set associative cache. It only tracks which blocks it holds; the data stays in
+/// `Machine` memory.
+pub struct Cache {
+    config: CacheConfig,
+    sets: Vec<Vec<Line>>,
+    stats: CacheStats,
+    clock: u64,
+    rng: u64,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Cache {
+        let sets = vec![vec![Line::default(); config.ways]; config.sets()];
+        Cache {
+            config,
+            sets,
+            stats: CacheStats::default(),
+            clock: 0,
+            rng: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    pub fn access(&mut self, addr: usize, write: bool) -> Outcome {
+        self.clock += 1;
+        let block = addr / self.config.block;
+        let set = block % self.sets.len();
+        let tag = block / self.sets.len();
+        match write {
+            true => self.stats.writes += 1,
+            false => self.stats.reads += 1,
+        }
+        let write_back = self.config.write == WritePolicy::WriteBack;
+        if write && !write_back {
+            self.stats.memory_writes += 1;
+        }
+
+        let lines = &mut self.sets[set];
+        if let Some(line) = lines.iter_mut().find(|l| l.valid && l.tag == tag) {
+            self.stats.hits += 1;
+            if self.config.replacement == Replacement::Lru {
+                line.stamp = self.clock;
+            }
+            line.dirty |= write && write_back;
+            return Outcome {
+                hit: true,
+                set,
+                evicted: None,
+            };
+        }
+
+        self.stats.misses += 1;
+        if write && !write_back {
+            return Outcome {
+                hit: false,
+                set,
+                evicted: None,
+            };
+        }
+        let victim = match lines.iter().position(|l| !l.valid) {
+            Some(way) => way,
+            None => match self.config.replacement {
+                Replacement::Random => {
+                    // xorshift64
+                    self.rng ^= self.rng << 13;
+                    self.rng ^= self.rng >> 7;
+                    self.rng ^= self.rng << 17;
+                    (self.rng % lines.len() as u64) as usize
+                }
+                _ => (0..lines.len()).min_by_key(|&i| lines[i].stamp).unwrap(),
+            },
+        };
+        let old = std::mem::replace(
+            &mut lines[victim],
+            Line {
+                valid: true,
+                dirty: write,
+                tag,
+                stamp: self.clock,
+            },
+        );
+        let evicted = match old.valid {
+            true => {
+                self.stats.evictions += 1;
+                if old.dirty {
+                    self.stats.writebacks += 1;
+                }
+                let addr = (old.tag * self.sets.len() + set) * self.config.block;
+                Some((addr, old.dirty))
+            }
+            false => None,
+        };
+        Outcome {
+            hit: false,
+            set,
+            evicted,
+        }
+    }
+
+    /// The configuration and statistics, headed by `name`.
+    pub fn report(&self, name: &str) -> String {
+        let stats = &self.stats;
+        let accesses = stats.hits + stats.misses;
+        let rate = |n: u64| match accesses {
+            0 => 0.0,
+            _ => n as f64 * 100.0 / accesses as f64,
+        };
+        let mut out = format!("{}: {}\n", name, self.config);
+        out.push_str(&format!(
+            "  accesses {} (reads {}, writes {})  hits {} ({:.2}%)  misses {} ({:.2}%)\n",
+            accesses,
+            stats.reads,
+            stats.writes,
+            stats.hits,
+            rate(stats.hits),
+            stats.misses,
+            rate(stats.misses)
+        ));
+        match self.config.write {
+            WritePolicy::WriteBack => out.push_str(&format!(
+                "  evictions {}  writebacks {}\n",
+                stats.evictions, stats.writebacks
+            )),
+            WritePolicy::WriteThrough => out.push_str(&format!(
+                "  evictions {}  memory writes {}\n",
+                stats.evictions, stats.memory_writes
+            )),
+        }
+        out
+    }
+}
+
+/// The L1 caches a machine runs with, and where to trace their accesses to.
+#[derive(Default)]
+pub(crate) struct Caches {
+    pub(crate) icache: Option<Cache>,
+    pub(crate) dcache: Option<Cache>,
+    pub(crate) trace: Option<Box<dyn Write>>,
+}
+
+impl Caches {
+    fn access(&mut self, data: bool, range: Range<usize>, write: bool) {
+        let cache = match data {
+            true => self.dcache.as_mut(),
+            false => self.icache.as_mut(),
+        };
+        let cache = match cache {
+            Some(cache) => cache,
+            None => return,
+        };
+        let block = cache.config.block;
+        // fetches look at every byte, data accesses at every block the word touches
+        let addrs: Vec<usize> = match data {
+            true => (range.start / block..range.end.div_ceil(block))
+                .map(|b| (b * block).max(range.start))
+                .collect(),
+            false => range.collect(),
+        };
+        for addr in addrs {
+            let outcome = cache.access(addr, write);
+            if let Some(trace) = self.trace.as_mut() {
+                let mut line = format!(
+                    "{} {} 0x{:04x} set {} {}",
+                    if data { "D" } else { "I" },
+                    if write { "W" } else { "R" },
+                    addr,
+                    outcome.set,
+                    if outcome.hit { "hit" } else { "miss" }
+                );
+                if let Some((block, dirty)) = outcome.evicted {
+                    line.push_str(&format!(" evict 0x{:04x}", block));
+                    if dirty {
+                        line.push_str(" writeback");
+                    }
+                }
+                let _ = writeln!(trace, "{}", line);
+            }
+        }
+    }
+}
+
+impl Machine {
+    /// Simulates L1 instruction and data caches in front of memory: every byte `fetch`
+    /// reads goes through the instruction cache and every word the memory stage reads or
+    /// writes through the data cache, including those extensions make with
+    /// [`Machine::read_data`] and [`Machine::write_data`]. Either can be left out.
+    pub fn set_caches(&mut self, icache: Option<CacheConfig>, dcache: Option<CacheConfig>) {
+        self.caches.icache = icache.map(Cache::new);
+        self.caches.dcache = dcache.map(Cache::new);
+    }
+
+    /// Writes a line per cache access, e.g. `D W 0x01f8 set 3 miss evict 0x00f8 writeback`.
+    pub fn set_cache_trace(&mut self, trace: Box<dyn Write>) {
+        self.caches.trace = Some(trace);
+    }
+
+    pub fn icache(&self) -> Option<&Cache> {
+        self.caches.icache.as_ref()
+    }
+
+    pub fn dcache(&self) -> Option<&Cache> {
+        self.caches.dcache.as_ref()
+    }
+
+    pub(crate) fn cache_fetch(&mut self, range: Range<usize>) {
+        self.caches.access(false, range, false);
+    }
+
+    // the word the memory stage is about to read or write; extensions go through
+    // read_data and write_data instead
+    pub(crate) fn cache_data(&mut self, state: &CycleState) {
+        let (addr, write) = match state.op {
+            OpCode::Rmmov | OpCode::Call | OpCode::Push => (state.val_e as usize, true),
+            OpCode::Mrmov => (state.val_e as usize, false),
+            OpCode::Ret | OpCode::Pop => (state.val_a as usize, false),
+            _ => return,
+        };
+        self.cache_word(addr, write);
+    }
+
+    fn cache_word(&mut self, addr: usize, write: bool) {
+        if let Some(end) = addr.checked_add(8) {
+            self.caches.access(true, addr..end, write);
+        }
+    }
+
+    /// Reads a word through the data cache, for the memory stage of an extension.
+    /// `get_mem_word` reads memory without the cache seeing it.
+    pub fn read_data(&mut self, addr: usize) -> Result<isize, anyhow::Error> {
+        self.cache_word(addr, false);
+        self.get_mem_word(addr)
+    }
+
+    /// Writes a word through the data cache, for the memory stage of an extension.
+    pub fn write_data(&mut self, addr: usize, word: isize) -> Result<(), anyhow::Error> {
+        self.cache_word(addr, true);
+        self.set_mem_word(addr, word)
+    }
+
+    /// Statistics for the caches in use.
+    pub fn cache_report(&self) -> String {
+        let mut out = String::new();
+        if let Some(cache) = &self.caches.icache {
+            out.push_str(&cache.report("L1i"));
+        }
+        if let Some(cache) = &self.caches.dcache {
+            out.push_str(&cache.report("L1d"));
+        }
+        out
+    }
+}
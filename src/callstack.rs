@@ -0,0 +1,215 @@
+use crate::{CycleState, Machine, OpCode, REG_NAMES, RSP};
+
+// %rbx, %rbp and %r12-%r14, which a routine has to give back as it found them
+const CALLEE_SAVED: [usize; 5] = [3, 5, 12, 13, 14];
+
+/// A `call` that has not returned yet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// Address of the `call` instruction.
+    pub site: usize,
+    pub callee: usize,
+    /// The return address the `call` pushed.
+    pub ret: usize,
+    // the callee-saved registers and %rsp from before the call
+    saved: [isize; 6],
+}
+
+/// Whether returns are checked against the calling convention: the callee-saved registers
+/// must hold what they held at the `call`, and `%rsp` must be back where it was.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CallCheck {
+    Off,
+    Warn,
+    /// Report violations as errors, stopping the run.
+    Stop,
+}
+
+/// How far [`Machine::run_until`] goes after its first cycle, unless the machine stops or
+/// reaches a breakpoint first.
+pub(crate) enum Until {
+    Step,
+    // until the shadow stack is no deeper than this
+    Return(usize),
+    Breakpoint,
+}
+
+impl Machine {
+    // keeps the shadow stack in step with call and ret, warning about returns that do not
+    // go back to where the matching call came from
+    pub(crate) fn track_call(
+        &mut self,
+        pc: usize,
+        state: &CycleState,
+    ) -> Result<(), anyhow::Error> {
+        match state.op {
+            OpCode::Call => self.frames.push(Frame {
+                site: pc,
+                callee: state.val_c as usize,
+                ret: state.val_p,
+                saved: {
+                    // %rsp as it should be once the ret pops the return address again
+                    let mut saved = self.callee_saved();
+                    saved[5] = saved[5].wrapping_add(8);
+                    saved
+                },
+            }),
+            OpCode::Ret => {
+                let target = state.val_m as usize;
+                let matched = self.frames.iter().rposition(|f| f.ret == target);
+                match matched {
+                    Some(i) if i + 1 == self.frames.len() => {
+                        if let Some(violation) = self.check_return(&self.frames[i]) {
+                            match self.call_check {
+                                CallCheck::Stop => {
+                                    self.frames.truncate(i);
+                                    anyhow::bail!("{}", violation);
+                                }
+                                _ => self.warnings.push(violation),
+                            }
+                        }
+                    }
+                    Some(i) => self.warnings.push(format!(
+                        "ret at {} returns to {}, skipping {} frame(s) of the shadow stack",
+                        self.describe(pc),
+                        self.describe(target),
+                        self.frames.len() - 1 - i
+                    )),
+                    None => {
+                        let expected = match self.frames.last() {
+                            Some(frame) => format!("expected {}", self.describe(frame.ret)),
+                            None => "no call is active".to_string(),
+                        };
+                        self.warnings.push(format!(
+                            "ret at {} returns to {}, but {} (corrupted return address?)",
+                            self.describe(pc),
+                            self.describe(target),
+                            expected
+                        ));
+                    }
+                }
+                let depth = matched.unwrap_or(self.frames.len().saturating_sub(1));
+                self.frames.truncate(depth);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn callee_saved(&self) -> [isize; 6] {
+        let mut saved = [0; 6];
+        for (slot, &reg) in saved.iter_mut().zip(&CALLEE_SAVED) {
+            *slot = self.regs[reg];
+        }
+        saved[5] = self.regs[RSP];
+        saved
+    }
+
+    // what `frame`'s callee changed that it should not have, once its ret has run
+    fn check_return(&self, frame: &Frame) -> Option<String> {
+        if self.call_check == CallCheck::Off {
+            return None;
+        }
+        let now = self.callee_saved();
+        let regs = CALLEE_SAVED.iter().chain(&[RSP]);
+        let changed: Vec<String> = regs
+            .zip(frame.saved.iter().zip(&now))
+            .filter(|(_, (old, new))| old != new)
+            .map(|(&reg, (old, new))| format!("{} 0x{:x} -> 0x{:x}", REG_NAMES[reg], old, new))
+            .collect();
+        match changed.is_empty() {
+            true => None,
+            false => Some(format!(
+                "{} (called from {}) did not preserve {}",
+                self.describe(frame.callee),
+                self.describe(frame.site),
+                changed.join(", ")
+            )),
+        }
+    }
+
+    pub fn set_call_check(&mut self, call_check: CallCheck) {
+        self.call_check = call_check;
+    }
+
+    /// The shadow call stack built from executed `call` and `ret` instructions, innermost
+    /// call last.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Warnings about mismatched returns since the last call.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    // the plain run loops have nowhere else to put them
+    pub(crate) fn report_warnings(&mut self) {
+        for warning in self.take_warnings() {
+            eprintln!("warning: {}", warning);
+        }
+    }
+
+    /// The current PC followed by the return address of every active call, innermost first.
+    pub fn backtrace(&self) -> String {
+        let mut out = format!("#0  {}\n", self.describe(self.pc));
+        for (i, frame) in self.frames.iter().rev().enumerate() {
+            out.push_str(&format!(
+                "#{:<2} {}  (call at 0x{:04x})\n",
+                i + 1,
+                self.describe(frame.ret),
+                frame.site
+            ));
+        }
+        out
+    }
+
+    fn at_call(&self) -> bool {
+        self.mem.get(self.pc).is_some_and(|byte| byte >> 4 == 8)
+    }
+
+    // over a call, or a single step anywhere else
+    pub(crate) fn until_over(&self) -> Until {
+        match self.at_call() {
+            true => Until::Return(self.frames.len()),
+            false => Until::Step,
+        }
+    }
+
+    // out of the current routine, or to the end outside of any
+    pub(crate) fn until_finish(&self) -> Until {
+        match self.frames.len() {
+            0 => Until::Breakpoint,
+            depth => Until::Return(depth - 1),
+        }
+    }
+
+    /// Runs at least one cycle, then until the machine stops, the PC reaches a breakpoint or
+    /// `until` holds. Returns the state of the last cycle, if the machine was running at all.
+    pub(crate) fn run_until(&mut self, until: Until) -> Result<Option<CycleState>, anyhow::Error> {
+        if !self.is_running() {
+            return Ok(None);
+        }
+        loop {
+            let state = self.step()?;
+            if !self.is_running() || self.breakpoints.contains(&self.pc) {
+                return Ok(Some(state));
+            }
+            match until {
+                Until::Step => return Ok(Some(state)),
+                Until::Return(depth) if self.frames.len() <= depth => return Ok(Some(state)),
+                _ => (),
+            }
+        }
+    }
+
+    /// Steps one instruction, running a `call` through to its return.
+    pub fn step_over(&mut self) -> Result<(), anyhow::Error> {
+        self.run_until(self.until_over()).map(|_| ())
+    }
+
+    /// Runs until the current routine returns.
+    pub fn finish(&mut self) -> Result<(), anyhow::Error> {
+        self.run_until(self.until_finish()).map(|_| ())
+    }
+}
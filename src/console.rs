@@ -0,0 +1,52 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+};
+
+use crate::{CycleState, InstructionExtension, Machine};
+
+/// Console output as a trap instruction: `putc rA` (icode C, ifun 0, two bytes) writes the
+/// low byte of rA. This is what the bundled runtime's `putc` and `print_int` use.
+pub struct Console {
+    out: RefCell<Box<dyn Write>>,
+}
+
+impl Console {
+    pub const ICODE: u8 = 0xc;
+
+    pub fn new(out: Box<dyn Write>) -> Console {
+        Console {
+            out: RefCell::new(out),
+        }
+    }
+
+    pub fn stdout() -> Console {
+        Console::new(Box::new(io::stdout()))
+    }
+}
+
+impl InstructionExtension for Console {
+    fn claims(&self, icode: u8, ifun: u8) -> bool {
+        icode == Console::ICODE && ifun == 0
+    }
+
+    fn length(&self, _icode: u8, _ifun: u8) -> usize {
+        2
+    }
+
+    fn mnemonic(&self, _icode: u8, _ifun: u8) -> String {
+        "putc".to_string()
+    }
+
+    fn decode(&self, machine: &Machine, state: &mut CycleState) -> Result<(), anyhow::Error> {
+        state.val_a = machine.get_reg(state.r_a)?;
+        Ok(())
+    }
+
+    fn memory(&self, _machine: &mut Machine, state: &mut CycleState) -> Result<(), anyhow::Error> {
+        let mut out = self.out.borrow_mut();
+        out.write_all(&[state.val_a as u8])?;
+        out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+
+use crate::{asm::split_labels, asm::strip_comment, CycleState, Machine, OpCode};
+
+/// Which instructions ran, and which way every conditional jump and move went.
+#[derive(Default)]
+pub struct Coverage {
+    executed: BTreeMap<usize, u64>,
+    // addr -> (condition held, condition failed)
+    branches: BTreeMap<usize, (u64, u64)>,
+}
+
+struct LineCoverage {
+    line: usize,
+    hits: u64,
+    // (addr, held, failed) for each conditional instruction on the line
+    branches: Vec<(usize, u64, u64)>,
+}
+
+fn percent(hit: usize, found: usize) -> f64 {
+    match found {
+        0 => 100.0,
+        _ => hit as f64 * 100.0 / found as f64,
+    }
+}
+
+// data directives emit bytes but are never run
+fn is_code(text: &str) -> bool {
+    let (_, rest) = split_labels(strip_comment(text));
+    let word = rest.split_whitespace().next().unwrap_or_default();
+    !word.is_empty() && (!word.starts_with('.') || word == ".include")
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    /// Marks the instruction at `pc` as run, for use with [`Machine::run_with`].
+    pub fn record(&mut self, pc: usize, state: &CycleState) {
+        *self.executed.entry(pc).or_default() += 1;
+        if matches!(state.op, OpCode::Jxx | OpCode::Cmov) && state.ifun != 0 {
+            let outcome = self.branches.entry(pc).or_default();
+            match state.cnd {
+                true => outcome.0 += 1,
+                false => outcome.1 += 1,
+            }
+        }
+    }
+
+    pub fn is_executed(&self, addr: usize) -> bool {
+        self.executed.contains_key(&addr)
+    }
+
+    // every listing line that holds code, with its counts
+    fn lines(&self, machine: &Machine) -> Vec<LineCoverage> {
+        let mut lines: BTreeMap<usize, LineCoverage> = BTreeMap::new();
+        for (addr, line) in machine.source.addrs() {
+            if !machine.source.text(line).is_some_and(is_code) {
+                continue;
+            }
+            let entry = lines.entry(line).or_insert(LineCoverage {
+                line,
+                hits: 0,
+                branches: Vec::new(),
+            });
+            entry.hits = entry
+                .hits
+                .max(self.executed.get(&addr).copied().unwrap_or(0));
+
+            let mut state = CycleState::new();
+            if machine.fetch(addr, &mut state).is_ok()
+                && matches!(state.op, OpCode::Jxx | OpCode::Cmov)
+                && state.ifun != 0
+            {
+                let (held, failed) = self.branches.get(&addr).copied().unwrap_or_default();
+                entry.branches.push((addr, held, failed));
+            }
+        }
+        lines.into_values().collect()
+    }
+
+    /// A summary of line and branch coverage, listing the lines that never ran and the
+    /// conditions that only ever went one way.
+    pub fn report(&self, machine: &Machine) -> String {
+        let lines = self.lines(machine);
+        let lines_hit = lines.iter().filter(|l| l.hits > 0).count();
+        let branches: Vec<_> = lines
+            .iter()
+            .flat_map(|l| l.branches.iter().map(move |b| (l.line, b)))
+            .collect();
+        let outcomes_hit: usize = branches
+            .iter()
+            .map(|(_, (_, held, failed))| (*held > 0) as usize + (*failed > 0) as usize)
+            .sum();
+
+        let mut out = format!(
+            "Coverage: {}/{} lines ({:.2}%), {}/{} branch outcomes ({:.2}%)\n",
+            lines_hit,
+            lines.len(),
+            percent(lines_hit, lines.len()),
+            outcomes_hit,
+            branches.len() * 2,
+            percent(outcomes_hit, branches.len() * 2)
+        );
+        for line in lines.iter().filter(|l| l.hits == 0) {
+            let text = machine.source.text(line.line).unwrap_or_default();
+            out.push_str(&format!("not run: {:4} |{}\n", line.line, text));
+        }
+        for (line, &(addr, held, failed)) in branches {
+            if held > 0 && failed > 0 {
+                continue;
+            }
+            let instr = match machine.disassemble(addr) {
+                Ok((instr, _)) => instr,
+                Err(_) => "??".to_string(),
+            };
+            let missing = match (held, failed) {
+                (0, 0) => "never ran",
+                (0, _) => "condition never held",
+                _ => "condition never failed",
+            };
+            out.push_str(&format!(
+                "partial: {:4} | {}: {} ({})\n",
+                line,
+                machine.describe(addr),
+                instr,
+                missing
+            ));
+        }
+        out
+    }
+
+    /// An lcov tracefile for the listing loaded from `path`. Each conditional instruction
+    /// is a block with two branches, condition held and condition failed. There is a single
+    /// `SF:` record: code from an `.include` or a macro is counted on the line of `path`
+    /// that pulled it in, not in a record of its own.
+    pub fn to_lcov(&self, machine: &Machine, path: &str) -> String {
+        let lines = self.lines(machine);
+        let mut out = format!("TN:\nSF:{}\n", path);
+
+        let mut found = 0;
+        let mut hit = 0;
+        for line in &lines {
+            for (block, &(_, held, failed)) in line.branches.iter().enumerate() {
+                for (branch, count) in [held, failed].into_iter().enumerate() {
+                    let taken = match line.hits {
+                        0 => "-".to_string(),
+                        _ => count.to_string(),
+                    };
+                    out.push_str(&format!(
+                        "BRDA:{},{},{},{}\n",
+                        line.line, block, branch, taken
+                    ));
+                    found += 1;
+                    hit += (count > 0) as usize;
+                }
+            }
+        }
+        out.push_str(&format!("BRF:{}\nBRH:{}\n", found, hit));
+
+        for line in &lines {
+            out.push_str(&format!("DA:{},{}\n", line.line, line.hits));
+        }
+        out.push_str(&format!(
+            "LF:{}\nLH:{}\nend_of_record\n",
+            lines.len(),
+            lines.iter().filter(|l| l.hits > 0).count()
+        ));
+        out
+    }
+}
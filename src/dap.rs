@@ -0,0 +1,480 @@
+use std::{
+    cell::RefCell,
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use crate::{
+    callstack::Until,
+    json::{parse_body, read_body, write_message, Json},
+    Assembler, Console, ImageFormat, LoadMode, Machine, StepMode, REG_NAMES, RSP,
+};
+
+const REGISTERS_REF: usize = 1;
+const FLAGS_REF: usize = 2;
+const STACK_REF: usize = 3;
+const MEMORY_REF: usize = 4;
+const STACK_WORDS: usize = 16;
+
+// console output, collected between stops and sent as `output` events
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A Debug Adapter Protocol server that launches one `.yo` or `.ys` program. Breakpoints
+/// are set by line of the program file, steps are one instruction, and the stack trace
+/// comes from following `call` and `ret`.
+pub struct DapServer {
+    mem_size: usize,
+    machine: Option<Machine>,
+    program: String,
+    output: Output,
+    stop_on_entry: bool,
+    seq: i64,
+}
+
+impl DapServer {
+    pub fn new(mem_size: usize) -> DapServer {
+        DapServer {
+            mem_size,
+            machine: None,
+            program: String::new(),
+            output: Output::default(),
+            stop_on_entry: false,
+            seq: 0,
+        }
+    }
+
+    fn send(&mut self, out: &mut dyn Write, mut msg: Vec<(&str, Json)>) -> io::Result<()> {
+        self.seq += 1;
+        msg.insert(0, ("seq", Json::from(self.seq)));
+        write_message(out, &Json::obj(msg))
+    }
+
+    fn event(&mut self, out: &mut dyn Write, event: &str, body: Json) -> io::Result<()> {
+        let msg = vec![
+            ("type", Json::from("event")),
+            ("event", Json::from(event)),
+            ("body", body),
+        ];
+        self.send(out, msg)
+    }
+
+    fn respond(
+        &mut self,
+        out: &mut dyn Write,
+        request: &Json,
+        result: Result<Json, String>,
+    ) -> io::Result<()> {
+        let mut msg = vec![
+            ("type", Json::from("response")),
+            ("request_seq", request.get("seq").clone()),
+            ("command", request.get("command").clone()),
+            ("success", Json::from(result.is_ok())),
+        ];
+        match result {
+            Ok(body) => msg.push(("body", body)),
+            Err(message) => msg.push(("message", Json::from(message))),
+        }
+        self.send(out, msg)
+    }
+
+    fn machine(&mut self) -> Result<&mut Machine, String> {
+        self.machine
+            .as_mut()
+            .ok_or_else(|| "no program has been launched".to_string())
+    }
+
+    fn launch(&mut self, args: &Json) -> Result<Json, String> {
+        let program = match args.get("program").as_str() {
+            Some(program) => program.to_string(),
+            None => return Err("launch needs a `program`".to_string()),
+        };
+        let console = Console::new(Box::new(self.output.clone()));
+        let mut machine =
+            Machine::with_extensions(self.mem_size, StepMode::NoStep, vec![Box::new(console)]);
+        let loaded = match ImageFormat::from_path(&program) {
+            ImageFormat::Source => Assembler::new()
+                .assemble_file(&program)
+                .map_err(|errors| {
+                    let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                    errors.join("\n")
+                })
+                .and_then(|p| machine.load_program(&p).map_err(|e| e.to_string())),
+            ImageFormat::Yo => fs::read_to_string(&program)
+                .map_err(|e| e.to_string())
+                .and_then(|text| {
+                    machine
+                        .load_with(&text, LoadMode::Fail)
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                }),
+            _ => Err("only .yo and .ys programs can be debugged".to_string()),
+        };
+        loaded.map_err(|e| format!("{}: {}", program, e))?;
+
+        self.stop_on_entry = args.get("stopOnEntry").as_bool().unwrap_or(false);
+        self.program = program;
+        self.machine = Some(machine);
+        Ok(Json::Null)
+    }
+
+    fn source(&self) -> Json {
+        let name = Path::new(&self.program)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Json::obj(vec![
+            ("name", Json::from(name)),
+            ("path", Json::from(self.program.as_str())),
+        ])
+    }
+
+    fn set_breakpoints(&mut self, args: &Json) -> Result<Json, String> {
+        let machine = self.machine()?;
+        let old: Vec<usize> = machine.breakpoints().collect();
+        for addr in old {
+            machine.remove_breakpoint(addr);
+        }
+
+        let mut lines: Vec<(usize, usize)> = machine.source.addrs().map(|(a, l)| (l, a)).collect();
+        lines.sort();
+        let mut verified = Vec::new();
+        for bp in args.get("breakpoints").as_array() {
+            let line = bp.get("line").as_i64().unwrap_or_default() as usize;
+            // the first instruction on or after the requested line
+            match lines.iter().find(|(l, _)| *l >= line) {
+                Some(&(at, addr)) => {
+                    machine.add_breakpoint(addr);
+                    verified.push(Json::obj(vec![
+                        ("verified", Json::from(true)),
+                        ("line", Json::from(at)),
+                    ]));
+                }
+                None => verified.push(Json::obj(vec![
+                    ("verified", Json::from(false)),
+                    ("line", Json::from(line)),
+                    ("message", Json::from("no code on or after this line")),
+                ])),
+            }
+        }
+        Ok(Json::obj(vec![("breakpoints", Json::from(verified))]))
+    }
+
+    fn frame(&self, id: usize, addr: usize) -> Json {
+        let machine = self.machine.as_ref().unwrap();
+        let name = match machine.symbols.nearest(addr) {
+            Some((name, _)) => name.to_string(),
+            None => format!("0x{:04x}", addr),
+        };
+        let line = machine.source.line_at(addr).map(|(l, _)| l).unwrap_or(0);
+        Json::obj(vec![
+            ("id", Json::from(id)),
+            ("name", Json::from(name)),
+            ("source", self.source()),
+            ("line", Json::from(line)),
+            ("column", Json::from(1usize)),
+            (
+                "instructionPointerReference",
+                Json::from(format!("0x{:x}", addr)),
+            ),
+        ])
+    }
+
+    fn stack_trace(&mut self) -> Result<Json, String> {
+        let machine = self.machine()?;
+        let mut addrs = vec![machine.pc];
+        addrs.extend(machine.frames().iter().rev().map(|f| f.site));
+        let frames: Vec<Json> = addrs
+            .into_iter()
+            .enumerate()
+            .map(|(i, addr)| self.frame(i, addr))
+            .collect();
+        let total = frames.len();
+        Ok(Json::obj(vec![
+            ("stackFrames", Json::from(frames)),
+            ("totalFrames", Json::from(total)),
+        ]))
+    }
+
+    fn scopes(&self) -> Json {
+        let scope = |name: &str, reference: usize| {
+            Json::obj(vec![
+                ("name", Json::from(name)),
+                ("variablesReference", Json::from(reference)),
+                ("expensive", Json::from(false)),
+            ])
+        };
+        Json::obj(vec![(
+            "scopes",
+            Json::from(vec![
+                scope("Registers", REGISTERS_REF),
+                scope("Flags", FLAGS_REF),
+                scope("Stack", STACK_REF),
+                scope("Memory", MEMORY_REF),
+            ]),
+        )])
+    }
+
+    fn variables(&mut self, args: &Json) -> Result<Json, String> {
+        let machine = self.machine()?;
+        let var = |name: String, value: String| {
+            Json::obj(vec![
+                ("name", Json::from(name)),
+                ("value", Json::from(value)),
+                ("variablesReference", Json::from(0usize)),
+            ])
+        };
+        let quad = |val: isize| format!("0x{:016x} ({})", val, val);
+
+        let mut vars = Vec::new();
+        match args.get("variablesReference").as_i64().unwrap_or(0) as usize {
+            REGISTERS_REF => {
+                for (i, val) in machine.regs.iter().enumerate() {
+                    vars.push(var(REG_NAMES[i].to_string(), quad(*val)));
+                }
+                vars.push(var("PC".to_string(), machine.describe(machine.pc)));
+            }
+            FLAGS_REF => {
+                let flags = machine.flags();
+                vars.push(var("SF".to_string(), (flags.0 as u8).to_string()));
+                vars.push(var("ZF".to_string(), (flags.1 as u8).to_string()));
+                vars.push(var("OF".to_string(), (flags.2 as u8).to_string()));
+                vars.push(var("STAT".to_string(), machine.status.to_string()));
+                vars.push(var("cycle".to_string(), machine.cycle.to_string()));
+            }
+            STACK_REF => {
+                let rsp = machine.regs[RSP] as usize;
+                for i in 0..STACK_WORDS {
+                    let addr = match rsp.checked_add(i * 8) {
+                        Some(addr) => addr,
+                        None => break,
+                    };
+                    match machine.get_mem_word(addr) {
+                        Ok(word) if addr < machine.mem.len().saturating_sub(7) => vars.push(var(
+                            format!("%rsp+0x{:x} (0x{:04x})", i * 8, addr),
+                            quad(word),
+                        )),
+                        _ => break,
+                    }
+                }
+            }
+            MEMORY_REF => {
+                for (addr, name) in machine.symbols.iter() {
+                    if let Ok(word) = machine.get_mem_word(addr) {
+                        vars.push(var(format!("{} (0x{:04x})", name, addr), quad(word)));
+                    }
+                }
+            }
+            _ => return Err("unknown variables reference".to_string()),
+        }
+        Ok(Json::obj(vec![("variables", Json::from(vars))]))
+    }
+
+    fn run(&mut self, until: Until, out: &mut dyn Write) -> io::Result<()> {
+        let machine = self.machine.as_mut().unwrap();
+        let result = machine.run_until(until).map(|_| ());
+        let reason = match machine.is_running() && machine.breakpoints.contains(&machine.pc) {
+            true => "breakpoint",
+            false => "step",
+        };
+        self.stopped(out, reason, result)
+    }
+
+    fn stopped(
+        &mut self,
+        out: &mut dyn Write,
+        reason: &str,
+        result: Result<(), anyhow::Error>,
+    ) -> io::Result<()> {
+        let text = String::from_utf8_lossy(&self.output.0.take()).into_owned();
+        if !text.is_empty() {
+            let body = Json::obj(vec![
+                ("category", Json::from("stdout")),
+                ("output", Json::from(text)),
+            ]);
+            self.event(out, "output", body)?;
+        }
+
+        let warnings = match self.machine.as_mut() {
+            Some(machine) => machine.take_warnings(),
+            None => Vec::new(),
+        };
+        for warning in warnings {
+            let body = Json::obj(vec![
+                ("category", Json::from("console")),
+                ("output", Json::from(format!("warning: {}\n", warning))),
+            ]);
+            self.event(out, "output", body)?;
+        }
+
+        let running = self.machine.as_ref().is_some_and(|m| m.is_running());
+        match result {
+            Err(e) => {
+                let body = Json::obj(vec![
+                    ("reason", Json::from("exception")),
+                    ("description", Json::from("fault")),
+                    ("text", Json::from(e.to_string())),
+                    ("threadId", Json::from(1usize)),
+                    ("allThreadsStopped", Json::from(true)),
+                ]);
+                self.event(out, "stopped", body)
+            }
+            Ok(()) if !running => {
+                self.event(
+                    out,
+                    "exited",
+                    Json::obj(vec![("exitCode", Json::from(0usize))]),
+                )?;
+                self.event(out, "terminated", Json::obj(vec![]))
+            }
+            Ok(()) => {
+                let body = Json::obj(vec![
+                    ("reason", Json::from(reason)),
+                    ("threadId", Json::from(1usize)),
+                    ("allThreadsStopped", Json::from(true)),
+                ]);
+                self.event(out, "stopped", body)
+            }
+        }
+    }
+
+    /// Serves requests until the client disconnects.
+    pub fn serve(
+        &mut self,
+        input: &mut dyn BufRead,
+        out: &mut dyn Write,
+    ) -> Result<(), anyhow::Error> {
+        while let Some(body) = read_body(input)? {
+            let request = match parse_body(body) {
+                Ok(request) => request,
+                Err(e) => {
+                    eprintln!("y86-dap: ignoring malformed message: {}", e);
+                    continue;
+                }
+            };
+            let args = request.get("arguments").clone();
+            let command = request
+                .get("command")
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            match command.as_str() {
+                "initialize" => {
+                    let caps = Json::obj(vec![
+                        ("supportsConfigurationDoneRequest", Json::from(true)),
+                        ("supportsTerminateRequest", Json::from(true)),
+                    ]);
+                    self.respond(out, &request, Ok(caps))?;
+                }
+                "launch" => {
+                    let result = self.launch(&args);
+                    let ok = result.is_ok();
+                    self.respond(out, &request, result)?;
+                    if ok {
+                        self.event(out, "initialized", Json::Null)?;
+                    }
+                }
+                "setBreakpoints" => {
+                    let result = self.set_breakpoints(&args);
+                    self.respond(out, &request, result)?;
+                }
+                "setExceptionBreakpoints" => {
+                    let body = Json::obj(vec![("breakpoints", Json::from(vec![]))]);
+                    self.respond(out, &request, Ok(body))?;
+                }
+                "configurationDone" => {
+                    self.respond(out, &request, Ok(Json::Null))?;
+                    if self.machine.is_none() {
+                        continue;
+                    }
+                    if self.stop_on_entry {
+                        self.stopped(out, "entry", Ok(()))?;
+                    } else {
+                        self.run(Until::Breakpoint, out)?;
+                    }
+                }
+                "threads" => {
+                    let thread = Json::obj(vec![
+                        ("id", Json::from(1usize)),
+                        ("name", Json::from("y86")),
+                    ]);
+                    let body = Json::obj(vec![("threads", Json::from(vec![thread]))]);
+                    self.respond(out, &request, Ok(body))?;
+                }
+                "stackTrace" => {
+                    let result = self.stack_trace();
+                    self.respond(out, &request, result)?;
+                }
+                "scopes" => {
+                    let body = self.scopes();
+                    self.respond(out, &request, Ok(body))?;
+                }
+                "variables" => {
+                    let result = self.variables(&args);
+                    self.respond(out, &request, result)?;
+                }
+                "continue" | "next" | "stepIn" | "stepOut" => {
+                    let running = match self.machine() {
+                        Ok(machine) => Ok(machine.is_running()),
+                        Err(e) => Err(e),
+                    };
+                    match running {
+                        Ok(true) => (),
+                        Ok(false) => {
+                            let msg = "the program is not running".to_string();
+                            self.respond(out, &request, Err(msg))?;
+                            continue;
+                        }
+                        Err(e) => {
+                            self.respond(out, &request, Err(e))?;
+                            continue;
+                        }
+                    }
+                    let machine = self.machine.as_ref().unwrap();
+                    let until = match command.as_str() {
+                        "continue" => Until::Breakpoint,
+                        "next" => machine.until_over(),
+                        "stepOut" => machine.until_finish(),
+                        _ => Until::Step,
+                    };
+                    let body = match command.as_str() {
+                        "continue" => Json::obj(vec![("allThreadsContinued", Json::from(true))]),
+                        _ => Json::Null,
+                    };
+                    self.respond(out, &request, Ok(body))?;
+                    self.run(until, out)?;
+                }
+                // requests are handled one at a time, so nothing could pause a running program
+                "pause" => {
+                    let msg = "pausing is not supported; set a breakpoint instead".to_string();
+                    self.respond(out, &request, Err(msg))?;
+                }
+                "disconnect" | "terminate" => {
+                    self.respond(out, &request, Ok(Json::Null))?;
+                    if command == "disconnect" {
+                        return Ok(());
+                    }
+                    self.event(out, "terminated", Json::obj(vec![]))?;
+                }
+                _ => {
+                    let msg = format!("unsupported request `{}`", command);
+                    self.respond(out, &request, Err(msg))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
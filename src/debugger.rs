@@ -0,0 +1,185 @@
+use std::io::{self, BufRead, Write};
+
+use crate::{memview::address, Machine, MemView, Status};
+
+const HELP: &str = "\
+s [n]         step n cycles (default 1)
+n             step over a call
+f             finish the current routine
+c             continue until a breakpoint or halt
+bt            backtrace of the active calls
+b [addr]      set a breakpoint, or list them
+d <addr>      delete a breakpoint
+x <view>      show memory, e.g. `x src:src+24,qd` or `x stack-32,qxa`
+i             print the machine state
+q             stop the program
+h             this help
+addresses are expressions and may use labels; an empty line repeats the last command";
+
+impl Machine {
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn cycle(&self) -> usize {
+        self.cycle
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.status == Status::Aok
+    }
+
+    /// Returns false if there already was a breakpoint at `addr`.
+    pub fn add_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Runs at least one cycle, then until the machine stops or the PC reaches a breakpoint.
+    pub fn resume(&mut self) -> Result<(), anyhow::Error> {
+        while self.is_running() {
+            self.step()?;
+            if self.breakpoints.contains(&self.pc) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn describe(&self, addr: usize) -> String {
+        match self.symbols.nearest(addr) {
+            Some(_) => format!("0x{:04x} <{}>", addr, self.symbols.symbolize(addr)),
+            None => format!("0x{:04x}", addr),
+        }
+    }
+
+    fn where_am_i(&self) {
+        let instr = match self.disassemble(self.pc) {
+            Ok((instr, _)) => instr,
+            Err(_) => "??".to_string(),
+        };
+        println!("{}: {}", self.describe(self.pc), instr);
+        if let Some(context) = self.source.context(self.pc, 2) {
+            println!("{}", context);
+        }
+    }
+
+    fn stopped(&mut self, result: Result<(), anyhow::Error>) {
+        for warning in self.take_warnings() {
+            println!("warning: {}", warning);
+        }
+        match result {
+            Err(e) => println!("error: {}", e),
+            Ok(()) if !self.is_running() => println!("{}", self.status),
+            Ok(()) => {
+                if self.breakpoints.contains(&self.pc) {
+                    println!("breakpoint at 0x{:04x}", self.pc);
+                }
+                self.where_am_i();
+            }
+        }
+    }
+
+    // the `-d` command loop, reading commands from stdin until the program halts or `q`
+    pub(crate) fn debug(&mut self) -> Result<(), anyhow::Error> {
+        self.where_am_i();
+        let stdin = io::stdin();
+        let mut last = String::new();
+        loop {
+            print!("(y86) ");
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let line = match line.trim() {
+                "" => last.clone(),
+                line => line.to_string(),
+            };
+            last = line.clone();
+
+            let (cmd, arg) = match line.split_once(char::is_whitespace) {
+                Some((cmd, arg)) => (cmd, arg.trim()),
+                None => (line.as_str(), ""),
+            };
+            match cmd {
+                "s" | "step" => {
+                    let count = match arg {
+                        "" => Ok(1),
+                        n => n.parse::<usize>().map_err(|e| e.to_string()),
+                    };
+                    let count = match count {
+                        Ok(count) => count,
+                        Err(e) => {
+                            println!("bad count: {}", e);
+                            continue;
+                        }
+                    };
+                    let mut result = Ok(());
+                    for _ in 0..count {
+                        if !self.is_running() {
+                            break;
+                        }
+                        if let Err(e) = self.step() {
+                            result = Err(e);
+                            break;
+                        }
+                    }
+                    self.stopped(result);
+                }
+                "n" | "next" => {
+                    let result = self.step_over();
+                    self.stopped(result);
+                }
+                "f" | "finish" => {
+                    let result = self.finish();
+                    self.stopped(result);
+                }
+                "c" | "continue" => {
+                    let result = self.resume();
+                    self.stopped(result);
+                }
+                "bt" | "backtrace" => print!("{}", self.backtrace()),
+                "b" | "break" if arg.is_empty() => {
+                    for addr in self.breakpoints() {
+                        println!("{}", self.describe(addr));
+                    }
+                }
+                "b" | "break" | "d" | "delete" => match address(arg, &self.symbols) {
+                    Ok(addr) if cmd.starts_with('b') => {
+                        self.add_breakpoint(addr);
+                        println!("breakpoint at {}", self.describe(addr));
+                    }
+                    Ok(addr) => {
+                        if !self.remove_breakpoint(addr) {
+                            println!("no breakpoint at 0x{:04x}", addr);
+                        }
+                    }
+                    Err(e) => println!("bad address: {}", e),
+                },
+                "x" => match MemView::parse(arg, &self.symbols) {
+                    Ok(view) => match self.view_mem(&view) {
+                        Ok(text) => print!("{}", text),
+                        Err(e) => println!("{}", e),
+                    },
+                    Err(e) => println!("bad view: {}", e),
+                },
+                "i" | "info" => print!("{}", self),
+                "q" | "quit" => return Ok(()),
+                "h" | "help" => println!("{}", HELP),
+                _ => println!("unknown command `{}`, try `h`", cmd),
+            }
+
+            if !self.is_running() {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use crate::{Flags, Machine, REG_NAMES};
+
+/// The architectural state before a cycle, to compare against after it.
+pub struct Snapshot {
+    pub(crate) pc: usize,
+    pub(crate) regs: Vec<isize>,
+    pub(crate) flags: Flags,
+    pub(crate) mem: Vec<u8>,
+}
+
+fn changed(old: String, new: String, color: bool) -> String {
+    if color {
+        format!("\x1b[2m{}\x1b[0m -> \x1b[1;33m{}\x1b[0m", old, new)
+    } else {
+        format!("{} -> {}", old, new)
+    }
+}
+
+impl Machine {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pc: self.pc,
+            regs: self.regs.clone(),
+            flags: self.flags.clone(),
+            mem: self.mem.clone(),
+        }
+    }
+
+    /// Describes the instruction run since `before` and every register, flag and memory
+    /// word it changed. Changed values are highlighted with ANSI colors when `color` is set,
+    /// otherwise each changed line is marked with `*`.
+    pub fn diff(&self, before: &Snapshot, color: bool) -> String {
+        let instr = match self.disassemble(before.pc) {
+            Ok((instr, _)) => instr,
+            Err(_) => "??".to_string(),
+        };
+        let mut out = format!(
+            "cycle {}: {}: {}\n",
+            self.cycle,
+            self.describe(before.pc),
+            instr
+        );
+        let marker = if color { "  " } else { "* " };
+
+        for (i, (old, new)) in before.regs.iter().zip(&self.regs).enumerate() {
+            if old != new {
+                let vals = changed(format!("0x{:016x}", old), format!("0x{:016x}", new), color);
+                out.push_str(&format!("{}{}: {}\n", marker, REG_NAMES[i], vals));
+            }
+        }
+
+        if before.flags != self.flags {
+            let vals = changed(before.flags.to_string(), self.flags.to_string(), color);
+            out.push_str(&format!("{}flags: {}\n", marker, vals));
+        }
+
+        for (i, (old, new)) in before.mem.chunks(8).zip(self.mem.chunks(8)).enumerate() {
+            if old != new {
+                let hex = |b: &[u8]| b.iter().map(|b| format!("{:02x}", b)).collect();
+                let addr = i * 8;
+                let vals = changed(hex(old), hex(new), color);
+                out.push_str(&format!("{}0x{:04x}: {}", marker, addr, vals));
+                match self.symbols.name_at(addr) {
+                    Some(name) => out.push_str(&format!(" <{}>\n", name)),
+                    None => out.push('\n'),
+                }
+            }
+        }
+
+        if !self.is_running() {
+            out.push_str(&format!("{}{}\n", marker, self.status));
+        }
+        out
+    }
+}
@@ -0,0 +1,59 @@
+use crate::{CycleState, FunCode, Machine, OpCode, REG_NAMES};
+
+fn reg_name(reg: usize) -> &'static str {
+    REG_NAMES.get(reg).copied().unwrap_or("%rnone")
+}
+
+fn cond_suffix(fun: FunCode) -> &'static str {
+    match fun {
+        FunCode::Lte => "le",
+        FunCode::Lt => "l",
+        FunCode::Eq => "e",
+        FunCode::Neq => "ne",
+        FunCode::Gte => "ge",
+        FunCode::Gt => "g",
+        _ => "",
+    }
+}
+
+impl Machine {
+    /// Decodes the instruction at `addr`, returning its assembly text and length.
+    pub fn disassemble(&self, addr: usize) -> Result<(String, usize), anyhow::Error> {
+        let mut state = CycleState::new();
+        self.fetch(addr, &mut state)?;
+        Ok((self.format_instr(&state), state.val_p - addr))
+    }
+
+    pub(crate) fn format_instr(&self, state: &CycleState) -> String {
+        let (r_a, r_b) = (reg_name(state.r_a), reg_name(state.r_b));
+        let mem_operand = if state.r_b < REG_NAMES.len() {
+            format!("0x{:x}({})", state.val_c, r_b)
+        } else {
+            format!("0x{:x}", state.val_c)
+        };
+
+        match state.op {
+            OpCode::Halt | OpCode::Nop | OpCode::Ret => state.op.to_string(),
+            OpCode::Cmov => match state.fun {
+                FunCode::Ucnd => format!("rrmovq {}, {}", r_a, r_b),
+                fun => format!("cmov{} {}, {}", cond_suffix(fun), r_a, r_b),
+            },
+            OpCode::Irmov => format!("irmovq $0x{:x}, {}", state.val_c, r_b),
+            OpCode::Rmmov => format!("rmmovq {}, {}", r_a, mem_operand),
+            OpCode::Mrmov => format!("mrmovq {}, {}", mem_operand, r_a),
+            OpCode::Opx => format!("{}q {}, {}", state.fun, r_a, r_b),
+            OpCode::Jxx => match state.fun {
+                FunCode::Ucnd => format!("jmp {}", self.symbols.symbolize(state.val_c as usize)),
+                fun => format!(
+                    "j{} {}",
+                    cond_suffix(fun),
+                    self.symbols.symbolize(state.val_c as usize)
+                ),
+            },
+            OpCode::Call => format!("call {}", self.symbols.symbolize(state.val_c as usize)),
+            OpCode::Push => format!("pushq {}", r_a),
+            OpCode::Pop => format!("popq {}", r_a),
+            OpCode::Ext(_) => self.op_name(state),
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::ops::Range;
+
+use crate::{linker::listing_line, Machine};
+
+/// Formats memory can be saved in after a run.
+#[derive(Clone, Copy)]
+pub enum DumpFormat {
+    Yo,
+    Binary,
+    Hexdump,
+}
+
+impl DumpFormat {
+    pub fn from_name(name: &str) -> Option<DumpFormat> {
+        match name {
+            "yo" => Some(DumpFormat::Yo),
+            "bin" | "raw" => Some(DumpFormat::Binary),
+            "hexdump" | "txt" => Some(DumpFormat::Hexdump),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format from a file extension, defaulting to a hexdump.
+    pub fn from_path(path: &str) -> DumpFormat {
+        match path.rsplit_once('.') {
+            Some((_, ext)) => DumpFormat::from_name(ext).unwrap_or(DumpFormat::Hexdump),
+            None => DumpFormat::Hexdump,
+        }
+    }
+}
+
+impl Machine {
+    pub fn mem_size(&self) -> usize {
+        self.mem.len()
+    }
+
+    fn mem_range(&self, range: Range<usize>) -> Result<&[u8], anyhow::Error> {
+        match self.mem.get(range.clone()) {
+            Some(bytes) => Ok(bytes),
+            None => anyhow::bail!(
+                "range 0x{:04x}..0x{:04x} is outside memory (size 0x{:x})",
+                range.start,
+                range.end,
+                self.mem.len()
+            ),
+        }
+    }
+
+    pub fn dump(&self, range: Range<usize>, format: DumpFormat) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(match format {
+            DumpFormat::Yo => self.dump_yo(range)?.into_bytes(),
+            DumpFormat::Binary => self.mem_range(range)?.to_vec(),
+            DumpFormat::Hexdump => self.hexdump(range)?.into_bytes(),
+        })
+    }
+
+    /// Writes memory as a `.yo` listing that `Machine::load` accepts, one line per 8 bytes.
+    /// All zero words are left out since memory starts zeroed, and labels are kept.
+    pub fn dump_yo(&self, range: Range<usize>) -> Result<String, anyhow::Error> {
+        let bytes = self.mem_range(range.clone())?;
+        let mut out = String::new();
+        for (i, chunk) in bytes.chunks(8).enumerate() {
+            let addr = range.start + i * 8;
+            // a label inside a chunk still gets its own address; the chunk starts at addr
+            for (at, name) in self
+                .symbols
+                .iter()
+                .filter(|(a, _)| (addr..addr + 8).contains(a))
+            {
+                out.push_str(&listing_line(Some(at), &[], &format!(" {}:", name)));
+            }
+            if chunk.iter().any(|&b| b != 0) {
+                out.push_str(&listing_line(Some(addr), chunk, ""));
+            }
+        }
+        Ok(out)
+    }
+
+    /// Canonical hex + ASCII dump, in the style of `hexdump -C`. Runs of identical lines
+    /// are collapsed to `*`.
+    pub fn hexdump(&self, range: Range<usize>) -> Result<String, anyhow::Error> {
+        let bytes = self.mem_range(range.clone())?;
+        let mut out = String::new();
+        let mut prev: Option<&[u8]> = None;
+        let mut skipping = false;
+        for (i, chunk) in bytes.chunks(16).enumerate() {
+            if prev == Some(chunk) {
+                if !skipping {
+                    out.push_str("*\n");
+                    skipping = true;
+                }
+                continue;
+            }
+            prev = Some(chunk);
+            skipping = false;
+
+            let mut hex = String::new();
+            for (j, b) in chunk.iter().enumerate() {
+                if j == 8 {
+                    hex.push(' ');
+                }
+                hex.push_str(&format!("{:02x} ", b));
+            }
+            let ascii: String = chunk
+                .iter()
+                .map(|&b| match b {
+                    0x20..=0x7e => b as char,
+                    _ => '.',
+                })
+                .collect();
+            out.push_str(&format!(
+                "{:08x}  {:<49} |{}|\n",
+                range.start + i * 16,
+                hex,
+                ascii
+            ));
+        }
+        out.push_str(&format!("{:08x}\n", range.end));
+        Ok(out)
+    }
+}
@@ -0,0 +1,65 @@
+use crate::{CycleState, Machine};
+
+/// Custom instructions that can be plugged into a `Machine` without touching the core
+/// fetch/decode/execute logic. An extension claims icode/ifun pairs and is consulted before
+/// the built-in instruction set, so it may also override a stock encoding.
+///
+/// Every stage hook defaults to doing nothing, so an extension only implements the stages
+/// its instruction actually uses.
+pub trait InstructionExtension {
+    fn claims(&self, icode: u8, ifun: u8) -> bool;
+
+    /// Total encoded length in bytes, including the icode:ifun byte.
+    fn length(&self, icode: u8, ifun: u8) -> usize;
+
+    fn mnemonic(&self, icode: u8, ifun: u8) -> String {
+        format!("ext{:x}:{:x}", icode, ifun)
+    }
+
+    /// `bytes` holds the whole instruction. The default follows the stock layouts: a
+    /// register byte at offset 1 when present, and a trailing 8 byte constant.
+    fn fetch(&self, state: &mut CycleState, bytes: &[u8]) -> Result<(), anyhow::Error> {
+        if bytes.len() == 2 || bytes.len() == 10 {
+            state.r_a = (bytes[1] / 16) as usize;
+            state.r_b = (bytes[1] & 0x0f) as usize;
+        }
+        if bytes.len() >= 9 {
+            let mut word = [0; 8];
+            word.copy_from_slice(&bytes[bytes.len() - 8..]);
+            state.val_c = isize::from_le_bytes(word);
+        }
+
+        Ok(())
+    }
+
+    fn decode(&self, _machine: &Machine, _state: &mut CycleState) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    fn execute(
+        &self,
+        _machine: &mut Machine,
+        _state: &mut CycleState,
+    ) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// Loads and stores made with [`Machine::read_data`] and [`Machine::write_data`] go
+    /// through the data cache like the stock instructions'.
+    fn memory(&self, _machine: &mut Machine, _state: &mut CycleState) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    fn writeback(
+        &self,
+        _machine: &mut Machine,
+        _state: &mut CycleState,
+    ) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// Next PC, or `None` to fall through to `valP`.
+    fn pc_update(&self, _state: &CycleState) -> Option<usize> {
+        None
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use crate::{Flags, Machine, Status, REG_NAMES};
+
+// gdb numbers the registers in this order: the 15 general purpose registers, then PC and
+// the condition codes, laid out like x86 eflags
+const PC_REG: usize = 15;
+const FLAGS_REG: usize = 16;
+const ZF_BIT: u32 = 6;
+const SF_BIT: u32 = 7;
+const OF_BIT: u32 = 11;
+
+fn target_xml() -> String {
+    let mut regs = String::new();
+    for (i, name) in REG_NAMES.iter().enumerate() {
+        let name = name.trim_start_matches('%');
+        let kind = match i {
+            4 | 5 => "data_ptr",
+            _ => "int64",
+        };
+        regs.push_str(&format!(
+            "<reg name=\"{}\" bitsize=\"64\" type=\"{}\" regnum=\"{}\"/>",
+            name, kind, i
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+<target version=\"1.0\"><feature name=\"org.y86.core\">\
+<flags id=\"y86_flags\" size=\"4\">\
+<field name=\"ZF\" start=\"{ZF_BIT}\" end=\"{ZF_BIT}\"/>\
+<field name=\"SF\" start=\"{SF_BIT}\" end=\"{SF_BIT}\"/>\
+<field name=\"OF\" start=\"{OF_BIT}\" end=\"{OF_BIT}\"/></flags>\
+{regs}<reg name=\"pc\" bitsize=\"64\" type=\"code_ptr\" regnum=\"{PC_REG}\"/>\
+<reg name=\"flags\" bitsize=\"32\" type=\"y86_flags\" regnum=\"{FLAGS_REG}\"/>\
+</feature></target>"
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+// "addr,len" as sent with m, M, Z and z
+fn addr_len(text: &str) -> Option<(usize, usize)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+const INTERRUPT: u8 = 0x03;
+
+// bytes from the debugger, read on their own thread so that a running `continue` can
+// notice an interrupt without blocking on the connection
+struct Incoming {
+    bytes: Receiver<u8>,
+    pending: VecDeque<u8>,
+}
+
+impl Incoming {
+    fn new(mut input: impl Read + Send + 'static) -> Incoming {
+        let (tx, bytes) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            while let Ok(n @ 1..) = input.read(&mut buf) {
+                if buf[..n].iter().any(|&b| tx.send(b).is_err()) {
+                    break;
+                }
+            }
+        });
+        Incoming {
+            bytes,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        self.pending.pop_front().or_else(|| self.bytes.recv().ok())
+    }
+
+    // whether the debugger has asked to stop, keeping anything else it sent for later
+    fn interrupted(&mut self) -> bool {
+        while let Ok(b) = self.bytes.try_recv() {
+            if b == INTERRUPT {
+                return true;
+            }
+            self.pending.push_back(b);
+        }
+        false
+    }
+}
+
+/// A GDB remote serial protocol server for one machine. Connect with
+/// `target remote localhost:<port>` (or `target remote | y86-rs --gdb stdio prog.yo`).
+pub struct GdbStub<'a> {
+    machine: &'a mut Machine,
+    ack: bool,
+}
+
+impl GdbStub<'_> {
+    pub fn new(machine: &mut Machine) -> GdbStub<'_> {
+        GdbStub { machine, ack: true }
+    }
+
+    // the next packet and whether its checksum matched
+    fn read_packet(&mut self, input: &mut Incoming) -> Option<(String, bool)> {
+        // skip acks and anything else until the start of a packet
+        while input.next()? != b'$' {}
+        let mut data = Vec::new();
+        loop {
+            match input.next()? {
+                b'#' => break,
+                b => data.push(b),
+            }
+        }
+        let checksum = [input.next()?, input.next()?];
+        let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        let valid = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            == Some(sum);
+        Some((String::from_utf8_lossy(&data).into_owned(), valid))
+    }
+
+    fn send(&self, output: &mut dyn Write, data: &str) -> Result<(), anyhow::Error> {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(output, "${}#{:02x}", data, sum)?;
+        output.flush()?;
+        Ok(())
+    }
+
+    fn flags_word(&self) -> u32 {
+        let Flags(sf, zf, of) = *self.machine.flags();
+        (zf as u32) << ZF_BIT | (sf as u32) << SF_BIT | (of as u32) << OF_BIT
+    }
+
+    fn read_reg(&self, reg: usize) -> Option<Vec<u8>> {
+        match reg {
+            0..=14 => Some(self.machine.regs[reg].to_le_bytes().to_vec()),
+            PC_REG => Some(self.machine.pc.to_le_bytes().to_vec()),
+            FLAGS_REG => Some(self.flags_word().to_le_bytes().to_vec()),
+            _ => None,
+        }
+    }
+
+    fn write_reg(&mut self, reg: usize, bytes: &[u8]) -> Option<()> {
+        match reg {
+            0..=14 => {
+                let val = isize::from_le_bytes(bytes.try_into().ok()?);
+                self.machine.set_reg(reg, val).ok()?;
+            }
+            PC_REG => self.machine.pc = usize::from_le_bytes(bytes.try_into().ok()?),
+            FLAGS_REG => {
+                let word = u32::from_le_bytes(bytes.try_into().ok()?);
+                let bit = |n: u32| word >> n & 1 == 1;
+                self.machine
+                    .set_flags(Flags(bit(SF_BIT), bit(ZF_BIT), bit(OF_BIT)));
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn stop_reply(&self, result: Result<(), anyhow::Error>) -> String {
+        match (result, &self.machine.status) {
+            (_, Status::Ins) => "S04".to_string(),
+            (Err(_), _) => "S0b".to_string(),
+            (Ok(()), Status::Halt) => "W00".to_string(),
+            (Ok(()), _) if self.machine.breakpoints.contains(&self.machine.pc) => {
+                "T05swbreak:;".to_string()
+            }
+            (Ok(()), _) => "S05".to_string(),
+        }
+    }
+
+    // runs until a breakpoint, the end of the program or an interrupt, returning whether
+    // it was interrupted
+    fn resume(&mut self, input: &mut Incoming) -> Result<bool, anyhow::Error> {
+        while self.machine.is_running() {
+            self.machine.step()?;
+            if self.machine.breakpoints.contains(&self.machine.pc) {
+                break;
+            }
+            if input.interrupted() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn handle(&mut self, packet: &str, input: &mut Incoming) -> Option<String> {
+        let (cmd, args) = packet.split_at(packet.len().min(1));
+        Some(match cmd {
+            "?" => self.stop_reply(Ok(())),
+            "g" => {
+                let regs: Vec<u8> = (0..=FLAGS_REG)
+                    .flat_map(|r| self.read_reg(r).unwrap())
+                    .collect();
+                hex(&regs)
+            }
+            "G" => {
+                let bytes = unhex(args)?;
+                let mut offset = 0;
+                for reg in 0..=FLAGS_REG {
+                    let len = self.read_reg(reg)?.len();
+                    self.write_reg(reg, bytes.get(offset..)?.get(..len)?)?;
+                    offset += len;
+                }
+                "OK".to_string()
+            }
+            "p" => hex(&self.read_reg(parse_hex(args)?)?),
+            "P" => {
+                let (reg, val) = args.split_once('=')?;
+                self.write_reg(parse_hex(reg)?, &unhex(val)?)?;
+                "OK".to_string()
+            }
+            "m" => {
+                let (addr, len) = addr_len(args)?;
+                match self.machine.mem.get(addr..addr.checked_add(len)?) {
+                    Some(bytes) => hex(bytes),
+                    None => "E01".to_string(),
+                }
+            }
+            "M" => {
+                let (range, data) = args.split_once(':')?;
+                let (addr, len) = addr_len(range)?;
+                let data = unhex(data)?;
+                match self.machine.mem.get_mut(addr..addr.checked_add(len)?) {
+                    Some(dest) if dest.len() == data.len() => {
+                        dest.copy_from_slice(&data);
+                        self.machine.mark_mem(addr..addr + len);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "s" | "c" => {
+                if let Some(addr) = parse_hex(args) {
+                    self.machine.pc = addr;
+                }
+                if !self.machine.is_running() {
+                    return Some(self.stop_reply(Ok(())));
+                }
+                let result = match cmd {
+                    "s" => self.machine.step().map(|_| false),
+                    _ => self.resume(input),
+                };
+                match result {
+                    Ok(true) => "S02".to_string(),
+                    result => self.stop_reply(result.map(|_| ())),
+                }
+            }
+            "Z" | "z" => {
+                let (kind, rest) = args.split_once(',')?;
+                if kind != "0" && kind != "1" {
+                    return Some(String::new());
+                }
+                let (addr, _) = addr_len(rest)?;
+                match cmd {
+                    "Z" => self.machine.add_breakpoint(addr),
+                    _ => self.machine.remove_breakpoint(addr),
+                };
+                "OK".to_string()
+            }
+            "H" => "OK".to_string(),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        })
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        let name = packet.split(':').next().unwrap_or_default();
+        match name {
+            "qSupported" => {
+                "PacketSize=4000;qXfer:features:read+;swbreak+;QStartNoAckMode+".to_string()
+            }
+            "QStartNoAckMode" => {
+                self.ack = false;
+                "OK".to_string()
+            }
+            "qXfer" => {
+                let xml = target_xml();
+                let request = packet.strip_prefix("qXfer:features:read:target.xml:");
+                match request.and_then(addr_len) {
+                    Some((offset, _)) if offset > xml.len() => "l".to_string(),
+                    Some((offset, len)) => {
+                        let end = offset.saturating_add(len).min(xml.len());
+                        let more = if end < xml.len() { 'm' } else { 'l' };
+                        format!("{}{}", more, &xml[offset..end])
+                    }
+                    None => "E00".to_string(),
+                }
+            }
+            "qAttached" => "1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "qC" => "QC1".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// Serves requests until the debugger detaches, kills the program or disconnects.
+    /// `input` is read on a separate thread, so that `continue` can be interrupted.
+    pub fn serve(
+        &mut self,
+        input: impl Read + Send + 'static,
+        output: &mut dyn Write,
+    ) -> Result<(), anyhow::Error> {
+        let mut input = Incoming::new(input);
+        while let Some((packet, valid)) = self.read_packet(&mut input) {
+            if self.ack {
+                // a corrupted packet is sent again after a nak
+                if !valid {
+                    output.write_all(b"-")?;
+                    output.flush()?;
+                    continue;
+                }
+                output.write_all(b"+")?;
+            }
+            match packet.as_str() {
+                "D" => {
+                    self.send(output, "OK")?;
+                    return Ok(());
+                }
+                "k" => return Ok(()),
+                _ => (),
+            }
+            let reply = match self.handle(&packet, &mut input) {
+                Some(reply) => reply,
+                None => "E02".to_string(),
+            };
+            self.send(output, &reply)?;
+        }
+        Ok(())
+    }
+}
@@ -1,14 +1,71 @@
+use cache::Caches;
 use core::mem::size_of;
 use std::{
+    collections::{BTreeSet, HashSet},
     fmt::Display,
-    io::{self, Read},
+    io::{self, IsTerminal, Read},
+    rc::Rc,
 };
 
+mod asm;
+mod cache;
+mod callstack;
+mod console;
+mod coverage;
+mod dap;
+mod debugger;
+mod diff;
+mod disasm;
+mod export;
+mod extension;
+mod gdb;
+mod json;
+mod linker;
+mod loader;
+mod lsp;
+mod memview;
+mod object;
+mod pipeline;
+mod predict;
+mod profile;
+mod runtime;
+mod source;
+mod symbols;
+mod tui;
+mod uninit;
+
+pub use asm::{AsmError, Assembler, Program, ProgramLine};
+pub use cache::{Cache, CacheConfig, CacheStats, Outcome, Replacement, WritePolicy};
+pub use callstack::{CallCheck, Frame};
+pub use console::Console;
+pub use coverage::Coverage;
+pub use dap::DapServer;
+pub use diff::Snapshot;
+pub use export::DumpFormat;
+pub use extension::InstructionExtension;
+pub use gdb::GdbStub;
+pub use linker::{Image, Linker};
+pub use loader::{ImageFormat, LoadError, LoadErrorKind, LoadMode};
+pub use lsp::LanguageServer;
+pub use memview::{MemView, Radix, Unit};
+pub use object::{Object, Reloc, Section};
+pub use pipeline::{Hazards, Pipeline, Squashed, Stall, Timing};
+pub use predict::{
+    predictor, AlwaysTaken, BranchPredictor, BranchStats, Btfnt, NeverTaken, OneBit, ReturnStack,
+    TwoBit,
+};
+pub use profile::Profile;
+pub use runtime::RUNTIME;
+pub use source::SourceMap;
+pub use symbols::SymbolTable;
+pub use tui::{TuiKey, TuiState};
+
 const REG_NAMES: [&str; 15] = [
     "%rax", "%rcx", "%rdx", "%rbx", "%rsp", "%rbp", "%rsi", "%rdi", "%r08", "%r09", "%r10", "%r11",
     "%r12", "%r13", "%r14",
 ];
 const RSP: usize = 4;
+const RNONE: usize = 0xf;
 
 fn wait_until_key(target: u8) {
     // this is so bad
@@ -19,12 +76,13 @@ fn wait_until_key(target: u8) {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum StepMode {
     NoStep,
     Stage,
     Cycle,
     Debug,
+    Tui,
 }
 
 pub enum Stage {
@@ -49,10 +107,17 @@ impl Display for Stage {
     }
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DecodeMode {
+    Lenient,
+    Strict,
+}
+
 #[derive(PartialEq)]
 enum Status {
     Halt,
     Aok,
+    Ins,
 }
 
 impl Display for Status {
@@ -60,11 +125,13 @@ impl Display for Status {
         match self {
             Status::Halt => write!(f, "STAT: HLT"),
             Status::Aok => write!(f, "STAT: AOK"),
+            Status::Ins => write!(f, "STAT: INS"),
         }
     }
 }
 
-struct Flags(bool, bool, bool);
+#[derive(Clone, PartialEq)]
+pub struct Flags(pub bool, pub bool, pub bool);
 
 impl Display for Flags {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -78,15 +145,29 @@ impl Display for Flags {
 pub struct Machine {
     mem: Vec<u8>,
     step_mode: StepMode,
+    decode_mode: DecodeMode,
+    call_check: CallCheck,
     regs: Vec<isize>,
     flags: Flags,
     status: Status,
     cycle: usize,
     pc: usize,
+    symbols: SymbolTable,
+    source: SourceMap,
+    extensions: Vec<Rc<dyn InstructionExtension>>,
+    breakpoints: BTreeSet<usize>,
+    frames: Vec<Frame>,
+    warnings: Vec<String>,
+    // which bytes of mem and which regs have been written, by the loader or the program
+    mem_init: Vec<bool>,
+    reg_init: Vec<bool>,
+    check_uninit: bool,
+    uninit_reported: HashSet<usize>,
+    caches: Caches,
 }
 
 #[derive(PartialEq)]
-enum OpCode {
+pub enum OpCode {
     Halt,
     Nop,
     Cmov,
@@ -99,6 +180,7 @@ enum OpCode {
     Ret,
     Push,
     Pop,
+    Ext(usize),
 }
 
 impl Display for OpCode {
@@ -116,12 +198,13 @@ impl Display for OpCode {
             OpCode::Ret => write!(f, "ret"),
             OpCode::Push => write!(f, "push"),
             OpCode::Pop => write!(f, "pop"),
+            OpCode::Ext(_) => write!(f, "ext"),
         }
     }
 }
 
 #[derive(Copy, Clone)]
-enum FunCode {
+pub enum FunCode {
     Add,
     Sub,
     And,
@@ -155,22 +238,52 @@ impl Display for FunCode {
     }
 }
 
-struct CycleState {
-    op: OpCode,
-    fun: FunCode,
-    r_a: usize,
-    r_b: usize,
-    val_c: isize,
-    val_p: usize,
-    val_a: isize,
-    val_b: isize,
-    val_e: isize,
-    val_m: isize,
-    cnd: bool,
+pub struct CycleState {
+    pub op: OpCode,
+    pub fun: FunCode,
+    pub icode: u8,
+    pub ifun: u8,
+    pub r_a: usize,
+    pub r_b: usize,
+    pub val_c: isize,
+    pub val_p: usize,
+    pub val_a: isize,
+    pub val_b: isize,
+    pub val_e: isize,
+    pub val_m: isize,
+    pub cnd: bool,
+}
+
+impl CycleState {
+    fn new() -> CycleState {
+        CycleState {
+            op: OpCode::Halt,
+            fun: FunCode::None,
+            icode: 0,
+            ifun: 0,
+            r_a: 0,
+            r_b: 0,
+            val_c: 0,
+            val_p: 0,
+            val_a: 0,
+            val_b: 0,
+            val_e: 0,
+            val_m: 0,
+            cnd: false,
+        }
+    }
 }
 
 impl Machine {
     pub fn new(mem_size: usize, step_mode: StepMode) -> Machine {
+        Machine::with_extensions(mem_size, step_mode, Vec::new())
+    }
+
+    pub fn with_extensions(
+        mem_size: usize,
+        step_mode: StepMode,
+        extensions: Vec<Box<dyn InstructionExtension>>,
+    ) -> Machine {
         let mem = vec![0; mem_size];
         let regs = vec![0; 15];
         let status = Status::Aok;
@@ -181,69 +294,43 @@ impl Machine {
         Machine {
             mem,
             step_mode,
+            decode_mode: DecodeMode::Lenient,
+            call_check: CallCheck::Off,
             regs,
             flags,
             status,
             cycle,
             pc,
+            symbols: SymbolTable::new(),
+            source: SourceMap::new(),
+            extensions: extensions.into_iter().map(Rc::from).collect(),
+            breakpoints: BTreeSet::new(),
+            frames: Vec::new(),
+            warnings: Vec::new(),
+            mem_init: vec![false; mem_size],
+            reg_init: vec![false; 15],
+            check_uninit: false,
+            uninit_reported: HashSet::new(),
+            caches: Caches::default(),
         }
     }
 
-    pub fn load(&mut self, file: String) -> Result<(), anyhow::Error> {
-        for line in file.lines() {
-            if !line.starts_with("0x") {
-                continue;
-            }
-            let colon = match line.find(':') {
-                Some(i) => i,
-                None => {
-                    continue;
-                }
-            };
-
-            let addr_str = line.get(2..colon).unwrap();
-            let mut addr = usize::from_str_radix(addr_str, 16)?;
-
-            let pipe = match line.find('|') {
-                Some(i) => i,
-                None => {
-                    continue;
-                }
-            };
-
-            let enc = line.get(colon + 1..pipe).unwrap().trim().as_bytes();
-            let mut i = 0;
-            while i < enc.len() {
-                let mut byte = enc[i];
-                if byte > b'9' {
-                    byte = (byte & 0x0f) + 9;
-                } else {
-                    byte &= 0x0f;
-                }
-                byte <<= 4;
+    pub fn set_decode_mode(&mut self, decode_mode: DecodeMode) {
+        self.decode_mode = decode_mode;
+    }
 
-                byte |= if enc[i + 1] > b'9' {
-                    (enc[i + 1] & 0x0f) + 9
-                } else {
-                    enc[i + 1] & 0x0f
-                };
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
 
-                if let Some(x) = self.mem.get_mut(addr) {
-                    *x = byte;
-                } else {
-                    break;
-                }
-                i += 2;
-                addr += 1;
-            }
-        }
-        Ok(())
+    pub fn source(&self) -> &SourceMap {
+        &self.source
     }
 
-    fn get_mem_word(&self, addr: usize) -> Result<isize, anyhow::Error> {
+    pub fn get_mem_word(&self, addr: usize) -> Result<isize, anyhow::Error> {
         let mut word = 0;
         let wordsize = size_of::<usize>();
-        let bytes = match self.mem.get(addr..addr + wordsize) {
+        let bytes = match self.mem.get(addr..addr.saturating_add(wordsize)) {
             Some(bytes) => bytes,
             None => anyhow::bail!("get word: bad addr"),
         };
@@ -255,9 +342,9 @@ impl Machine {
         Ok(word)
     }
 
-    fn set_mem_word(&mut self, addr: usize, word: isize) -> Result<(), anyhow::Error> {
+    pub fn set_mem_word(&mut self, addr: usize, word: isize) -> Result<(), anyhow::Error> {
         let wordsize = size_of::<usize>();
-        let bytes = match self.mem.get_mut(addr..addr + wordsize) {
+        let bytes = match self.mem.get_mut(addr..addr.saturating_add(wordsize)) {
             Some(bytes) => bytes,
             None => anyhow::bail!("set word: bad addr"),
         };
@@ -265,28 +352,44 @@ impl Machine {
         for (wbyte, mbyte) in word.to_le_bytes().iter().zip(bytes.iter_mut()) {
             *mbyte = *wbyte
         }
+        self.mark_mem(addr..addr + wordsize);
 
         Ok(())
     }
 
-    fn fetch(&self, state: &mut CycleState) -> Result<(), anyhow::Error> {
-        let (code, fun) = match self.mem.get(self.pc) {
+    fn fetch(&self, pc: usize, state: &mut CycleState) -> Result<(), anyhow::Error> {
+        let (code, fun) = match self.mem.get(pc) {
             Some(byte) => (byte / 16, byte & 0x0f),
             None => anyhow::bail!("bad addr"),
         };
+        state.icode = code;
+        state.ifun = fun;
+
+        if let Some(idx) = self.extensions.iter().position(|e| e.claims(code, fun)) {
+            let ext = &self.extensions[idx];
+            let len = ext.length(code, fun);
+            let bytes = match self.mem.get(pc..pc + len) {
+                Some(bytes) => bytes,
+                None => anyhow::bail!("bad addr"),
+            };
+            state.op = OpCode::Ext(idx);
+            state.val_p = pc + len;
+            ext.fetch(state, bytes)?;
+            return Ok(());
+        }
 
         match code {
             0 => {
                 state.op = OpCode::Halt;
-                state.val_p = self.pc + 1;
+                state.val_p = pc + 1;
             }
             1 => {
                 state.op = OpCode::Nop;
-                state.val_p = self.pc + 1;
+                state.val_p = pc + 1;
             }
             2 => {
                 state.op = OpCode::Cmov;
-                state.val_p = self.pc + 2;
+                state.val_p = pc + 2;
                 state.fun = match fun {
                     0 => FunCode::Ucnd,
                     1 => FunCode::Lte,
@@ -297,19 +400,19 @@ impl Machine {
                     6 => FunCode::Gt,
                     _ => anyhow::bail!("bad ifun for cmov"),
                 };
-                (state.r_a, state.r_b) = match self.mem.get(self.pc + 1) {
+                (state.r_a, state.r_b) = match self.mem.get(pc + 1) {
                     Some(byte) => ((byte / 16) as usize, (byte & 0x0f) as usize),
                     None => anyhow::bail!("bad addr"),
                 };
             }
             3 => {
                 state.op = OpCode::Irmov;
-                state.val_p = self.pc + 10;
-                (state.r_a, state.r_b) = match self.mem.get(self.pc + 1) {
+                state.val_p = pc + 10;
+                (state.r_a, state.r_b) = match self.mem.get(pc + 1) {
                     Some(byte) => ((byte / 16) as usize, (byte & 0x0f) as usize),
                     None => anyhow::bail!("bad addr"),
                 };
-                state.val_c = self.get_mem_word(self.pc + 2)?;
+                state.val_c = self.get_mem_word(pc + 2)?;
             }
             4 | 5 => {
                 state.op = if code == 4 {
@@ -317,12 +420,12 @@ impl Machine {
                 } else {
                     OpCode::Mrmov
                 };
-                state.val_p = self.pc + 10;
-                (state.r_a, state.r_b) = match self.mem.get(self.pc + 1) {
+                state.val_p = pc + 10;
+                (state.r_a, state.r_b) = match self.mem.get(pc + 1) {
                     Some(byte) => ((byte / 16) as usize, (byte & 0x0f) as usize),
                     None => anyhow::bail!("bad addr"),
                 };
-                state.val_c = self.get_mem_word(self.pc + 2)?;
+                state.val_c = self.get_mem_word(pc + 2)?;
             }
             6 => {
                 state.op = OpCode::Opx;
@@ -333,11 +436,11 @@ impl Machine {
                     3 => FunCode::Xor,
                     _ => anyhow::bail!("bad ifun for opx"),
                 };
-                (state.r_a, state.r_b) = match self.mem.get(self.pc + 1) {
+                (state.r_a, state.r_b) = match self.mem.get(pc + 1) {
                     Some(byte) => ((byte / 16) as usize, (byte & 0x0f) as usize),
                     None => anyhow::bail!("bad addr"),
                 };
-                state.val_p = self.pc + 2;
+                state.val_p = pc + 2;
             }
             7 => {
                 state.op = OpCode::Jxx;
@@ -351,33 +454,33 @@ impl Machine {
                     6 => FunCode::Gt,
                     _ => anyhow::bail!("bad ifun for jxx"),
                 };
-                state.val_c = self.get_mem_word(self.pc + 1)?;
-                state.val_p = self.pc + 9;
+                state.val_c = self.get_mem_word(pc + 1)?;
+                state.val_p = pc + 9;
             }
             8 => {
                 state.op = OpCode::Call;
-                state.val_c = self.get_mem_word(self.pc + 1)?;
-                state.val_p = self.pc + 9;
+                state.val_c = self.get_mem_word(pc + 1)?;
+                state.val_p = pc + 9;
             }
             9 => {
                 state.op = OpCode::Ret;
-                state.val_p = self.pc + 1;
+                state.val_p = pc + 1;
             }
             0xa => {
                 state.op = OpCode::Push;
-                (state.r_a, state.r_b) = match self.mem.get(self.pc + 1) {
+                (state.r_a, state.r_b) = match self.mem.get(pc + 1) {
                     Some(byte) => ((byte / 16) as usize, (byte & 0x0f) as usize),
                     None => anyhow::bail!("bad addr"),
                 };
-                state.val_p = self.pc + 2;
+                state.val_p = pc + 2;
             }
             0xb => {
                 state.op = OpCode::Pop;
-                (state.r_a, state.r_b) = match self.mem.get(self.pc + 1) {
+                (state.r_a, state.r_b) = match self.mem.get(pc + 1) {
                     Some(byte) => ((byte / 16) as usize, (byte & 0x0f) as usize),
                     None => anyhow::bail!("bad addr"),
                 };
-                state.val_p = self.pc + 2;
+                state.val_p = pc + 2;
             }
             _ => anyhow::bail!("bad icode"),
         }
@@ -385,7 +488,60 @@ impl Machine {
         Ok(())
     }
 
-    fn decode(&self, state: &mut CycleState) -> Result<(), anyhow::Error> {
+    // names the icode or ifun that made the fetch of `state` fail, if either did
+    fn check_opcode(&self, state: &CycleState) -> Option<String> {
+        let (code, fun) = (state.icode, state.ifun);
+        if self.extensions.iter().any(|e| e.claims(code, fun)) {
+            return None;
+        }
+        match code {
+            0x2 | 0x7 if fun > 6 => Some(format!("icode {:x} has no ifun {:x}", code, fun)),
+            0x6 if fun > 3 => Some(format!("icode {:x} has no ifun {:x}", code, fun)),
+            0xc..=0xf => Some(format!("unknown icode {:x}", code)),
+            _ => None,
+        }
+    }
+
+    // checks the register specifier and ifun of an already fetched instruction against
+    // the Y86-64 encoding rules, where the lenient fetch only fails once a bad register is used
+    fn check_encoding(&self, state: &CycleState) -> Result<(), String> {
+        let (name, no_fun, r_a_none, r_b_none) = match state.op {
+            OpCode::Halt => ("halt", true, None, None),
+            OpCode::Nop => ("nop", true, None, None),
+            OpCode::Cmov => ("rrmovq/cmovXX", false, Some(false), Some(false)),
+            OpCode::Irmov => ("irmovq", true, Some(true), Some(false)),
+            // rB is the base register and may be F for absolute addressing
+            OpCode::Rmmov => ("rmmovq", true, Some(false), None),
+            OpCode::Mrmov => ("mrmovq", true, Some(false), None),
+            OpCode::Opx => ("OPq", false, Some(false), Some(false)),
+            OpCode::Jxx => ("jXX", false, None, None),
+            OpCode::Call => ("call", true, None, None),
+            OpCode::Ret => ("ret", true, None, None),
+            OpCode::Push => ("pushq", true, Some(false), Some(true)),
+            OpCode::Pop => ("popq", true, Some(false), Some(true)),
+            OpCode::Ext(_) => return Ok(()),
+        };
+
+        if no_fun && state.ifun != 0 {
+            return Err(format!("{} must have ifun 0, found {:x}", name, state.ifun));
+        }
+        for (field, reg, want_none) in [("rA", state.r_a, r_a_none), ("rB", state.r_b, r_b_none)] {
+            match want_none {
+                Some(true) if reg != RNONE => {
+                    return Err(format!("{} {} must be F, found {:x}", name, field, reg))
+                }
+                Some(false) if reg == RNONE => {
+                    return Err(format!("{} {} must name a register, found F", name, field))
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn decode(&mut self, state: &mut CycleState) -> Result<(), anyhow::Error> {
+        self.check_reg_reads(state);
         match state.op {
             OpCode::Rmmov | OpCode::Opx | OpCode::Cmov => {
                 state.val_a = match self.regs.get(state.r_a) {
@@ -394,6 +550,8 @@ impl Machine {
                 };
                 state.val_b = match self.regs.get(state.r_b) {
                     Some(&val) => val,
+                    // an F base register means absolute addressing
+                    None if state.r_b == RNONE && state.op == OpCode::Rmmov => 0,
                     None => anyhow::bail!("bad reg in rmmov/opx"),
                 }
             }
@@ -401,6 +559,7 @@ impl Machine {
                 let (idx, val) = (state.r_b, &mut state.val_b);
                 *val = match self.regs.get(idx) {
                     Some(&val) => val,
+                    None if idx == RNONE => 0,
                     None => anyhow::bail!("bad reg in cmov/mrmov"),
                 };
             }
@@ -418,6 +577,7 @@ impl Machine {
                 state.val_b = rsp;
                 state.val_a = rsp;
             }
+            OpCode::Ext(idx) => self.extensions[idx].decode(self, state)?,
             OpCode::Push => {
                 state.val_a = match self.regs.get(state.r_a) {
                     Some(&val) => val,
@@ -436,6 +596,10 @@ impl Machine {
     }
 
     fn execute(&mut self, state: &mut CycleState) -> Result<(), anyhow::Error> {
+        if let OpCode::Ext(idx) = state.op {
+            return self.with_extension(idx, |ext, m| ext.execute(m, state));
+        }
+
         state.val_e = match state.op {
             OpCode::Irmov => state.val_c,
             OpCode::Cmov => {
@@ -487,14 +651,20 @@ impl Machine {
     }
 
     fn memory(&mut self, state: &mut CycleState) -> Result<(), anyhow::Error> {
+        self.cache_data(state);
         match state.op {
             OpCode::Rmmov => self.set_mem_word(state.val_e as usize, state.val_a)?,
             OpCode::Mrmov => {
+                self.check_mem_read(state.val_e as usize, state);
                 state.val_m = self.get_mem_word(state.val_e as usize)?;
             }
             OpCode::Call => self.set_mem_word(state.val_e as usize, state.val_p as isize)?,
             OpCode::Push => self.set_mem_word(state.val_e as usize, state.val_a)?,
-            OpCode::Ret | OpCode::Pop => state.val_m = self.get_mem_word(state.val_a as usize)?,
+            OpCode::Ret | OpCode::Pop => {
+                self.check_mem_read(state.val_a as usize, state);
+                state.val_m = self.get_mem_word(state.val_a as usize)?;
+            }
+            OpCode::Ext(idx) => self.with_extension(idx, |ext, m| ext.memory(m, state))?,
             _ => (),
         };
 
@@ -516,17 +686,19 @@ impl Machine {
                 None => anyhow::bail!("bad reg for call/ret/push"),
             },
             OpCode::Pop => {
-                match self.regs.get_mut(state.r_a) {
-                    Some(reg) => *reg = state.val_a,
-                    None => anyhow::bail!("bad reg for pop"),
-                };
                 match self.regs.get_mut(RSP) {
                     Some(reg) => *reg = state.val_e,
-                    None => anyhow::bail!("bad reg for mrmov"),
+                    None => anyhow::bail!("bad reg for pop"),
+                };
+                match self.regs.get_mut(state.r_a) {
+                    Some(reg) => *reg = state.val_m,
+                    None => anyhow::bail!("bad reg for pop"),
                 };
             }
+            OpCode::Ext(idx) => self.with_extension(idx, |ext, m| ext.writeback(m, state))?,
             _ => (),
         };
+        self.mark_writeback(state);
 
         Ok(())
     }
@@ -539,6 +711,7 @@ impl Machine {
             OpCode::Jxx => state.val_e as usize,
             OpCode::Ret => state.val_m as usize,
             OpCode::Call => state.val_c as usize,
+            OpCode::Ext(idx) => self.extensions[idx].pc_update(state).unwrap_or(state.val_p),
             _ => state.val_p,
         };
 
@@ -546,80 +719,141 @@ impl Machine {
     }
 
     pub fn run(&mut self) -> Result<(), anyhow::Error> {
+        if self.step_mode == StepMode::Cycle {
+            return self.run_cycles();
+        }
         while self.status == Status::Aok {
             match self.step_mode {
-                StepMode::Stage | StepMode::Cycle => {
+                StepMode::Stage => {
                     println!("{}", self);
+                    if let Some(context) = self.source.context(self.pc, 2) {
+                        println!("{}", context);
+                    }
                     wait_until_key(0x0a);
                 }
+                StepMode::Debug => return self.debug(),
+                StepMode::Tui => return self.tui(),
                 _ => (),
             }
 
-            let mut cycle_state = CycleState {
-                op: OpCode::Halt,
-                fun: FunCode::None,
-                r_a: 0,
-                r_b: 0,
-                val_c: 0,
-                val_p: 0,
-                val_a: 0,
-                val_b: 0,
-                val_e: 0,
-                val_m: 0,
-                cnd: false,
-            };
+            self.step()?;
+            self.report_warnings();
+        }
+
+        Ok(())
+    }
 
-            self.fetch(&mut cycle_state)?;
-            self.do_step(Stage::Fetch, &cycle_state);
+    /// Runs until the machine stops without any stepping, handing `observe` the PC and the
+    /// cycle state of every instruction once it has run.
+    pub fn run_with(
+        &mut self,
+        mut observe: impl FnMut(&Machine, usize, &CycleState),
+    ) -> Result<(), anyhow::Error> {
+        while self.status == Status::Aok {
+            let pc = self.pc;
+            let state = self.step()?;
+            self.report_warnings();
+            observe(self, pc, &state);
+        }
+
+        Ok(())
+    }
 
-            self.decode(&mut cycle_state)?;
-            self.do_step(Stage::Decode, &cycle_state);
+    // `-c`: the full state once, then only what each cycle changed
+    fn run_cycles(&mut self) -> Result<(), anyhow::Error> {
+        let color = io::stdout().is_terminal();
+        println!("{}", self);
+        while self.status == Status::Aok {
+            if let Some(context) = self.source.context(self.pc, 2) {
+                println!("{}", context);
+            }
+            wait_until_key(0x0a);
 
-            self.execute(&mut cycle_state)?;
-            self.do_step(Stage::Execute, &cycle_state);
+            let before = self.snapshot();
+            self.step()?;
+            print!("{}", self.diff(&before, color));
+            self.report_warnings();
+        }
 
-            self.memory(&mut cycle_state)?;
-            self.do_step(Stage::Memory, &cycle_state);
+        Ok(())
+    }
 
-            self.writeback(&mut cycle_state)?;
-            self.do_step(Stage::Writeback, &cycle_state);
+    /// Runs a single fetch through PC update cycle. Errors carry the listing line of the
+    /// faulting instruction when one is known, and a backtrace inside a call.
+    pub fn step(&mut self) -> Result<CycleState, anyhow::Error> {
+        let pc = self.pc;
+        self.cycle_once().map_err(|e| {
+            let e = match self.source.context(pc, 2) {
+                Some(context) => anyhow::anyhow!("{}\nat 0x{:04x}:\n{}", e, pc, context),
+                None => e,
+            };
+            match self.frames.is_empty() {
+                true => e,
+                false => anyhow::anyhow!("{}\nbacktrace:\n{}", e, self.backtrace()),
+            }
+        })
+    }
 
-            self.pc_update(&mut cycle_state)?;
-            self.do_step(Stage::PcUpdate, &cycle_state);
+    fn cycle_once(&mut self) -> Result<CycleState, anyhow::Error> {
+        let mut cycle_state = CycleState::new();
 
-            self.cycle += 1;
+        if let Err(e) = self.fetch(self.pc, &mut cycle_state) {
+            if self.decode_mode == DecodeMode::Strict {
+                if let Some(violation) = self.check_opcode(&cycle_state) {
+                    self.status = Status::Ins;
+                    anyhow::bail!("INS fault at 0x{:04x}: {}", self.pc, violation);
+                }
+            }
+            return Err(e);
         }
+        self.cache_fetch(self.pc..cycle_state.val_p);
+        if self.decode_mode == DecodeMode::Strict {
+            if let Err(violation) = self.check_encoding(&cycle_state) {
+                self.status = Status::Ins;
+                anyhow::bail!("INS fault at 0x{:04x}: {}", self.pc, violation);
+            }
+        }
+        self.do_step(Stage::Fetch, &cycle_state);
 
-        Ok(())
+        self.decode(&mut cycle_state)?;
+        self.do_step(Stage::Decode, &cycle_state);
+
+        self.execute(&mut cycle_state)?;
+        self.do_step(Stage::Execute, &cycle_state);
+
+        self.memory(&mut cycle_state)?;
+        self.do_step(Stage::Memory, &cycle_state);
+
+        self.writeback(&mut cycle_state)?;
+        self.do_step(Stage::Writeback, &cycle_state);
+
+        let pc = self.pc;
+        self.pc_update(&mut cycle_state)?;
+        self.do_step(Stage::PcUpdate, &cycle_state);
+        self.track_call(pc, &cycle_state)?;
+
+        self.cycle += 1;
+
+        Ok(cycle_state)
     }
 
     fn format_mem(&self) -> String {
-        let mut i = 0;
         let mut str = String::new();
         let wordsize = size_of::<usize>();
-        while i < self.mem.len() {
-            let bytes = match self.mem.get(i..i + wordsize) {
-                Some(val) => val,
-                None => panic!("badaddr"),
-            };
-            i += wordsize;
-
-            if bytes.iter().all(|&e| e == 0) {
+        for (i, bytes) in self.mem.chunks(wordsize).enumerate() {
+            let addr = i * wordsize;
+            if bytes.iter().all(|&e| e == 0)
+                || !(addr..addr + wordsize).any(|a| self.is_initialized(a))
+            {
                 continue;
             }
 
-            str.push_str(&format!(
-                "0x{:04x}: {:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}\n",
-                i - wordsize,
-                bytes[0],
-                bytes[1],
-                bytes[2],
-                bytes[3],
-                bytes[4],
-                bytes[5],
-                bytes[6],
-                bytes[7]
-            ));
+            let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            str.push_str(&format!("0x{:04x}: {}", addr, hex));
+            match self.symbols.name_at(addr) {
+                Some(name) => str.push_str(&format!(" <{}>\n", name)),
+                None => str.push('\n'),
+            }
         }
 
         str
@@ -636,7 +870,49 @@ impl Machine {
         str
     }
 
-    fn cond(&self, fun: FunCode) -> bool {
+    pub fn get_reg(&self, reg: usize) -> Result<isize, anyhow::Error> {
+        match self.regs.get(reg) {
+            Some(&val) => Ok(val),
+            None => anyhow::bail!("bad reg {:x}", reg),
+        }
+    }
+
+    pub fn set_reg(&mut self, reg: usize, val: isize) -> Result<(), anyhow::Error> {
+        match self.regs.get_mut(reg) {
+            Some(r) => *r = val,
+            None => anyhow::bail!("bad reg {:x}", reg),
+        }
+        self.mark_reg(reg);
+
+        Ok(())
+    }
+
+    pub fn flags(&self) -> &Flags {
+        &self.flags
+    }
+
+    pub fn set_flags(&mut self, flags: Flags) {
+        self.flags = flags;
+    }
+
+    fn with_extension<T>(
+        &mut self,
+        idx: usize,
+        f: impl FnOnce(&dyn InstructionExtension, &mut Machine) -> T,
+    ) -> T {
+        // a handle of its own, so the extension can still look itself up through `self`
+        let ext = Rc::clone(&self.extensions[idx]);
+        f(ext.as_ref(), self)
+    }
+
+    fn op_name(&self, state: &CycleState) -> String {
+        match state.op {
+            OpCode::Ext(idx) => self.extensions[idx].mnemonic(state.icode, state.ifun),
+            _ => state.op.to_string(),
+        }
+    }
+
+    pub fn cond(&self, fun: FunCode) -> bool {
         let sf = self.flags.0;
         let zf = self.flags.1;
         let of = self.flags.2;
@@ -646,8 +922,8 @@ impl Machine {
             FunCode::Lt => sf ^ of,
             FunCode::Eq => zf,
             FunCode::Neq => !zf,
-            FunCode::Gte => !(sf ^ of) && zf,
-            FunCode::Gt => !(sf ^ of),
+            FunCode::Gte => !(sf ^ of),
+            FunCode::Gt => !(sf ^ of) && !zf,
             _ => false,
         }
     }
@@ -655,14 +931,15 @@ impl Machine {
     fn do_step(&self, stage: Stage, state: &CycleState) {
         if self.step_mode == StepMode::Stage {
             println!(
-                r#"{}:
+                r#"{}: {}
 icode:ifun = {}:{} rA:rB = {:x}:{:x}
 valC = 0x{:016x} valP = 0x{:016x}
 valA = 0x{:016x} valB = 0x{:016x}
 valE = 0x{:016x} valM = 0x{:016x}
 Cnd = {}"#,
                 stage,
-                state.op,
+                self.format_instr(state),
+                self.op_name(state),
                 state.fun,
                 state.r_a,
                 state.r_b,
@@ -686,6 +963,14 @@ impl Display for Machine {
         writeln!(f, "{}", self.format_regs())?;
         writeln!(f, "{}", self.flags)?;
         writeln!(f, "{}", self.status)?;
-        writeln!(f, "PC: 0x{:04x}", self.pc)
+        match self.symbols.nearest(self.pc) {
+            Some(_) => writeln!(
+                f,
+                "PC: 0x{:04x} <{}>",
+                self.pc,
+                self.symbols.symbolize(self.pc)
+            ),
+            None => writeln!(f, "PC: 0x{:04x}", self.pc),
+        }
     }
 }
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use crate::{object::Object, Machine, SourceMap};
+
+/// Lays out relocatable objects into a single absolute image. Sections with the same name
+/// are placed together, in the order names are first seen, each 8 byte aligned.
+pub struct Linker {
+    objects: Vec<Object>,
+}
+
+/// A linked program, loadable with `Machine::load_image` or written out as a `.yo` listing.
+pub struct Image {
+    // (address, bytes, assembly text) per listing line
+    lines: Vec<(Option<usize>, Vec<u8>, String)>,
+    pub symbols: Vec<(String, usize)>,
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Linker::new()
+    }
+}
+
+impl Linker {
+    pub fn new() -> Linker {
+        Linker {
+            objects: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, object: Object) {
+        self.objects.push(object);
+    }
+
+    pub fn link(&self, base: usize) -> Result<Image, anyhow::Error> {
+        let mut order: Vec<&str> = Vec::new();
+        for obj in &self.objects {
+            for sec in &obj.sections {
+                if !order.contains(&sec.name.as_str()) {
+                    order.push(&sec.name);
+                }
+            }
+        }
+
+        // absolute address of every (object, section)
+        let mut placement = HashMap::new();
+        let mut addr = base;
+        for name in &order {
+            for (obj_idx, obj) in self.objects.iter().enumerate() {
+                if let Some(sec_idx) = obj.sections.iter().position(|s| s.name == *name) {
+                    let len = obj.sections[sec_idx].bytes.len();
+                    let start = match addr.checked_next_multiple_of(8) {
+                        Some(start) if start.checked_add(len).is_some() => start,
+                        _ => anyhow::bail!(
+                            "{}: the image does not fit in memory from base 0x{:x}",
+                            obj.name,
+                            base
+                        ),
+                    };
+                    placement.insert((obj_idx, sec_idx), start);
+                    addr = start + len;
+                }
+            }
+        }
+
+        let mut globals = HashMap::new();
+        for (obj_idx, obj) in self.objects.iter().enumerate() {
+            for name in &obj.globals {
+                let (sec_idx, offset) = match obj.label(name) {
+                    Some(found) => found,
+                    None => {
+                        anyhow::bail!("{}: exported symbol `{}` is not defined", obj.name, name)
+                    }
+                };
+                let addr = placement[&(obj_idx, sec_idx)] + offset;
+                if let Some(prev) = globals.insert(name.as_str(), (addr, obj_idx)) {
+                    anyhow::bail!(
+                        "{}: symbol `{}` is already exported by {}",
+                        obj.name,
+                        name,
+                        self.objects[prev.1].name
+                    );
+                }
+            }
+        }
+
+        let mut image = Image {
+            lines: Vec::new(),
+            symbols: Vec::new(),
+        };
+        for name in &order {
+            for (obj_idx, obj) in self.objects.iter().enumerate() {
+                let sec_idx = match obj.sections.iter().position(|s| s.name == *name) {
+                    Some(idx) => idx,
+                    None => continue,
+                };
+                let sec = &obj.sections[sec_idx];
+                let start = placement[&(obj_idx, sec_idx)];
+
+                let mut bytes = sec.bytes.clone();
+                for reloc in &sec.relocs {
+                    let target = match obj.label(&reloc.symbol) {
+                        Some((idx, offset)) => placement[&(obj_idx, idx)] + offset,
+                        None => match globals.get(reloc.symbol.as_str()) {
+                            Some(&(addr, _)) => addr,
+                            None => {
+                                anyhow::bail!("{}: undefined symbol `{}`", obj.name, reloc.symbol)
+                            }
+                        },
+                    };
+                    let value = (target as isize).wrapping_add(reloc.addend);
+                    bytes[reloc.offset..reloc.offset + 8].copy_from_slice(&value.to_le_bytes());
+                }
+
+                for (label, offset) in &sec.labels {
+                    image.symbols.push((label.clone(), start + offset));
+                }
+                for (offset, len, text) in &sec.lines {
+                    let line = match offset {
+                        Some(off) => (Some(start + off), bytes[*off..off + len].to_vec()),
+                        None => (None, Vec::new()),
+                    };
+                    image.lines.push((line.0, line.1, text.clone()));
+                }
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+pub(crate) fn listing_line(addr: Option<usize>, bytes: &[u8], text: &str) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    match addr {
+        Some(addr) => format!("0x{:04x}: {:<20} |{}\n", addr, hex, text),
+        None => format!("{:29}|{}\n", "", text),
+    }
+}
+
+impl Image {
+    pub fn to_listing(&self) -> String {
+        self.lines
+            .iter()
+            .map(|(addr, bytes, text)| listing_line(*addr, bytes, text))
+            .collect()
+    }
+}
+
+impl Machine {
+    /// Copies a linked image into memory, along with its symbols and listing lines.
+    pub fn load_image(&mut self, image: &Image) -> Result<(), anyhow::Error> {
+        self.source = SourceMap::new();
+        for (i, (addr, bytes, text)) in image.lines.iter().enumerate() {
+            self.source.push_line(text);
+            let addr = match addr {
+                Some(addr) if !bytes.is_empty() => *addr,
+                _ => continue,
+            };
+            let range = addr.checked_add(bytes.len()).map(|end| addr..end);
+            match range.clone().and_then(|range| self.mem.get_mut(range)) {
+                Some(dest) => dest.copy_from_slice(bytes),
+                None => anyhow::bail!(
+                    "linked code at 0x{:04x} does not fit in memory (size 0x{:x})",
+                    addr,
+                    self.mem.len()
+                ),
+            }
+            self.mark_mem(range.unwrap_or_default());
+            self.source.map(addr, i + 1);
+        }
+        for (name, addr) in &image.symbols {
+            self.symbols.insert(name, *addr);
+        }
+        Ok(())
+    }
+}
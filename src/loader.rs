@@ -0,0 +1,397 @@
+use std::fmt::Display;
+
+use crate::{symbols::parse_label, Machine, SourceMap};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LoadMode {
+    Fail,
+    Warn,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ImageFormat {
+    Yo,
+    Binary,
+    IntelHex,
+    Object,
+    Source,
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name {
+            "yo" => Some(ImageFormat::Yo),
+            "bin" | "raw" => Some(ImageFormat::Binary),
+            "hex" | "ihex" => Some(ImageFormat::IntelHex),
+            "yro" => Some(ImageFormat::Object),
+            "ys" => Some(ImageFormat::Source),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format from a file extension, defaulting to a `.yo` listing.
+    pub fn from_path(path: &str) -> ImageFormat {
+        match path.rsplit_once('.') {
+            Some((_, ext)) => ImageFormat::from_name(ext).unwrap_or(ImageFormat::Yo),
+            None => ImageFormat::Yo,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoadErrorKind {
+    Malformed(&'static str),
+    InvalidHex(char),
+    OddNibbles,
+    OutOfRange { addr: usize, size: usize },
+    Overlap(usize),
+    Checksum { expected: u8, found: u8 },
+}
+
+impl Display for LoadErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadErrorKind::Malformed(why) => write!(f, "{}", why),
+            LoadErrorKind::InvalidHex(c) => write!(f, "invalid hex digit {:?}", c),
+            LoadErrorKind::OddNibbles => write!(f, "odd number of hex digits in byte encoding"),
+            LoadErrorKind::OutOfRange { addr, size } => write!(
+                f,
+                "address 0x{:04x} is beyond memory (size 0x{:x})",
+                addr, size
+            ),
+            LoadErrorKind::Overlap(addr) => {
+                write!(f, "byte at 0x{:04x} was already written", addr)
+            }
+            LoadErrorKind::Checksum { expected, found } => write!(
+                f,
+                "bad record checksum, expected {:02x} found {:02x}",
+                expected, found
+            ),
+        }
+    }
+}
+
+/// A problem in an object file, positioned by 1-based line and column.
+#[derive(Debug, PartialEq)]
+pub struct LoadError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: LoadErrorKind,
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.kind
+        )
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+pub(crate) struct ParsedLine<'a> {
+    pub(crate) addr: usize,
+    pub(crate) asm: &'a str,
+    // each byte along with the column its encoding starts at
+    pub(crate) bytes: Vec<(u8, usize)>,
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+// parses the `<ADDRESS>: <BYTE ENCODING>` part of a line, `None` for lines with no address
+pub(crate) fn parse_line(line: &str, line_no: usize) -> Result<Option<ParsedLine<'_>>, LoadError> {
+    let (code, asm) = match line.find('|') {
+        Some(i) => (&line[..i], &line[i + 1..]),
+        None => (line, ""),
+    };
+    let err = |column: usize, kind: LoadErrorKind| LoadError {
+        line: line_no,
+        column: column + 1,
+        kind,
+    };
+
+    let start = code.len() - code.trim_start().len();
+    if code[start..].trim_end().is_empty() {
+        return Ok(None);
+    }
+    if !code[start..].starts_with("0x") {
+        return Err(err(start, LoadErrorKind::Malformed("expected an address")));
+    }
+    let colon = match code.find(':') {
+        Some(i) => i,
+        None => return Err(err(code.len(), LoadErrorKind::Malformed("missing ':'"))),
+    };
+
+    let digits = start + 2;
+    if digits == colon {
+        return Err(err(colon, LoadErrorKind::Malformed("empty address")));
+    }
+    let mut addr: usize = 0;
+    for (i, c) in code.bytes().enumerate().take(colon).skip(digits) {
+        let nibble = match hex_value(c) {
+            Some(n) => n,
+            None => return Err(err(i, LoadErrorKind::InvalidHex(c as char))),
+        };
+        addr = match addr.checked_mul(16) {
+            Some(a) => a | nibble as usize,
+            None => return Err(err(digits, LoadErrorKind::Malformed("address too large"))),
+        };
+    }
+
+    let mut bytes = Vec::new();
+    let mut high: Option<(u8, usize)> = None;
+    for (i, c) in code.bytes().enumerate().skip(colon + 1) {
+        if c.is_ascii_whitespace() {
+            continue;
+        }
+        let nibble = match hex_value(c) {
+            Some(n) => n,
+            None => return Err(err(i, LoadErrorKind::InvalidHex(c as char))),
+        };
+        high = match high {
+            Some((h, column)) => {
+                bytes.push((h << 4 | nibble, column + 1));
+                None
+            }
+            None => Some((nibble, i)),
+        };
+    }
+    if let Some((_, column)) = high {
+        return Err(err(column, LoadErrorKind::OddNibbles));
+    }
+
+    Ok(Some(ParsedLine { addr, asm, bytes }))
+}
+
+impl Machine {
+    pub fn load(&mut self, file: String) -> Result<(), anyhow::Error> {
+        self.load_with(&file, LoadMode::Fail)?;
+        Ok(())
+    }
+
+    /// Loads a `.yo` listing. In `LoadMode::Warn` problems are collected and returned
+    /// instead, skipping whatever part of a line could not be loaded.
+    pub fn load_with(&mut self, file: &str, mode: LoadMode) -> Result<Vec<LoadError>, LoadError> {
+        let mut warnings = Vec::new();
+        let mut report = |e: LoadError| match mode {
+            LoadMode::Fail => Err(e),
+            LoadMode::Warn => {
+                warnings.push(e);
+                Ok(())
+            }
+        };
+        let mut written = vec![false; self.mem.len()];
+        // line numbers are this file's, so lines from an earlier load have to go
+        self.source = SourceMap::new();
+
+        for (i, line) in file.lines().enumerate() {
+            let line_no = i + 1;
+            self.source.push_line(match line.find('|') {
+                Some(i) => &line[i + 1..],
+                None => line,
+            });
+            let parsed = match parse_line(line, line_no) {
+                Ok(Some(parsed)) => parsed,
+                Ok(None) => continue,
+                Err(e) => {
+                    report(e)?;
+                    continue;
+                }
+            };
+            if let Some(name) = parse_label(parsed.asm) {
+                self.symbols.insert(name, parsed.addr);
+            }
+            if !parsed.bytes.is_empty() {
+                self.source.map(parsed.addr, line_no);
+            }
+
+            for (offset, (byte, column)) in parsed.bytes.into_iter().enumerate() {
+                let err = |kind| LoadError {
+                    line: line_no,
+                    column,
+                    kind,
+                };
+                let size = self.mem.len();
+                let addr = match parsed.addr.checked_add(offset) {
+                    Some(addr) if addr < size => addr,
+                    addr => {
+                        let addr = addr.unwrap_or(parsed.addr);
+                        report(err(LoadErrorKind::OutOfRange { addr, size }))?;
+                        break;
+                    }
+                };
+                if written[addr] {
+                    report(err(LoadErrorKind::Overlap(addr)))?;
+                }
+                written[addr] = true;
+                self.mem[addr] = byte;
+                self.mem_init[addr] = true;
+            }
+        }
+
+        Ok(warnings)
+    }
+
+    /// Copies a flat binary image into memory starting at `base`, and starts running it there.
+    pub fn load_binary(&mut self, image: &[u8], base: usize) -> Result<(), anyhow::Error> {
+        let range = base.checked_add(image.len()).map(|end| base..end);
+        let dest = match range.clone().and_then(|range| self.mem.get_mut(range)) {
+            Some(dest) => dest,
+            None => anyhow::bail!(
+                "image of 0x{:x} bytes at 0x{:04x} does not fit in memory (size 0x{:x})",
+                image.len(),
+                base,
+                self.mem.len()
+            ),
+        };
+        dest.copy_from_slice(image);
+        self.mark_mem(range.unwrap_or_default());
+        self.source = SourceMap::new();
+        self.pc = base;
+        Ok(())
+    }
+
+    /// Loads Intel HEX records. Data, EOF, extended segment/linear address and start
+    /// address records are understood; a start address sets the PC.
+    pub fn load_ihex(&mut self, file: &str, mode: LoadMode) -> Result<Vec<LoadError>, LoadError> {
+        let mut warnings = Vec::new();
+        let mut report = |e: LoadError| match mode {
+            LoadMode::Fail => Err(e),
+            LoadMode::Warn => {
+                warnings.push(e);
+                Ok(())
+            }
+        };
+        let mut written = vec![false; self.mem.len()];
+        let mut upper: usize = 0;
+        self.source = SourceMap::new();
+
+        for (i, line) in file.lines().enumerate() {
+            let line_no = i + 1;
+            let record = match parse_record(line, line_no) {
+                Ok(Some(record)) => record,
+                Ok(None) => continue,
+                Err(e) => {
+                    report(e)?;
+                    continue;
+                }
+            };
+            let err = |column, kind| LoadError {
+                line: line_no,
+                column,
+                kind,
+            };
+            let word = |data: &[u8]| data.iter().fold(0, |acc, &b| acc << 8 | b as usize);
+
+            match (record.kind, record.data.len()) {
+                (0x00, _) => {
+                    for (offset, &byte) in record.data.iter().enumerate() {
+                        // data starts at column 10, two digits per byte
+                        let column = 10 + offset * 2;
+                        let size = self.mem.len();
+                        let addr = match upper.checked_add(record.addr + offset) {
+                            Some(addr) if addr < size => addr,
+                            addr => {
+                                let addr = addr.unwrap_or(upper);
+                                report(err(column, LoadErrorKind::OutOfRange { addr, size }))?;
+                                break;
+                            }
+                        };
+                        if written[addr] {
+                            report(err(column, LoadErrorKind::Overlap(addr)))?;
+                        }
+                        written[addr] = true;
+                        self.mem[addr] = byte;
+                        self.mem_init[addr] = true;
+                    }
+                }
+                (0x01, 0) => break,
+                (0x02, 2) => upper = word(&record.data) << 4,
+                (0x04, 2) => upper = word(&record.data) << 16,
+                (0x03, 4) => self.pc = (word(&record.data[..2]) << 4) + word(&record.data[2..]),
+                (0x05, 4) => self.pc = word(&record.data),
+                (0x00..=0x05, _) => report(err(
+                    2,
+                    LoadErrorKind::Malformed("bad length for record type"),
+                ))?,
+                _ => report(err(8, LoadErrorKind::Malformed("unknown record type")))?,
+            }
+        }
+
+        Ok(warnings)
+    }
+}
+
+struct Record {
+    kind: u8,
+    addr: usize,
+    data: Vec<u8>,
+}
+
+fn parse_record(line: &str, line_no: usize) -> Result<Option<Record>, LoadError> {
+    let err = |column: usize, kind: LoadErrorKind| LoadError {
+        line: line_no,
+        column: column + 1,
+        kind,
+    };
+    let line = line.trim_end();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if !line.starts_with(':') {
+        return Err(err(
+            0,
+            LoadErrorKind::Malformed("expected ':' to start a record"),
+        ));
+    }
+
+    let digits = line.as_bytes();
+    if digits.len().is_multiple_of(2) {
+        return Err(err(digits.len() - 1, LoadErrorKind::OddNibbles));
+    }
+    let mut bytes = Vec::new();
+    for i in (1..digits.len()).step_by(2) {
+        let (high, low) = match (hex_value(digits[i]), hex_value(digits[i + 1])) {
+            (Some(high), Some(low)) => (high, low),
+            (None, _) => return Err(err(i, LoadErrorKind::InvalidHex(digits[i] as char))),
+            (_, None) => return Err(err(i + 1, LoadErrorKind::InvalidHex(digits[i + 1] as char))),
+        };
+        bytes.push(high << 4 | low);
+    }
+
+    if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+        return Err(err(
+            1,
+            LoadErrorKind::Malformed("record length does not match data"),
+        ));
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - 1);
+    let expected = body
+        .iter()
+        .fold(0u8, |acc, &b| acc.wrapping_add(b))
+        .wrapping_neg();
+    if expected != checksum[0] {
+        return Err(err(
+            line.len() - 2,
+            LoadErrorKind::Checksum {
+                expected,
+                found: checksum[0],
+            },
+        ));
+    }
+
+    Ok(Some(Record {
+        kind: body[3],
+        addr: (body[1] as usize) << 8 | body[2] as usize,
+        data: body[4..].to_vec(),
+    }))
+}
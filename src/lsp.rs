@@ -0,0 +1,439 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use crate::{
+    asm::{is_ident, mnemonic, register, split_labels, strip_comment, Form, MNEMONICS},
+    json::{parse_body, read_body, write_message, Json},
+    Assembler, Program, REG_NAMES,
+};
+
+const DIRECTIVES: [&str; 11] = [
+    ".pos", ".align", ".quad", ".long", ".word", ".byte", ".equ", ".set", ".include", ".macro",
+    ".endm",
+];
+
+// LSP CompletionItemKind and DiagnosticSeverity values
+const KIND_VARIABLE: usize = 6;
+const KIND_KEYWORD: usize = 14;
+const KIND_REFERENCE: usize = 18;
+const SEVERITY_ERROR: usize = 1;
+
+fn layout(form: Form) -> &'static str {
+    match form {
+        Form::Bare => "icode:ifun",
+        Form::RegReg => "icode:ifun rA:rB",
+        Form::ImmReg => "icode:ifun F:rB V",
+        Form::RegMem | Form::MemReg => "icode:ifun rA:rB D",
+        Form::Dest => "icode:ifun Dest",
+        Form::Reg => "icode:ifun rA:F",
+    }
+}
+
+fn uri_to_path(uri: &str) -> String {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut out = Vec::new();
+    let mut bytes = path.bytes();
+    while let Some(b) = bytes.next() {
+        let escaped: String = bytes.clone().take(2).map(char::from).collect();
+        match (b, u8::from_str_radix(&escaped, 16)) {
+            (b'%', Ok(decoded)) if escaped.len() == 2 => {
+                out.push(decoded);
+                bytes.nth(1);
+            }
+            _ => out.push(b),
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn path_to_uri(path: &str) -> String {
+    let path = match Path::new(path).canonicalize() {
+        Ok(abs) => abs.to_string_lossy().into_owned(),
+        Err(_) => path.to_string(),
+    };
+    format!("file://{}", path.replace('%', "%25").replace(' ', "%20"))
+}
+
+fn range(line: usize, start: usize, end: usize) -> Json {
+    let pos = |character: usize| {
+        Json::obj(vec![
+            ("line", Json::from(line)),
+            ("character", Json::from(character)),
+        ])
+    };
+    Json::obj(vec![("start", pos(start)), ("end", pos(end))])
+}
+
+// LSP positions count UTF-16 code units, while lines are walked here by char
+fn to_utf16(line: &str, chars: usize) -> usize {
+    line.chars().take(chars).map(char::len_utf16).sum()
+}
+
+fn from_utf16(line: &str, units: usize) -> usize {
+    let mut seen = 0;
+    line.chars()
+        .take_while(|c| {
+            seen += c.len_utf16();
+            seen <= units
+        })
+        .count()
+}
+
+fn line_of(text: &str, line: usize) -> &str {
+    text.lines().nth(line).unwrap_or_default()
+}
+
+// the identifier or register under the cursor and where it starts
+fn word_at(text: &str, line: usize, character: usize) -> Option<(String, usize)> {
+    let line = text.lines().nth(line)?;
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '%';
+    let chars: Vec<char> = line.chars().collect();
+    let at = character.min(chars.len());
+    let start = (0..at)
+        .rev()
+        .take_while(|&i| is_word(chars[i]))
+        .last()
+        .unwrap_or(at);
+    let end = (at..chars.len())
+        .find(|&i| !is_word(chars[i]))
+        .unwrap_or(chars.len());
+    if start == end {
+        return None;
+    }
+    Some((chars[start..end].iter().collect(), start))
+}
+
+// where `name` is defined as a label, `.equ` or `.set` in `text`: (line, UTF-16 column)
+fn definition_in(text: &str, name: &str) -> Option<(usize, usize)> {
+    let column = |line: &str, rest: &str| line[..line.len() - rest.len()].encode_utf16().count();
+    for (i, line) in text.lines().enumerate() {
+        let code = strip_comment(line);
+        // each label is all there is before its colon, apart from spaces
+        let mut rest = code;
+        while let Some(colon) = rest.find(':') {
+            let label = rest[..colon].trim();
+            if !is_ident(label) {
+                break;
+            }
+            if label == name {
+                return Some((i, column(line, rest.trim_start())));
+            }
+            rest = &rest[colon + 1..];
+        }
+        let rest = rest.trim_start();
+        for directive in [".equ", ".set"] {
+            if let Some(args) = rest.strip_prefix(directive) {
+                if args.split(',').next().map(str::trim) == Some(name) {
+                    return Some((i, column(line, args.trim_start())));
+                }
+            }
+        }
+    }
+    None
+}
+
+struct Document {
+    text: String,
+    program: Option<Program>,
+}
+
+/// A Language Server Protocol server for `.ys` files: diagnostics from the assembler,
+/// hover with the encoding of each instruction, go to definition for labels and constants
+/// and completion of mnemonics, registers, directives and labels.
+pub struct LanguageServer {
+    docs: HashMap<String, Document>,
+}
+
+impl Default for LanguageServer {
+    fn default() -> Self {
+        LanguageServer::new()
+    }
+}
+
+impl LanguageServer {
+    pub fn new() -> LanguageServer {
+        LanguageServer {
+            docs: HashMap::new(),
+        }
+    }
+
+    fn notify(&self, out: &mut dyn Write, method: &str, params: Json) -> std::io::Result<()> {
+        let msg = Json::obj(vec![
+            ("jsonrpc", Json::from("2.0")),
+            ("method", Json::from(method)),
+            ("params", params),
+        ]);
+        write_message(out, &msg)
+    }
+
+    // assembles the document, keeping the program for hovers and publishing any errors
+    fn update(&mut self, out: &mut dyn Write, uri: &str, text: String) -> std::io::Result<()> {
+        let path = uri_to_path(uri);
+        let result = Assembler::new().assemble(&path, &text);
+        let mut diagnostics = Vec::new();
+        let program = match result {
+            Ok(program) => Some(program),
+            Err(errors) => {
+                for e in errors {
+                    // errors inside included files are shown on the first line
+                    let (line, column, message) = match e.file == path {
+                        true => (e.line.saturating_sub(1), e.column, e.message),
+                        false => (0, 0, e.to_string()),
+                    };
+                    let l = line_of(&text, line);
+                    let start = to_utf16(l, column.saturating_sub(1));
+                    let end = l.encode_utf16().count();
+                    diagnostics.push(Json::obj(vec![
+                        ("range", range(line, start.min(end), end)),
+                        ("severity", Json::from(SEVERITY_ERROR)),
+                        ("source", Json::from("y86")),
+                        ("message", Json::from(message)),
+                    ]));
+                }
+                None
+            }
+        };
+        self.docs
+            .insert(uri.to_string(), Document { text, program });
+
+        let params = Json::obj(vec![
+            ("uri", Json::from(uri)),
+            ("diagnostics", Json::from(diagnostics)),
+        ]);
+        self.notify(out, "textDocument/publishDiagnostics", params)
+    }
+
+    fn hover(&self, doc: &Document, line: usize, character: usize) -> Json {
+        let (word, start) = match word_at(&doc.text, line, character) {
+            Some(found) => found,
+            None => return Json::Null,
+        };
+        let mut parts = Vec::new();
+        if let Some((code, form)) = mnemonic(&word) {
+            parts.push(format!(
+                "`{}`: icode:ifun {:x}:{:x}, {} byte(s), encoded as `{}`",
+                word,
+                code >> 4,
+                code & 0xf,
+                form.size(),
+                layout(form)
+            ));
+            // the bytes this line actually assembled to
+            let emitted = doc.program.iter().flat_map(|p| &p.lines).find(|l| {
+                l.file == 0 && l.line == line + 1 && l.addr.is_some() && !l.bytes.is_empty()
+            });
+            if let Some(l) = emitted {
+                let hex: Vec<String> = l.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                parts.push(format!("at 0x{:04x}: `{}`", l.addr.unwrap(), hex.join(" ")));
+            }
+        } else if word.starts_with('%') {
+            match register(&word) {
+                Ok(id) => parts.push(format!("`{}`: register {:x}", word, id)),
+                Err(e) => parts.push(e),
+            }
+        } else if let Some(program) = &doc.program {
+            if let Some((_, addr)) = program.symbols.iter().find(|(name, _)| *name == word) {
+                parts.push(format!("`{}` = 0x{:04x}", word, addr));
+            }
+        }
+        if parts.is_empty() {
+            return Json::Null;
+        }
+        Json::obj(vec![
+            (
+                "contents",
+                Json::obj(vec![
+                    ("kind", Json::from("markdown")),
+                    ("value", Json::from(parts.join("\n\n"))),
+                ]),
+            ),
+            ("range", {
+                let l = line_of(&doc.text, line);
+                range(
+                    line,
+                    to_utf16(l, start),
+                    to_utf16(l, start + word.chars().count()),
+                )
+            }),
+        ])
+    }
+
+    fn definition(&self, uri: &str, doc: &Document, line: usize, character: usize) -> Json {
+        let word = match word_at(&doc.text, line, character) {
+            Some((word, _)) => word,
+            None => return Json::Null,
+        };
+        let location = |uri: String, (line, col): (usize, usize)| {
+            Json::obj(vec![
+                ("uri", Json::from(uri)),
+                ("range", range(line, col, col + word.encode_utf16().count())),
+            ])
+        };
+        if let Some(at) = definition_in(&doc.text, &word) {
+            return location(uri.to_string(), at);
+        }
+        // included files; bundled runtime files have no path to jump to
+        for (name, text) in doc.program.iter().flat_map(|p| p.files.iter().skip(1)) {
+            if let Some(at) = definition_in(text, &word) {
+                if Path::new(name).exists() {
+                    return location(path_to_uri(name), at);
+                }
+            }
+        }
+        Json::Null
+    }
+
+    fn completion(&self, doc: &Document, line: usize, character: usize) -> Json {
+        let prefix = word_at(&doc.text, line, character).map(|(w, _)| w);
+        let item = |label: &str, kind: usize, detail: String| {
+            Json::obj(vec![
+                ("label", Json::from(label)),
+                ("kind", Json::from(kind)),
+                ("detail", Json::from(detail)),
+            ])
+        };
+
+        let mut items = Vec::new();
+        for name in REG_NAMES {
+            items.push(item(name, KIND_VARIABLE, "register".to_string()));
+        }
+        if !prefix.as_deref().is_some_and(|p| p.starts_with('%')) {
+            for (name, _, form) in MNEMONICS {
+                items.push(item(name, KIND_KEYWORD, format!("{} byte(s)", form.size())));
+            }
+            for name in DIRECTIVES {
+                items.push(item(name, KIND_KEYWORD, "directive".to_string()));
+            }
+            for l in doc.text.lines() {
+                for label in split_labels(strip_comment(l)).0 {
+                    items.push(item(label, KIND_REFERENCE, "label".to_string()));
+                }
+            }
+        }
+        Json::from(items)
+    }
+
+    fn position(params: &Json) -> (String, usize, usize) {
+        let uri = params
+            .get("textDocument")
+            .get("uri")
+            .as_str()
+            .unwrap_or_default();
+        let pos = params.get("position");
+        let line = pos.get("line").as_i64().unwrap_or(0) as usize;
+        let character = pos.get("character").as_i64().unwrap_or(0) as usize;
+        (uri.to_string(), line, character)
+    }
+
+    /// Serves requests until the client sends `exit`.
+    pub fn serve(
+        &mut self,
+        input: &mut dyn BufRead,
+        out: &mut dyn Write,
+    ) -> Result<(), anyhow::Error> {
+        while let Some(body) = read_body(input)? {
+            let msg = match parse_body(body) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    eprintln!("y86-lsp: ignoring malformed message: {}", e);
+                    continue;
+                }
+            };
+            let method = msg.get("method").as_str().unwrap_or_default().to_string();
+            let params = msg.get("params");
+            let result = match method.as_str() {
+                "initialize" => Json::obj(vec![
+                    (
+                        "capabilities",
+                        Json::obj(vec![
+                            ("textDocumentSync", Json::from(1usize)),
+                            ("hoverProvider", Json::from(true)),
+                            ("definitionProvider", Json::from(true)),
+                            (
+                                "completionProvider",
+                                Json::obj(vec![(
+                                    "triggerCharacters",
+                                    Json::from(vec![Json::from("%"), Json::from(".")]),
+                                )]),
+                            ),
+                        ]),
+                    ),
+                    (
+                        "serverInfo",
+                        Json::obj(vec![("name", Json::from("y86-lsp"))]),
+                    ),
+                ]),
+                "textDocument/didOpen" => {
+                    let doc = params.get("textDocument");
+                    let uri = doc.get("uri").as_str().unwrap_or_default().to_string();
+                    let text = doc.get("text").as_str().unwrap_or_default().to_string();
+                    self.update(out, &uri, text)?;
+                    continue;
+                }
+                "textDocument/didChange" => {
+                    let uri = params.get("textDocument").get("uri").as_str();
+                    let uri = uri.unwrap_or_default().to_string();
+                    // full sync, so the last change holds the whole text
+                    let changes = params.get("contentChanges").as_array();
+                    if let Some(text) = changes.last().and_then(|c| c.get("text").as_str()) {
+                        self.update(out, &uri, text.to_string())?;
+                    }
+                    continue;
+                }
+                "textDocument/didClose" => {
+                    let uri = params.get("textDocument").get("uri").as_str();
+                    self.docs.remove(uri.unwrap_or_default());
+                    continue;
+                }
+                "textDocument/hover" | "textDocument/definition" | "textDocument/completion" => {
+                    let (uri, line, character) = LanguageServer::position(params);
+                    match self.docs.get(&uri) {
+                        Some(doc) => {
+                            let character = from_utf16(line_of(&doc.text, line), character);
+                            match method.as_str() {
+                                "textDocument/hover" => self.hover(doc, line, character),
+                                "textDocument/definition" => {
+                                    self.definition(&uri, doc, line, character)
+                                }
+                                _ => self.completion(doc, line, character),
+                            }
+                        }
+                        None => Json::Null,
+                    }
+                }
+                "shutdown" => Json::Null,
+                "exit" => return Ok(()),
+                _ => {
+                    // notifications we don't handle need no reply
+                    if msg.get("id") == &Json::Null {
+                        continue;
+                    }
+                    let error = Json::obj(vec![
+                        ("code", Json::from(-32601i64)),
+                        (
+                            "message",
+                            Json::from(format!("unsupported method `{}`", method)),
+                        ),
+                    ]);
+                    let reply = Json::obj(vec![
+                        ("jsonrpc", Json::from("2.0")),
+                        ("id", msg.get("id").clone()),
+                        ("error", error),
+                    ]);
+                    write_message(out, &reply)?;
+                    continue;
+                }
+            };
+            let reply = Json::obj(vec![
+                ("jsonrpc", Json::from("2.0")),
+                ("id", msg.get("id").clone()),
+                ("result", result),
+            ]);
+            write_message(out, &reply)?;
+        }
+        Ok(())
+    }
+}
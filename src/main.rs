@@ -1,36 +1,360 @@
-use std::{env, fs};
-use y86_rs::{Machine, StepMode};
+use std::{
+    env, fs,
+    io::{self, Write},
+    net::TcpListener,
+    ops::{Range, RangeInclusive},
+};
+use y86_rs::{
+    predictor, Assembler, BranchStats, CacheConfig, CallCheck, Console, Coverage, DapServer,
+    DecodeMode, DumpFormat, GdbStub, Hazards, ImageFormat, Linker, LoadMode, Machine, MemView,
+    Object, Pipeline, Profile, StepMode,
+};
 
 const MEM_MAX: usize = 1 << 13;
+const GARBAGE_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
 
-fn parse_args() -> (String, StepMode) {
-    let mut args: Vec<String> = env::args().skip(1).collect();
-    if args.is_empty() {
-        todo!("handle arg error");
+struct Args {
+    files: Vec<String>,
+    step_mode: StepMode,
+    decode_mode: DecodeMode,
+    call_check: CallCheck,
+    check_uninit: bool,
+    garbage: bool,
+    icache: Option<CacheConfig>,
+    dcache: Option<CacheConfig>,
+    cache_trace: Option<String>,
+    predictors: Vec<String>,
+    pipelines: Vec<Hazards>,
+    diagram: Option<Hazards>,
+    diagram_cycles: RangeInclusive<usize>,
+    load_mode: LoadMode,
+    format: Option<ImageFormat>,
+    base: usize,
+    emit: Option<String>,
+    dump: Option<String>,
+    dump_format: Option<DumpFormat>,
+    dump_range: Option<Range<usize>>,
+    views: Vec<String>,
+    gdb: Option<String>,
+    dap: bool,
+    profile: Option<usize>,
+    coverage: Option<String>,
+}
+
+fn parse_num(s: &str) -> Result<usize, anyhow::Error> {
+    Ok(match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16)?,
+        None => s.parse()?,
+    })
+}
+
+fn parse_args() -> Result<Args, anyhow::Error> {
+    let mut args = Args {
+        files: Vec::new(),
+        step_mode: StepMode::NoStep,
+        decode_mode: DecodeMode::Lenient,
+        call_check: CallCheck::Off,
+        check_uninit: false,
+        garbage: false,
+        icache: None,
+        dcache: None,
+        cache_trace: None,
+        predictors: Vec::new(),
+        pipelines: Vec::new(),
+        diagram: None,
+        diagram_cycles: 1..=50,
+        load_mode: LoadMode::Fail,
+        format: None,
+        base: 0,
+        emit: None,
+        dump: None,
+        dump_format: None,
+        dump_range: None,
+        views: Vec::new(),
+        gdb: None,
+        dap: false,
+        profile: None,
+        coverage: None,
+    };
+
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        let mut value = || match argv.next() {
+            Some(value) => Ok(value),
+            None => Err(anyhow::anyhow!("{} expects a value", arg)),
+        };
+        match arg.as_str() {
+            "-c" => args.step_mode = StepMode::Cycle,
+            "-s" => args.step_mode = StepMode::Stage,
+            "-d" => args.step_mode = StepMode::Debug,
+            "-t" => args.step_mode = StepMode::Tui,
+            "--strict" => args.decode_mode = DecodeMode::Strict,
+            "--load-warn" => args.load_mode = LoadMode::Warn,
+            "--check-calls" => args.call_check = CallCheck::Warn,
+            "--check-calls-stop" => args.call_check = CallCheck::Stop,
+            "--check-uninit" => args.check_uninit = true,
+            "--garbage" => args.garbage = true,
+            "--icache" | "--dcache" => {
+                let spec = value()?;
+                let config = CacheConfig::parse(&spec)
+                    .map_err(|e| anyhow::anyhow!("{} {}: {}", arg, spec, e))?;
+                match arg.as_str() {
+                    "--icache" => args.icache = Some(config),
+                    _ => args.dcache = Some(config),
+                }
+            }
+            "--cache-trace" => args.cache_trace = Some(value()?),
+            "--predict" => {
+                let name = value()?;
+                if predictor(&name).is_none() {
+                    anyhow::bail!(
+                        "unknown predictor {:?}, expected taken, not-taken, btfnt, 1bit or 2bit, optionally with +ras",
+                        name
+                    );
+                }
+                args.predictors.push(name);
+            }
+            "--pipeline" | "--diagram" => {
+                let name = value()?;
+                let hazards = match Hazards::from_name(&name) {
+                    Some(hazards) => hazards,
+                    None => anyhow::bail!(
+                        "unknown pipeline {:?}, expected stall, pipe-, pipe or load-forward",
+                        name
+                    ),
+                };
+                match arg.as_str() {
+                    "--pipeline" => args.pipelines.push(hazards),
+                    _ => args.diagram = Some(hazards),
+                }
+            }
+            "--diagram-cycles" => {
+                let range = value()?;
+                args.diagram_cycles = match range.split_once(':') {
+                    Some((first, last)) => parse_num(first)?..=parse_num(last)?,
+                    None => {
+                        anyhow::bail!("--diagram-cycles expects <first>:<last>, got {:?}", range)
+                    }
+                };
+            }
+            "--format" => {
+                let name = value()?;
+                args.format = match ImageFormat::from_name(&name) {
+                    Some(format) => Some(format),
+                    None => {
+                        anyhow::bail!(
+                            "unknown format {:?}, expected yo, ys, bin, hex or yro",
+                            name
+                        )
+                    }
+                };
+            }
+            "--base" => args.base = parse_num(&value()?)?,
+            "--emit" => args.emit = Some(value()?),
+            "--dap" => args.dap = true,
+            "--coverage" => args.coverage = Some(value()?),
+            "--profile" => args.profile = Some(args.profile.unwrap_or(10)),
+            "--profile-top" => args.profile = Some(parse_num(&value()?)?),
+            "--gdb" => args.gdb = Some(value()?),
+            "--mem" => args.views.push(value()?),
+            "--dump" => args.dump = Some(value()?),
+            "--dump-format" => {
+                let name = value()?;
+                args.dump_format = match DumpFormat::from_name(&name) {
+                    Some(format) => Some(format),
+                    None => {
+                        anyhow::bail!(
+                            "unknown dump format {:?}, expected yo, bin or hexdump",
+                            name
+                        )
+                    }
+                };
+            }
+            "--dump-range" => {
+                let range = value()?;
+                args.dump_range = match range.split_once(':') {
+                    Some((start, end)) => Some(parse_num(start)?..parse_num(end)?),
+                    None => anyhow::bail!("--dump-range expects <start>:<end>, got {:?}", range),
+                };
+            }
+            _ if arg.starts_with('-') => anyhow::bail!("unknown option {}", arg),
+            _ => args.files.push(arg),
+        }
     }
-    let file = args.remove(0);
 
-    let step_mode = if env::args().any(|e| e == "-c") {
-        StepMode::Cycle
-    } else if env::args().any(|e| e == "-s") {
-        StepMode::Stage
-    } else if env::args().any(|e| e == "-d") {
-        StepMode::Debug
-    } else {
-        StepMode::NoStep
+    let traced = args.profile.is_some()
+        || args.coverage.is_some()
+        || !args.predictors.is_empty()
+        || !args.pipelines.is_empty()
+        || args.diagram.is_some();
+    if traced && args.step_mode != StepMode::NoStep {
+        anyhow::bail!(
+            "--profile, --coverage, --predict, --pipeline and --diagram cannot be combined with the stepping modes"
+        );
+    }
+
+    if args.files.is_empty() && !args.dap {
+        anyhow::bail!("usage: y86-rs [options] <file.yo|file.ys|file.yro...|file.bin|file.hex>");
+    }
+
+    Ok(args)
+}
+
+fn load(machine: &mut Machine, args: &Args) -> Result<(), anyhow::Error> {
+    let file = &args.files[0];
+    let format = match &args.format {
+        Some(format) => format,
+        None => &ImageFormat::from_path(file),
     };
 
-    (file, step_mode)
+    let warnings = match format {
+        ImageFormat::Yo => machine.load_with(&fs::read_to_string(file)?, args.load_mode),
+        ImageFormat::IntelHex => machine.load_ihex(&fs::read_to_string(file)?, args.load_mode),
+        ImageFormat::Binary => {
+            machine.load_binary(&fs::read(file)?, args.base)?;
+            Ok(Vec::new())
+        }
+        ImageFormat::Object => {
+            let mut linker = Linker::new();
+            for file in &args.files {
+                let object = Object::parse(file, &fs::read_to_string(file)?)
+                    .map_err(|e| anyhow::anyhow!("{}: {}", file, e))?;
+                linker.add(object);
+            }
+            let image = linker.link(args.base)?;
+            if let Some(out) = &args.emit {
+                fs::write(out, image.to_listing())?;
+            }
+            machine.load_image(&image)?;
+            Ok(Vec::new())
+        }
+        ImageFormat::Source => {
+            let program = Assembler::new().assemble_file(file).map_err(|errors| {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                anyhow::anyhow!("{}", errors.join("\n"))
+            })?;
+            if let Some(out) = &args.emit {
+                fs::write(out, program.to_listing())?;
+            }
+            machine.load_program(&program)?;
+            Ok(Vec::new())
+        }
+    }
+    .map_err(|e| anyhow::anyhow!("{}: {}", file, e))?;
+
+    for warning in warnings {
+        eprintln!("warning: {}: {}", file, warning);
+    }
+
+    Ok(())
 }
 
 fn main() -> Result<(), anyhow::Error> {
-    let (infile, mode) = parse_args();
-    let infile = fs::read_to_string(infile)?;
-    let mut machine = Machine::new(MEM_MAX, mode);
-    machine.load(infile)?;
-    match machine.run() {
-        Ok(_) =>  print!("{machine}"),
-        Err(e) => return Err(e),
+    let args = parse_args()?;
+    if args.dap {
+        return DapServer::new(MEM_MAX).serve(&mut io::stdin().lock(), &mut io::stdout());
+    }
+    // with the gdb protocol on stdout, program output goes to stderr instead
+    let console = match args.gdb.as_deref() {
+        Some("stdio") => Console::new(Box::new(io::stderr())),
+        _ => Console::stdout(),
+    };
+    let mut machine = Machine::with_extensions(MEM_MAX, args.step_mode, vec![Box::new(console)]);
+    machine.set_decode_mode(args.decode_mode);
+    machine.set_call_check(args.call_check);
+    machine.set_check_uninit(args.check_uninit);
+    if args.garbage {
+        machine.fill_garbage(GARBAGE_SEED);
+    }
+    machine.set_caches(args.icache.clone(), args.dcache.clone());
+    if let Some(path) = &args.cache_trace {
+        machine.set_cache_trace(Box::new(io::BufWriter::new(fs::File::create(path)?)));
+    }
+    load(&mut machine, &args)?;
+
+    match args.gdb.as_deref() {
+        Some("stdio") => {
+            return GdbStub::new(&mut machine).serve(io::stdin(), &mut io::stdout());
+        }
+        Some(port) => {
+            let listener = TcpListener::bind(("127.0.0.1", port.parse::<u16>()?))?;
+            eprintln!("waiting for gdb on {}", listener.local_addr()?);
+            let (mut stream, _) = listener.accept()?;
+            let input = stream.try_clone()?;
+            GdbStub::new(&mut machine).serve(input, &mut stream)?;
+            stream.flush()?;
+            return Ok(());
+        }
+        None => (),
+    }
+
+    let mut profile = args.profile.map(|_| Profile::new(&machine));
+    let mut coverage = args.coverage.as_ref().map(|_| Coverage::new());
+    let mut branches: Vec<BranchStats> = args
+        .predictors
+        .iter()
+        .filter_map(|name| predictor(name))
+        .map(BranchStats::new)
+        .collect();
+    let mut pipelines: Vec<Pipeline> = args.pipelines.iter().map(|&h| Pipeline::new(h)).collect();
+    let mut diagram = args.diagram.map(Pipeline::new);
+    if profile.is_some()
+        || coverage.is_some()
+        || !branches.is_empty()
+        || !pipelines.is_empty()
+        || diagram.is_some()
+    {
+        machine.run_with(|machine, pc, state| {
+            for stats in &mut branches {
+                stats.record(pc, state);
+            }
+            for pipeline in pipelines.iter_mut().chain(&mut diagram) {
+                pipeline.record(machine, pc, state);
+            }
+            if let Some(profile) = &mut profile {
+                profile.record(machine, pc, state);
+            }
+            if let Some(coverage) = &mut coverage {
+                coverage.record(pc, state);
+            }
+        })?;
+    } else {
+        machine.run()?;
+    }
+    print!("{machine}");
+    for stats in &branches {
+        print!("\n{}", stats.report(&machine));
+    }
+    for pipeline in &pipelines {
+        print!("\n{}", pipeline.report());
+    }
+    if let Some(diagram) = &diagram {
+        print!(
+            "\n{}{}",
+            diagram.report(),
+            diagram.diagram(args.diagram_cycles.clone())
+        );
+    }
+    if args.icache.is_some() || args.dcache.is_some() {
+        print!("\n{}", machine.cache_report());
+    }
+    if let (Some(profile), Some(top)) = (&profile, args.profile) {
+        print!("\n{}", machine.profile_report(profile, top));
+    }
+    if let (Some(coverage), Some(out)) = (&coverage, &args.coverage) {
+        print!("\n{}", coverage.report(&machine));
+        fs::write(out, coverage.to_lcov(&machine, &args.files[0]))?;
+    }
+    for spec in &args.views {
+        let view = MemView::parse(spec, machine.symbols())
+            .map_err(|e| anyhow::anyhow!("--mem {}: {}", spec, e))?;
+        print!("\n{}", machine.view_mem(&view)?);
+    }
+
+    if let Some(out) = &args.dump {
+        let format = args.dump_format.unwrap_or(DumpFormat::from_path(out));
+        let range = args.dump_range.clone().unwrap_or(0..machine.mem_size());
+        fs::write(out, machine.dump(range, format)?)?;
     }
     Ok(())
 }
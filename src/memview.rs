@@ -0,0 +1,174 @@
+use std::ops::Range;
+
+use crate::{asm::expr, Machine, SymbolTable};
+
+const LINE_BYTES: usize = 16;
+const DEFAULT_LEN: usize = 64;
+
+/// Size of each value in a memory view, named after the assembler data directives.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Unit {
+    Byte,
+    Word,
+    Long,
+    Quad,
+}
+
+/// How little endian values are shown.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Radix {
+    Hex,
+    Signed,
+    Unsigned,
+}
+
+/// A formatted window onto memory.
+///
+/// Written as `<start>[:<end>][,<options>]`, where start and end are constant expressions
+/// that may use labels (`src`, `stack-64:stack`) and options are any of `b`/`w`/`l`/`q` for
+/// the unit, `x`/`d`/`u` for hex, signed or unsigned values and `a` for an ASCII column.
+/// Without an end, 64 bytes are shown.
+#[derive(Clone)]
+pub struct MemView {
+    pub range: Range<usize>,
+    pub unit: Unit,
+    pub radix: Radix,
+    pub ascii: bool,
+}
+
+impl Unit {
+    pub fn size(&self) -> usize {
+        match self {
+            Unit::Byte => 1,
+            Unit::Word => 2,
+            Unit::Long => 4,
+            Unit::Quad => 8,
+        }
+    }
+}
+
+pub(crate) fn address(text: &str, symbols: &SymbolTable) -> Result<usize, String> {
+    let val = expr::eval(text, &|name| symbols.lookup(name).map(|addr| addr as i64))?;
+    if val < 0 {
+        return Err(format!("`{}` is a negative address", text.trim()));
+    }
+    Ok(val as usize)
+}
+
+impl MemView {
+    pub fn new(range: Range<usize>) -> MemView {
+        MemView {
+            range,
+            unit: Unit::Quad,
+            radix: Radix::Hex,
+            ascii: false,
+        }
+    }
+
+    pub fn parse(spec: &str, symbols: &SymbolTable) -> Result<MemView, String> {
+        let (range, options) = match spec.split_once(',') {
+            Some((range, options)) => (range, options.trim()),
+            None => (spec, ""),
+        };
+        let range = match range.split_once(':') {
+            Some((start, end)) => address(start, symbols)?..address(end, symbols)?,
+            None => {
+                let start = address(range, symbols)?;
+                start..start.saturating_add(DEFAULT_LEN)
+            }
+        };
+
+        let mut view = MemView::new(range);
+        for opt in options.chars() {
+            match opt {
+                'b' => view.unit = Unit::Byte,
+                'w' => view.unit = Unit::Word,
+                'l' => view.unit = Unit::Long,
+                'q' => view.unit = Unit::Quad,
+                'x' => view.radix = Radix::Hex,
+                'd' => view.radix = Radix::Signed,
+                'u' => view.radix = Radix::Unsigned,
+                'a' => view.ascii = true,
+                _ => {
+                    return Err(format!(
+                        "unknown view option {:?}, expected b w l q x d u a",
+                        opt
+                    ))
+                }
+            }
+        }
+        Ok(view)
+    }
+
+    fn format_value(&self, bytes: &[u8]) -> String {
+        let mut raw = [0; 8];
+        raw[..bytes.len()].copy_from_slice(bytes);
+        let val = u64::from_le_bytes(raw);
+        let bits = bytes.len() * 8;
+        match self.radix {
+            Radix::Hex => format!("{:0width$x}", val, width = bytes.len() * 2),
+            Radix::Unsigned => format!("{:>width$}", val, width = unsigned_width(bits)),
+            Radix::Signed => {
+                let shift = 64 - bits;
+                let val = ((val << shift) as i64) >> shift;
+                format!("{:>width$}", val, width = unsigned_width(bits) + 1)
+            }
+        }
+    }
+}
+
+fn unsigned_width(bits: usize) -> usize {
+    match bits {
+        8 => 3,
+        16 => 5,
+        32 => 10,
+        _ => 20,
+    }
+}
+
+impl Machine {
+    pub fn view_mem(&self, view: &MemView) -> Result<String, anyhow::Error> {
+        let range = &view.range;
+        let bytes = match self.mem.get(range.clone()) {
+            Some(bytes) if !range.is_empty() => bytes,
+            _ => anyhow::bail!(
+                "range 0x{:04x}..0x{:04x} is empty or outside memory (size 0x{:x})",
+                range.start,
+                range.end,
+                self.mem.len()
+            ),
+        };
+        let size = view.unit.size();
+        if bytes.len() % size != 0 {
+            anyhow::bail!(
+                "range of 0x{:x} bytes is not a whole number of {} byte values",
+                bytes.len(),
+                size
+            );
+        }
+
+        let mut out = String::new();
+        for (i, line) in bytes.chunks(LINE_BYTES).enumerate() {
+            let addr = range.start + i * LINE_BYTES;
+            if let Some(name) = self.symbols.name_at(addr) {
+                out.push_str(&format!("<{}>:\n", name));
+            }
+            let values: Vec<String> = line.chunks(size).map(|v| view.format_value(v)).collect();
+            out.push_str(&format!("0x{:04x}: {}", addr, values.join(" ")));
+            if view.ascii {
+                let full = LINE_BYTES / size * (values[0].len() + 1) - 1;
+                let pad = full - (values.join(" ").len());
+                let ascii: String = line
+                    .iter()
+                    .map(|&b| match b {
+                        0x20..=0x7e => b as char,
+                        _ => '.',
+                    })
+                    .collect();
+                out.push_str(&format!("{:pad$}  |{}|", "", ascii, pad = pad));
+            }
+            out.push('\n');
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{
+    loader::{parse_line, LoadError, LoadErrorKind},
+    symbols::parse_label,
+};
+
+// far more than any machine's memory, but small enough to allocate
+const MAX_SECTION: usize = 1 << 24;
+
+/// An 8 byte absolute address patched in at link time, as used by the immediates of
+/// `irmovq`, `call`, `jXX`, `rmmovq`/`mrmovq` displacements and `.quad`.
+pub struct Reloc {
+    pub offset: usize,
+    pub symbol: String,
+    pub addend: isize,
+    line: usize,
+    column: usize,
+}
+
+pub struct Section {
+    pub name: String,
+    pub bytes: Vec<u8>,
+    pub labels: Vec<(String, usize)>,
+    pub relocs: Vec<Reloc>,
+    // listing lines as (offset, byte count, assembly text)
+    pub(crate) lines: Vec<(Option<usize>, usize, String)>,
+}
+
+impl Section {
+    fn new(name: &str) -> Section {
+        Section {
+            name: name.to_string(),
+            bytes: Vec::new(),
+            labels: Vec::new(),
+            relocs: Vec::new(),
+            lines: Vec::new(),
+        }
+    }
+}
+
+/// A relocatable object (`.yro`): a `.yo` listing whose addresses are offsets into the
+/// current section, plus directive lines
+///
+/// ```text
+/// .section <name>            start (or continue) a section, `text` if none is given
+/// .global <label>            export a label to other objects
+/// .extern <name>             import a label from another object
+/// .reloc <offset> <sym>[+n]  patch the quad at <offset> with the address of <sym> + n
+/// ```
+pub struct Object {
+    pub name: String,
+    pub sections: Vec<Section>,
+    pub globals: Vec<String>,
+    pub externs: Vec<String>,
+}
+
+fn parse_offset(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+// `sym`, `sym+8`, `sym-0x10`
+fn parse_reloc_target(s: &str) -> Option<(String, isize)> {
+    let split = s.find(['+', '-']).unwrap_or(s.len());
+    let (symbol, addend) = s.split_at(split);
+    let addend = match addend.chars().next() {
+        Some('+') => parse_offset(&addend[1..])? as isize,
+        Some('-') => -(parse_offset(&addend[1..])? as isize),
+        _ => 0,
+    };
+    if symbol.is_empty() {
+        return None;
+    }
+    Some((symbol.to_string(), addend))
+}
+
+impl Object {
+    pub fn parse(name: &str, file: &str) -> Result<Object, LoadError> {
+        let mut obj = Object {
+            name: name.to_string(),
+            sections: Vec::new(),
+            globals: Vec::new(),
+            externs: Vec::new(),
+        };
+        let mut current = 0;
+
+        for (i, line) in file.lines().enumerate() {
+            let line_no = i + 1;
+            let err = |why| LoadError {
+                line: line_no,
+                column: 1,
+                kind: LoadErrorKind::Malformed(why),
+            };
+
+            if line.starts_with('.') {
+                let mut words = line.split_whitespace();
+                match (words.next(), words.next(), words.next()) {
+                    (Some(".section"), Some(sec), None) => {
+                        current = match obj.sections.iter().position(|s| s.name == sec) {
+                            Some(idx) => idx,
+                            None => {
+                                obj.sections.push(Section::new(sec));
+                                obj.sections.len() - 1
+                            }
+                        };
+                    }
+                    (Some(".global"), Some(sym), None) => obj.globals.push(sym.to_string()),
+                    (Some(".extern"), Some(sym), None) => obj.externs.push(sym.to_string()),
+                    (Some(".reloc"), Some(offset), Some(target)) => {
+                        let offset = parse_offset(offset).ok_or(err("bad reloc offset"))?;
+                        let (symbol, addend) =
+                            parse_reloc_target(target).ok_or(err("bad reloc target"))?;
+                        if obj.sections.is_empty() {
+                            obj.sections.push(Section::new("text"));
+                        }
+                        obj.sections[current].relocs.push(Reloc {
+                            offset,
+                            symbol,
+                            addend,
+                            line: line_no,
+                            // where the offset starts, just past `.reloc`
+                            column: line.len() - line[".reloc".len()..].trim_start().len() + 1,
+                        });
+                    }
+                    _ => return Err(err("unknown directive")),
+                }
+                continue;
+            }
+
+            if obj.sections.is_empty() {
+                obj.sections.push(Section::new("text"));
+            }
+            let section = &mut obj.sections[current];
+            let text = match line.find('|') {
+                Some(i) => &line[i + 1..],
+                None => "",
+            };
+            let parsed = match parse_line(line, line_no)? {
+                Some(parsed) => parsed,
+                None => {
+                    section.lines.push((None, 0, text.to_string()));
+                    continue;
+                }
+            };
+
+            if let Some(label) = parse_label(parsed.asm) {
+                section.labels.push((label.to_string(), parsed.addr));
+            }
+            // label-only lines still reserve space, e.g. a stack placed with `.pos`
+            let end = match parsed.addr.checked_add(parsed.bytes.len()) {
+                Some(end) if end <= MAX_SECTION => end,
+                _ => {
+                    return Err(LoadError {
+                        line: line_no,
+                        column: line.len() - line.trim_start().len() + 1,
+                        kind: LoadErrorKind::OutOfRange {
+                            addr: parsed.addr,
+                            size: MAX_SECTION,
+                        },
+                    })
+                }
+            };
+            if section.bytes.len() < end {
+                section.bytes.resize(end, 0);
+            }
+            for (offset, (byte, _)) in parsed.bytes.iter().enumerate() {
+                section.bytes[parsed.addr + offset] = *byte;
+            }
+            section
+                .lines
+                .push((Some(parsed.addr), parsed.bytes.len(), text.to_string()));
+        }
+
+        for section in &obj.sections {
+            // external references have to be declared, so a misspelled name is caught here
+            if let Some(reloc) = section
+                .relocs
+                .iter()
+                .find(|r| obj.label(&r.symbol).is_none() && !obj.externs.contains(&r.symbol))
+            {
+                return Err(LoadError {
+                    line: reloc.line,
+                    column: 1,
+                    kind: LoadErrorKind::Malformed(
+                        "relocation against a symbol that is neither defined nor declared .extern",
+                    ),
+                });
+            }
+            if let Some(reloc) = section.relocs.iter().find(|r| {
+                r.offset
+                    .checked_add(8)
+                    .is_none_or(|end| end > section.bytes.len())
+            }) {
+                return Err(LoadError {
+                    line: reloc.line,
+                    column: reloc.column,
+                    kind: LoadErrorKind::OutOfRange {
+                        addr: reloc.offset,
+                        size: section.bytes.len(),
+                    },
+                });
+            }
+        }
+
+        Ok(obj)
+    }
+
+    pub fn label(&self, name: &str) -> Option<(usize, usize)> {
+        self.sections.iter().enumerate().find_map(|(idx, sec)| {
+            sec.labels
+                .iter()
+                .find(|(label, _)| label == name)
+                .map(|&(_, offset)| (idx, offset))
+        })
+    }
+}
@@ -0,0 +1,385 @@
+use std::{fmt::Display, ops::RangeInclusive};
+
+use crate::{CycleState, FunCode, Machine, OpCode, RNONE, RSP};
+
+/// How a five stage pipeline deals with data and control hazards.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Hazards {
+    /// No forwarding and no branch prediction: fetch waits for every conditional jump.
+    Stall,
+    /// The textbook's PIPE-: no forwarding, conditional jumps predicted taken.
+    PipeMinus,
+    /// PIPE: forwarding from execute, memory and write-back, predicted taken jumps.
+    Forward,
+    /// PIPE with load forwarding, which hands a loaded value straight to a following
+    /// `rmmovq` or `pushq` that only stores it.
+    LoadForward,
+}
+
+impl Hazards {
+    pub fn from_name(name: &str) -> Option<Hazards> {
+        match name {
+            "stall" => Some(Hazards::Stall),
+            "pipe-" => Some(Hazards::PipeMinus),
+            "pipe" | "forward" => Some(Hazards::Forward),
+            "load-forward" => Some(Hazards::LoadForward),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Hazards {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Hazards::Stall => write!(f, "stall only (no forwarding, no branch prediction)"),
+            Hazards::PipeMinus => write!(f, "PIPE- (no forwarding, jumps predicted taken)"),
+            Hazards::Forward => write!(f, "PIPE (full forwarding, jumps predicted taken)"),
+            Hazards::LoadForward => write!(f, "PIPE with load forwarding"),
+        }
+    }
+}
+
+/// Why an instruction was held up.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Stall {
+    /// Waiting in decode for a register an earlier instruction has yet to write back.
+    Data,
+    /// Waiting in decode for a value still being loaded from memory.
+    LoadUse,
+    /// Fetched after a conditional jump went the other way than predicted.
+    Mispredict,
+    /// Fetch waiting for a conditional jump without prediction.
+    Branch,
+    /// Fetch waiting for a `ret` to read its return address.
+    Return,
+}
+
+const STALLS: [Stall; 5] = [
+    Stall::Data,
+    Stall::LoadUse,
+    Stall::Mispredict,
+    Stall::Branch,
+    Stall::Return,
+];
+
+impl Display for Stall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stall::Data => write!(f, "data dependency"),
+            Stall::LoadUse => write!(f, "load/use"),
+            Stall::Mispredict => write!(f, "mispredicted jump"),
+            Stall::Branch => write!(f, "unpredicted jump"),
+            Stall::Return => write!(f, "ret"),
+        }
+    }
+}
+
+/// When one instruction went through the pipeline, in cycles counted from 1. It is in
+/// fetch from `fetch` until it
+/// moves to decode at `decode`, stays in decode until `execute - 1`, and then takes one
+/// cycle for each of execute, memory and write-back.
+#[derive(Clone, Debug)]
+pub struct Timing {
+    pub pc: usize,
+    pub instr: String,
+    pub fetch: usize,
+    pub decode: usize,
+    pub execute: usize,
+    /// The cycles the instruction lost waiting in fetch and in decode, and to what.
+    pub stalls: Vec<(Stall, usize)>,
+}
+
+impl Timing {
+    pub fn memory(&self) -> usize {
+        self.execute + 1
+    }
+
+    pub fn writeback(&self) -> usize {
+        self.execute + 2
+    }
+}
+
+/// An instruction fetched after a mispredicted jump and cancelled before it could execute.
+#[derive(Clone, Debug)]
+pub struct Squashed {
+    pub pc: usize,
+    pub instr: String,
+    pub fetch: usize,
+    pub decode: Option<usize>,
+    /// The index of the jump in [`Pipeline::timings`].
+    pub after: usize,
+}
+
+// a line of the timing diagram, in the order rows reach execute
+struct Row {
+    key: (usize, usize),
+    label: String,
+    // (cycle, stage)
+    cells: Vec<(usize, char)>,
+}
+
+/// A trace driven model of the five stage Y86 pipeline: it takes the instructions the
+/// sequential machine runs and works out when each would have gone through each stage.
+pub struct Pipeline {
+    hazards: Hazards,
+    timings: Vec<Timing>,
+    // the last instruction to write each register, and whether it writes it from memory
+    writers: [Option<(usize, bool)>; 15],
+    // the earliest fetch for the next instruction when the last one holds fetch up
+    redirect: Option<(Stall, usize)>,
+    stalls: [usize; STALLS.len()],
+    squashed: Vec<Squashed>,
+}
+
+// the registers an instruction reads in decode, as (srcA, srcB)
+fn sources(state: &CycleState) -> [usize; 2] {
+    match state.op {
+        OpCode::Rmmov | OpCode::Opx => [state.r_a, state.r_b],
+        OpCode::Cmov => [state.r_a, RNONE],
+        OpCode::Mrmov => [RNONE, state.r_b],
+        OpCode::Push => [state.r_a, RSP],
+        OpCode::Call | OpCode::Ret | OpCode::Pop => [RNONE, RSP],
+        _ => [RNONE, RNONE],
+    }
+}
+
+// the registers written from execute (dstE) and from memory (dstM)
+fn destinations(state: &CycleState) -> [usize; 2] {
+    match state.op {
+        OpCode::Irmov | OpCode::Opx => [state.r_b, RNONE],
+        OpCode::Cmov if state.cnd => [state.r_b, RNONE],
+        OpCode::Mrmov => [RNONE, state.r_a],
+        OpCode::Push | OpCode::Call | OpCode::Ret => [RSP, RNONE],
+        OpCode::Pop => [RSP, state.r_a],
+        _ => [RNONE, RNONE],
+    }
+}
+
+impl Pipeline {
+    pub fn new(hazards: Hazards) -> Pipeline {
+        Pipeline {
+            hazards,
+            timings: Vec::new(),
+            writers: [None; 15],
+            redirect: None,
+            stalls: [0; STALLS.len()],
+            squashed: Vec::new(),
+        }
+    }
+
+    pub fn hazards(&self) -> Hazards {
+        self.hazards
+    }
+
+    pub fn timings(&self) -> &[Timing] {
+        &self.timings
+    }
+
+    /// Cycles until the last instruction leaves write-back.
+    pub fn cycles(&self) -> usize {
+        self.timings.last().map(|t| t.writeback()).unwrap_or(0)
+    }
+
+    /// Instructions fetched down the wrong path of a mispredicted jump and cancelled.
+    pub fn squashed(&self) -> &[Squashed] {
+        &self.squashed
+    }
+
+    pub fn stall_cycles(&self, stall: Stall) -> usize {
+        let idx = STALLS.iter().position(|&s| s == stall).unwrap();
+        self.stalls[idx]
+    }
+
+    /// Adds the instruction the machine just ran at `pc`, for use with [`Machine::run_with`].
+    pub fn record(&mut self, machine: &Machine, pc: usize, state: &CycleState) {
+        // the next instruction is fetched as the previous one moves on to decode
+        let (mut fetch, decode_free) = match self.timings.last() {
+            Some(prev) => (prev.decode, prev.execute),
+            None => (1, 0),
+        };
+        let mut stalls = Vec::new();
+        let mut decode = (fetch + 1).max(decode_free);
+        // counted by how much later the instruction gets to decode
+        if let Some((kind, at)) = self.redirect.take() {
+            fetch = fetch.max(at);
+            if fetch + 1 > decode {
+                stalls.push((kind, fetch + 1 - decode));
+                decode = fetch + 1;
+            }
+        }
+
+        // decode ends the cycle before execute, once every source register can be read
+        let mut execute = decode + 1;
+        let mut held = Stall::Data;
+        let [src_a, src_b] = sources(state);
+        for src in [src_a, src_b] {
+            let (writer, load) = match self.writers.get(src).copied().flatten() {
+                Some(writer) => writer,
+                None => continue,
+            };
+            let writer = &self.timings[writer];
+            let store_only =
+                src == src_a && src != src_b && matches!(state.op, OpCode::Rmmov | OpCode::Push);
+            let (ready, kind) = match (self.hazards, load) {
+                (Hazards::Stall | Hazards::PipeMinus, _) => (writer.writeback() + 1, Stall::Data),
+                (Hazards::LoadForward, true) if store_only => (writer.execute, Stall::LoadUse),
+                (_, true) => (writer.memory(), Stall::LoadUse),
+                (_, false) => (writer.execute, Stall::Data),
+            };
+            if ready + 1 > execute {
+                execute = ready + 1;
+                held = kind;
+            }
+        }
+        if execute > decode + 1 {
+            stalls.push((held, execute - decode - 1));
+        }
+
+        for &(kind, cycles) in &stalls {
+            let idx = STALLS.iter().position(|&s| s == kind).unwrap();
+            self.stalls[idx] += cycles;
+        }
+        let idx = self.timings.len();
+        let [dst_e, dst_m] = destinations(state);
+        for (dst, load) in [(dst_e, false), (dst_m, true)] {
+            if let Some(writer) = self.writers.get_mut(dst) {
+                *writer = Some((idx, load));
+            }
+        }
+
+        let conditional = state.op == OpCode::Jxx && !matches!(state.fun, FunCode::Ucnd);
+        self.redirect = match state.op {
+            // the return address is known once the ret has read it in memory
+            OpCode::Ret => Some((Stall::Return, execute + 2)),
+            _ if conditional && self.hazards == Hazards::Stall => {
+                Some((Stall::Branch, execute + 1))
+            }
+            _ if conditional && !state.cnd => Some((Stall::Mispredict, execute + 1)),
+            _ => None,
+        };
+        if let Some((Stall::Mispredict, _)) = self.redirect {
+            // the predicted target is fetched while the jump decodes, and the instruction
+            // after it while the jump executes, until the jump turns out not taken
+            let mut addr = state.val_c as usize;
+            for (fetch, decode) in [(decode, Some(execute)), (execute, None)] {
+                let (instr, len) = match machine.disassemble(addr) {
+                    Ok(instr) => instr,
+                    Err(_) => ("(invalid)".to_string(), 1),
+                };
+                self.squashed.push(Squashed {
+                    pc: addr,
+                    instr,
+                    fetch,
+                    decode,
+                    after: idx,
+                });
+                addr = addr.wrapping_add(len);
+            }
+        }
+        self.timings.push(Timing {
+            pc,
+            instr: machine.format_instr(state),
+            fetch,
+            decode,
+            execute,
+            stalls,
+        });
+    }
+
+    /// Cycle count, CPI and the stall cycles lost to each kind of hazard.
+    pub fn report(&self) -> String {
+        let instrs = self.timings.len();
+        let cycles = self.cycles();
+        let cpi = |cycles: usize| match instrs {
+            0 => 0.0,
+            _ => cycles as f64 / instrs as f64,
+        };
+        let stalled: usize = self.stalls.iter().sum();
+        let mut out = format!("Pipeline: {}\n", self.hazards);
+        out.push_str(&format!(
+            "  instructions {}  cycles {}  CPI {:.3} ({:.3} without the 4 cycles to fill the pipeline)\n",
+            instrs,
+            cycles,
+            cpi(cycles),
+            cpi(cycles.saturating_sub(4))
+        ));
+        out.push_str(&format!("  stall cycles {}\n", stalled));
+        for (kind, &cycles) in STALLS.iter().zip(&self.stalls) {
+            if cycles > 0 {
+                out.push_str(&format!(
+                    "    {:20} {:8} {:6.2}%\n",
+                    kind.to_string(),
+                    cycles,
+                    cycles as f64 * 100.0 / stalled as f64
+                ));
+            }
+        }
+        out
+    }
+
+    /// A textbook style diagram of which stage each instruction is in over `cycles`, one row
+    /// per instruction. Cycles spent stalled in fetch or decode show as `f` and `d`; squashed
+    /// instructions and the bubbles that take their place in execute get rows of their own.
+    pub fn diagram(&self, cycles: RangeInclusive<usize>) -> String {
+        let mut rows: Vec<Row> = Vec::new();
+        for t in &self.timings {
+            let mut cells = vec![(t.fetch, 'F')];
+            cells.extend((t.fetch + 1..t.decode).map(|c| (c, 'f')));
+            cells.push((t.decode, 'D'));
+            cells.extend((t.decode + 1..t.execute).map(|c| (c, 'd')));
+            cells.extend([(t.execute, 'E'), (t.memory(), 'M'), (t.writeback(), 'W')]);
+            let label = format!("0x{:04x}: {}", t.pc, t.instr);
+            rows.push(Row {
+                key: (t.execute, 0),
+                label,
+                cells,
+            });
+        }
+        for (i, s) in self.squashed.iter().enumerate() {
+            let mut cells = vec![(s.fetch, 'F')];
+            cells.extend(s.decode.map(|c| (c, 'D')));
+            let label = format!("0x{:04x}: {} (squashed)", s.pc, s.instr);
+            rows.push(Row {
+                key: (self.timings[s.after].execute, i + 1),
+                label,
+                cells,
+            });
+        }
+        let executed: Vec<usize> = self.timings.iter().map(|t| t.execute).collect();
+        if let (Some(&first), Some(&last)) = (executed.first(), executed.last()) {
+            for cycle in first..last {
+                if executed.binary_search(&cycle).is_err() {
+                    let cells = vec![(cycle, 'E'), (cycle + 1, 'M'), (cycle + 2, 'W')];
+                    rows.push(Row {
+                        key: (cycle, 0),
+                        label: "bubble".to_string(),
+                        cells,
+                    });
+                }
+            }
+        }
+        rows.sort_by_key(|row| row.key);
+        rows.retain(|row| row.cells.iter().any(|(c, _)| cycles.contains(c)));
+
+        let label_width = rows.iter().map(|row| row.label.len()).max().unwrap_or(0);
+        let width = cycles.end().to_string().len() + 1;
+        let mut out = format!("{:label_width$}", "");
+        for cycle in cycles.clone() {
+            out.push_str(&format!("{:>width$}", cycle));
+        }
+        out.push('\n');
+        for row in rows {
+            let mut line = format!("{:label_width$}", row.label);
+            for cycle in cycles.clone() {
+                let mark = match row.cells.iter().find(|(c, _)| *c == cycle) {
+                    Some((_, mark)) => *mark,
+                    None => ' ',
+                };
+                line.push_str(&format!("{:>width$}", mark));
+            }
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+}
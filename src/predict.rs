@@ -0,0 +1,285 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{CycleState, FunCode, Machine, OpCode};
+
+/// Guesses branch outcomes ahead of execution, the way a pipeline has to when it fetches
+/// past a `jXX` or `ret` before the branch resolves.
+pub trait BranchPredictor {
+    fn name(&self) -> String;
+
+    /// Whether the conditional jump at `pc` to `target` will be taken.
+    fn predict(&mut self, pc: usize, target: usize) -> bool;
+
+    /// Learns how the conditional jump at `pc` went.
+    fn update(&mut self, _pc: usize, _taken: bool) {}
+
+    /// The address the next `ret` will go to, if the predictor has a guess at all. A `ret`
+    /// without a guess counts as mispredicted.
+    fn predict_return(&mut self) -> Option<usize> {
+        None
+    }
+
+    /// Sees a `call` that will return to `ret`.
+    fn call(&mut self, _ret: usize) {}
+}
+
+pub struct AlwaysTaken;
+
+impl BranchPredictor for AlwaysTaken {
+    fn name(&self) -> String {
+        "always taken".to_string()
+    }
+
+    fn predict(&mut self, _pc: usize, _target: usize) -> bool {
+        true
+    }
+}
+
+pub struct NeverTaken;
+
+impl BranchPredictor for NeverTaken {
+    fn name(&self) -> String {
+        "never taken".to_string()
+    }
+
+    fn predict(&mut self, _pc: usize, _target: usize) -> bool {
+        false
+    }
+}
+
+/// Backward taken, forward not taken: loops jump back, so backward jumps are guessed taken.
+pub struct Btfnt;
+
+impl BranchPredictor for Btfnt {
+    fn name(&self) -> String {
+        "backward taken, forward not taken".to_string()
+    }
+
+    fn predict(&mut self, pc: usize, target: usize) -> bool {
+        target <= pc
+    }
+}
+
+/// One bit per branch remembering its last outcome. Branches start out predicted taken.
+#[derive(Default)]
+pub struct OneBit {
+    last: HashMap<usize, bool>,
+}
+
+impl BranchPredictor for OneBit {
+    fn name(&self) -> String {
+        "1-bit".to_string()
+    }
+
+    fn predict(&mut self, pc: usize, _target: usize) -> bool {
+        self.last.get(&pc).copied().unwrap_or(true)
+    }
+
+    fn update(&mut self, pc: usize, taken: bool) {
+        self.last.insert(pc, taken);
+    }
+}
+
+/// A 2-bit saturating counter per branch, predicting taken in its upper two states. Counters
+/// start weakly taken.
+#[derive(Default)]
+pub struct TwoBit {
+    counters: HashMap<usize, u8>,
+}
+
+impl BranchPredictor for TwoBit {
+    fn name(&self) -> String {
+        "2-bit saturating counters".to_string()
+    }
+
+    fn predict(&mut self, pc: usize, _target: usize) -> bool {
+        self.counters.get(&pc).copied().unwrap_or(2) >= 2
+    }
+
+    fn update(&mut self, pc: usize, taken: bool) {
+        let counter = self.counters.entry(pc).or_insert(2);
+        *counter = match taken {
+            true => (*counter + 1).min(3),
+            false => counter.saturating_sub(1),
+        };
+    }
+}
+
+/// Adds a return-address stack of `depth` entries to another predictor. A full stack drops
+/// its oldest entry.
+pub struct ReturnStack {
+    inner: Box<dyn BranchPredictor>,
+    depth: usize,
+    stack: Vec<usize>,
+}
+
+impl ReturnStack {
+    pub fn new(inner: Box<dyn BranchPredictor>, depth: usize) -> ReturnStack {
+        ReturnStack {
+            inner,
+            depth,
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl BranchPredictor for ReturnStack {
+    fn name(&self) -> String {
+        format!(
+            "{} with a {}-entry return stack",
+            self.inner.name(),
+            self.depth
+        )
+    }
+
+    fn predict(&mut self, pc: usize, target: usize) -> bool {
+        self.inner.predict(pc, target)
+    }
+
+    fn update(&mut self, pc: usize, taken: bool) {
+        self.inner.update(pc, taken);
+    }
+
+    fn predict_return(&mut self) -> Option<usize> {
+        self.stack.pop()
+    }
+
+    fn call(&mut self, ret: usize) {
+        if self.stack.len() == self.depth {
+            self.stack.remove(0);
+        }
+        self.stack.push(ret);
+    }
+}
+
+const RETURN_STACK_DEPTH: usize = 16;
+
+/// A predictor by name: `taken`, `not-taken`, `btfnt`, `1bit` or `2bit`, with `+ras` for a
+/// return-address stack.
+pub fn predictor(name: &str) -> Option<Box<dyn BranchPredictor>> {
+    let (base, ras) = match name.strip_suffix("+ras") {
+        Some(base) => (base, true),
+        None => (name, false),
+    };
+    let predictor: Box<dyn BranchPredictor> = match base {
+        "taken" => Box::new(AlwaysTaken),
+        "not-taken" => Box::new(NeverTaken),
+        "btfnt" => Box::new(Btfnt),
+        "1bit" => Box::new(OneBit::default()),
+        "2bit" => Box::new(TwoBit::default()),
+        _ => return None,
+    };
+    Some(match ras {
+        true => Box::new(ReturnStack::new(predictor, RETURN_STACK_DEPTH)),
+        false => predictor,
+    })
+}
+
+#[derive(Default)]
+struct SiteStats {
+    count: u64,
+    taken: u64,
+    mispredicted: u64,
+}
+
+/// How well one predictor did on a run, per branch address.
+pub struct BranchStats {
+    predictor: Box<dyn BranchPredictor>,
+    jumps: BTreeMap<usize, SiteStats>,
+    returns: BTreeMap<usize, SiteStats>,
+}
+
+fn accuracy(count: u64, mispredicted: u64) -> f64 {
+    match count {
+        0 => 100.0,
+        _ => (count - mispredicted) as f64 * 100.0 / count as f64,
+    }
+}
+
+impl BranchStats {
+    pub fn new(predictor: Box<dyn BranchPredictor>) -> BranchStats {
+        BranchStats {
+            predictor,
+            jumps: BTreeMap::new(),
+            returns: BTreeMap::new(),
+        }
+    }
+
+    /// Predicts and resolves the instruction at `pc`, for use with [`Machine::run_with`].
+    /// Only conditional jumps and returns are predicted; `jmp` and `call` always go where
+    /// they say.
+    pub fn record(&mut self, pc: usize, state: &CycleState) {
+        match state.op {
+            OpCode::Jxx if !matches!(state.fun, FunCode::Ucnd) => {
+                let guess = self.predictor.predict(pc, state.val_c as usize);
+                self.predictor.update(pc, state.cnd);
+                let site = self.jumps.entry(pc).or_default();
+                site.count += 1;
+                site.taken += state.cnd as u64;
+                site.mispredicted += (guess != state.cnd) as u64;
+            }
+            OpCode::Call => self.predictor.call(state.val_p),
+            OpCode::Ret => {
+                let guess = self.predictor.predict_return();
+                let site = self.returns.entry(pc).or_default();
+                site.count += 1;
+                site.mispredicted += (guess != Some(state.val_m as usize)) as u64;
+            }
+            _ => (),
+        }
+    }
+
+    pub fn mispredicted(&self) -> u64 {
+        self.jumps
+            .values()
+            .chain(self.returns.values())
+            .map(|s| s.mispredicted)
+            .sum()
+    }
+
+    /// Overall accuracy for conditional jumps and returns, then every branch address.
+    pub fn report(&self, machine: &Machine) -> String {
+        let mut out = format!("Branch prediction: {}\n", self.predictor.name());
+        let total = |sites: &BTreeMap<usize, SiteStats>| {
+            sites
+                .values()
+                .fold((0, 0), |(n, m), s| (n + s.count, m + s.mispredicted))
+        };
+        let (jumps, jump_misses) = total(&self.jumps);
+        let (returns, return_misses) = total(&self.returns);
+        out.push_str(&format!(
+            "  conditional jumps {:6}  mispredicted {:6}  {:6.2}% correct\n",
+            jumps,
+            jump_misses,
+            accuracy(jumps, jump_misses)
+        ));
+        out.push_str(&format!(
+            "  returns           {:6}  mispredicted {:6}  {:6.2}% correct\n",
+            returns,
+            return_misses,
+            accuracy(returns, return_misses)
+        ));
+
+        let mut sites: Vec<_> = self.jumps.iter().chain(&self.returns).collect();
+        sites.sort_by_key(|(addr, _)| **addr);
+        for (&addr, site) in sites {
+            let instr = match machine.disassemble(addr) {
+                Ok((instr, _)) => instr,
+                Err(_) => "??".to_string(),
+            };
+            let taken = match self.jumps.contains_key(&addr) {
+                true => format!("taken {}/{}", site.taken, site.count),
+                false => format!("ran {}", site.count),
+            };
+            out.push_str(&format!(
+                "  {:30} {:24} {:14} mispredicted {:5} {:6.2}% correct\n",
+                machine.describe(addr),
+                instr,
+                taken,
+                site.mispredicted,
+                accuracy(site.count, site.mispredicted)
+            ));
+        }
+        out
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::{btree_map::Entry, BTreeMap, HashMap};
+
+use crate::{CycleState, Machine, OpCode};
+
+#[derive(Default)]
+struct FuncCounts {
+    calls: u64,
+    own: u64,
+    total: u64,
+}
+
+/// Execution counts per instruction, opcode and function. Every SEQ cycle runs one
+/// instruction, so these are cycle counts as well.
+pub struct Profile {
+    total: u64,
+    by_pc: BTreeMap<usize, u64>,
+    // (icode, ifun) -> mnemonic, count
+    by_op: BTreeMap<(u8, u8), (String, u64)>,
+    // keyed by entry address; the program's entry point is treated as a function too
+    by_func: BTreeMap<usize, FuncCounts>,
+    // entry addresses of the routines currently being run, innermost last
+    calls: Vec<usize>,
+    // entry address -> (times on the call stack, cycle count when it first went on), so
+    // a recursive routine's total only counts each instruction once
+    active: HashMap<usize, (usize, u64)>,
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => count as f64 * 100.0 / total as f64,
+    }
+}
+
+impl Profile {
+    /// An empty profile for a program that starts at the machine's current PC.
+    pub fn new(machine: &Machine) -> Profile {
+        let entry = machine.pc;
+        let mut by_func = BTreeMap::new();
+        by_func.insert(entry, FuncCounts::default());
+        Profile {
+            total: 0,
+            by_pc: BTreeMap::new(),
+            by_op: BTreeMap::new(),
+            by_func,
+            calls: vec![entry],
+            active: HashMap::from([(entry, (1, 0))]),
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    // instructions run while the routine at `entry` was on the call stack
+    fn func_total(&self, entry: usize) -> u64 {
+        let done = self.by_func.get(&entry).map_or(0, |f| f.total);
+        match self.active.get(&entry) {
+            Some(&(_, since)) => done + self.total - since,
+            None => done,
+        }
+    }
+
+    /// How many times the instruction at `addr` ran.
+    pub fn count(&self, addr: usize) -> u64 {
+        self.by_pc.get(&addr).copied().unwrap_or(0)
+    }
+
+    /// Counts the instruction at `pc`, for use with [`Machine::run_with`]. Functions are the
+    /// targets of `call`, and an instruction counts towards every function on the call stack
+    /// when it runs.
+    pub fn record(&mut self, machine: &Machine, pc: usize, state: &CycleState) {
+        self.total += 1;
+        *self.by_pc.entry(pc).or_default() += 1;
+        match self.by_op.entry((state.icode, state.ifun)) {
+            Entry::Occupied(mut op) => op.get_mut().1 += 1,
+            Entry::Vacant(op) => {
+                let instr = machine.format_instr(state);
+                let mnemonic = instr.split_whitespace().next().unwrap_or_default();
+                op.insert((mnemonic.to_string(), 1));
+            }
+        }
+
+        let current = *self.calls.last().unwrap();
+        self.by_func.entry(current).or_default().own += 1;
+
+        match state.op {
+            OpCode::Call => {
+                let callee = state.val_c as usize;
+                self.by_func.entry(callee).or_default().calls += 1;
+                self.calls.push(callee);
+                self.active.entry(callee).or_insert((0, self.total)).0 += 1;
+            }
+            OpCode::Ret if self.calls.len() > 1 => {
+                let callee = self.calls.pop().unwrap();
+                if let Some((depth, since)) = self.active.get_mut(&callee) {
+                    *depth -= 1;
+                    if *depth == 0 {
+                        let ran = self.total - *since;
+                        self.active.remove(&callee);
+                        self.by_func.entry(callee).or_default().total += ran;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Machine {
+    /// The listing annotated with execution counts, followed by per-function and
+    /// per-opcode summaries and the `top` most executed instructions.
+    pub fn profile_report(&self, profile: &Profile, top: usize) -> String {
+        let total = profile.total;
+        let mut out = format!("Profile: {} cycles\n\n", total);
+
+        out.push_str("   count       %   addr | listing\n");
+        if self.source.is_empty() {
+            for (&addr, &count) in &profile.by_pc {
+                let instr = match self.disassemble(addr) {
+                    Ok((instr, _)) => instr,
+                    Err(_) => "??".to_string(),
+                };
+                out.push_str(&format!(
+                    "{:8} {:6.2}% | {}: {}\n",
+                    count,
+                    percent(count, total),
+                    self.describe(addr),
+                    instr
+                ));
+            }
+        } else {
+            // line -> (first address, count)
+            let mut by_line = BTreeMap::new();
+            for (addr, line) in self.source.addrs() {
+                by_line.entry(line).or_insert((addr, 0)).1 += profile.count(addr);
+            }
+            for (n, text) in self.source.lines() {
+                match by_line.get(&n) {
+                    Some(&(addr, count)) if count > 0 => out.push_str(&format!(
+                        "{:8} {:6.2}% 0x{:04x} |{}\n",
+                        count,
+                        percent(count, total),
+                        addr,
+                        text
+                    )),
+                    Some(&(addr, _)) => {
+                        out.push_str(&format!("{:17}0x{:04x} |{}\n", "", addr, text))
+                    }
+                    None => out.push_str(&format!("{:24}|{}\n", "", text)),
+                }
+            }
+        }
+
+        out.push_str("\nFunctions:\n     calls      self       %     total       % | function\n");
+        let mut funcs: Vec<_> = profile
+            .by_func
+            .iter()
+            .map(|(&addr, f)| (addr, f, profile.func_total(addr)))
+            .filter(|&(_, _, func_total)| func_total > 0)
+            .collect();
+        funcs.sort_by_key(|&(addr, f, _)| (std::cmp::Reverse(f.own), addr));
+        for (addr, func, func_total) in funcs {
+            out.push_str(&format!(
+                "{:10} {:9} {:6.2}% {:9} {:6.2}% | {}\n",
+                func.calls,
+                func.own,
+                percent(func.own, total),
+                func_total,
+                percent(func_total, total),
+                self.describe(addr)
+            ));
+        }
+
+        out.push_str("\nOpcodes:\n     count       % | icode:ifun\n");
+        let mut ops: Vec<_> = profile.by_op.iter().collect();
+        ops.sort_by_key(|(code, (_, count))| (std::cmp::Reverse(*count), **code));
+        for ((icode, ifun), (name, count)) in ops {
+            out.push_str(&format!(
+                "{:10} {:6.2}% | {:x}:{:x} {}\n",
+                count,
+                percent(*count, total),
+                icode,
+                ifun,
+                name
+            ));
+        }
+
+        out.push_str(&format!("\nTop {} instructions:\n", top));
+        let mut hot: Vec<_> = profile.by_pc.iter().collect();
+        hot.sort_by_key(|(addr, count)| (std::cmp::Reverse(**count), **addr));
+        for (&addr, &count) in hot.into_iter().take(top) {
+            let instr = match self.disassemble(addr) {
+                Ok((instr, _)) => instr,
+                Err(_) => "??".to_string(),
+            };
+            out.push_str(&format!(
+                "{:10} {:6.2}% | {}: {}\n",
+                count,
+                percent(count, total),
+                self.describe(addr),
+                instr
+            ));
+        }
+
+        out
+    }
+}
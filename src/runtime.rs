@@ -0,0 +1,12 @@
+// Y86 routines shipped with the crate. Every `Assembler` can include them by name, e.g.
+// `.include "y86/memcpy.ys"`; arguments follow the x86-64 convention (%rdi, %rsi, %rdx,
+// result in %rax) and only caller-saved registers are clobbered.
+pub const RUNTIME: [(&str, &str); 7] = [
+    ("y86/console.ys", include_str!("runtime/console.ys")),
+    ("y86/memcpy.ys", include_str!("runtime/memcpy.ys")),
+    ("y86/memset.ys", include_str!("runtime/memset.ys")),
+    ("y86/strlen.ys", include_str!("runtime/strlen.ys")),
+    ("y86/mul.ys", include_str!("runtime/mul.ys")),
+    ("y86/div.ys", include_str!("runtime/div.ys")),
+    ("y86/print_int.ys", include_str!("runtime/print_int.ys")),
+];
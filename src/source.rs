@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+
+/// Maps addresses back to the listing line that produced them.
+#[derive(Default)]
+pub struct SourceMap {
+    // assembly text of every listing line, indexed by line number - 1
+    lines: Vec<String>,
+    by_addr: BTreeMap<usize, usize>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    pub(crate) fn push_line(&mut self, text: &str) {
+        self.lines.push(text.trim_end().to_string());
+    }
+
+    pub(crate) fn map(&mut self, addr: usize, line: usize) {
+        self.by_addr.insert(addr, line);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_addr.is_empty()
+    }
+
+    /// The 1-based line number and text of the line that emitted `addr`.
+    pub fn line_at(&self, addr: usize) -> Option<(usize, &str)> {
+        let line = *self.by_addr.get(&addr)?;
+        Some((line, self.lines.get(line - 1)?.as_str()))
+    }
+
+    pub fn text(&self, line: usize) -> Option<&str> {
+        self.lines.get(line.checked_sub(1)?).map(|s| s.as_str())
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.lines
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (i + 1, s.as_str()))
+    }
+
+    pub fn addrs(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.by_addr.iter().map(|(&addr, &line)| (addr, line))
+    }
+
+    /// The line for `addr` with `radius` lines on either side, the current one marked by `>`.
+    pub fn context(&self, addr: usize, radius: usize) -> Option<String> {
+        let (line, _) = self.line_at(addr)?;
+        let first = line.saturating_sub(radius).max(1);
+        let last = (line + radius).min(self.lines.len());
+
+        let mut str = String::new();
+        for n in first..=last {
+            let marker = if n == line { '>' } else { ' ' };
+            str.push_str(&format!("{} {:4} |{}\n", marker, n, self.lines[n - 1]));
+        }
+
+        Some(str)
+    }
+}
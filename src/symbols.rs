@@ -0,0 +1,71 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Labels recovered from an object file, used to show addresses as `label+0xoff`.
+#[derive(Default)]
+pub struct SymbolTable {
+    by_addr: BTreeMap<usize, String>,
+    by_name: HashMap<String, usize>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    // the first label seen at an address is the one used when symbolizing
+    pub fn insert(&mut self, name: &str, addr: usize) {
+        self.by_addr.entry(addr).or_insert_with(|| name.to_string());
+        self.by_name.insert(name.to_string(), addr);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<usize> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn name_at(&self, addr: usize) -> Option<&str> {
+        self.by_addr.get(&addr).map(|s| s.as_str())
+    }
+
+    /// The nearest label at or below `addr` and the offset from it.
+    pub fn nearest(&self, addr: usize) -> Option<(&str, usize)> {
+        self.by_addr
+            .range(..=addr)
+            .next_back()
+            .map(|(&base, name)| (name.as_str(), addr - base))
+    }
+
+    pub fn symbolize(&self, addr: usize) -> String {
+        match self.nearest(addr) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, off)) => format!("{}+0x{:x}", name, off),
+            None => format!("0x{:04x}", addr),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.by_addr
+            .iter()
+            .map(|(&addr, name)| (addr, name.as_str()))
+    }
+}
+
+// a label is the leading `name:` of the assembly text after the `|`
+pub(crate) fn parse_label(asm: &str) -> Option<&str> {
+    let asm = asm.trim_start();
+    let colon = asm.find(':')?;
+    let name = &asm[..colon];
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => (),
+        _ => return None,
+    }
+    if chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+        Some(name)
+    } else {
+        None
+    }
+}
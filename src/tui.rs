@@ -0,0 +1,433 @@
+use std::{
+    io::{self, IsTerminal, Read, Write},
+    panic::{self, PanicHookInfo},
+    process::{Command, Stdio},
+    sync::Arc,
+};
+
+use crate::{callstack::Until, CycleState, Machine, MemView, Snapshot, Unit, REG_NAMES, RSP};
+
+const LEFT: usize = 46;
+const DISASM_LINES: usize = 19;
+const PANE_LINES: usize = 9;
+
+const BOLD: &str = "\x1b[1m";
+const CHANGED: &str = "\x1b[1;33m";
+const CURRENT: &str = "\x1b[7m";
+const RESET: &str = "\x1b[0m";
+
+const KEYS: &str =
+    "s step  n over  f finish  c continue  j/k move  b breakpoint  m memory  [/] scroll  q quit";
+
+type PanicHook = Box<dyn Fn(&PanicHookInfo) + Send + Sync>;
+
+// back to the normal screen and the settings `stty -g` saved, if it could
+fn restore_terminal(saved: Option<&str>) {
+    print!("\x1b[?25h\x1b[?1049l");
+    let _ = io::stdout().flush();
+    if let Some(saved) = saved {
+        let _ = Command::new("stty").arg(saved).status();
+    }
+}
+
+// puts the terminal into raw mode and the alternate screen for as long as it is alive,
+// restoring it before a panic message is printed as well
+struct RawMode {
+    // `None` without a working `stty`, when keys only arrive a line at a time
+    saved: Option<String>,
+    hook: Arc<PanicHook>,
+}
+
+impl RawMode {
+    fn enter() -> Result<RawMode, anyhow::Error> {
+        if !io::stdin().is_terminal() {
+            anyhow::bail!("the terminal UI needs a terminal on stdin");
+        }
+        let saved = Command::new("stty")
+            .arg("-g")
+            .stdin(Stdio::inherit())
+            .output()
+            .ok()
+            .filter(|out| out.status.success())
+            .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string());
+        if saved.is_some() {
+            Command::new("stty").args(["raw", "-echo"]).status()?;
+        }
+
+        let hook = Arc::new(panic::take_hook());
+        let (prev, restore) = (hook.clone(), saved.clone());
+        panic::set_hook(Box::new(move |info| {
+            restore_terminal(restore.as_deref());
+            prev(info);
+        }));
+        print!("\x1b[?1049h\x1b[?25l");
+        Ok(RawMode { saved, hook })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        restore_terminal(self.saved.as_deref());
+        // the hook cannot be changed while unwinding, and has restored the terminal anyway
+        if !std::thread::panicking() {
+            let prev = self.hook.clone();
+            panic::set_hook(Box::new(move |info| prev(info)));
+        }
+    }
+}
+
+/// A key press the terminal interface reacts to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TuiKey {
+    Char(u8),
+    Up,
+    Down,
+}
+
+fn read_key() -> Result<Option<TuiKey>, anyhow::Error> {
+    let mut stdin = io::stdin().lock();
+    let mut byte = [0];
+    if stdin.read(&mut byte)? == 0 {
+        return Ok(None);
+    }
+    if byte[0] != 0x1b {
+        return Ok(Some(TuiKey::Char(byte[0])));
+    }
+    let mut seq = [0; 2];
+    stdin.read_exact(&mut seq)?;
+    Ok(Some(match seq {
+        [b'[', b'A'] => TuiKey::Up,
+        [b'[', b'B'] => TuiKey::Down,
+        _ => TuiKey::Char(0x1b),
+    }))
+}
+
+// pads `text` to `width` columns before wrapping it in `style`, so escapes don't count
+fn styled(style: &str, text: &str, width: usize) -> String {
+    match style {
+        "" => format!("{:<width$}", text, width = width),
+        _ => format!("{}{:<width$}{}", style, text, RESET, width = width),
+    }
+}
+
+/// What the terminal interface (`-t`) keeps besides the machine: the disassembly cursor,
+/// the state before the last command, the memory pane and the message line. Screens are
+/// rendered with [`Machine::tui_screen`] and keys handled with [`Machine::tui_key`], apart
+/// from the terminal itself.
+pub struct TuiState {
+    cursor: usize,
+    before: Option<Snapshot>,
+    state: Option<CycleState>,
+    view: MemView,
+    message: String,
+    // the memory view being typed after `m`
+    prompt: Option<String>,
+}
+
+impl TuiState {
+    /// The cursor at the PC and memory from the first label past it.
+    pub fn new(machine: &Machine) -> TuiState {
+        let start = machine
+            .symbols
+            .iter()
+            .map(|(addr, _)| addr)
+            .find(|&a| a > machine.pc);
+        let start = start.unwrap_or_default() & !0xf;
+        let mut view = MemView::new(start..start.saturating_add(PANE_LINES * 16));
+        view.unit = Unit::Quad;
+        TuiState {
+            cursor: machine.pc,
+            before: None,
+            state: None,
+            view,
+            message: String::new(),
+            prompt: None,
+        }
+    }
+}
+
+impl Machine {
+    // instruction boundaries: listing lines with bytes when there is a listing, otherwise
+    // a linear sweep from address 0 that skips bytes that do not decode
+    fn instr_starts(&self) -> Vec<usize> {
+        let mut starts: Vec<usize> = self.source.addrs().map(|(addr, _)| addr).collect();
+        if starts.is_empty() {
+            let mut addr = 0;
+            while addr < self.mem.len() && addr <= self.pc + 64 {
+                starts.push(addr);
+                addr += match self.disassemble(addr) {
+                    Ok((_, len)) => len,
+                    Err(_) => 1,
+                };
+            }
+        }
+        if let Err(idx) = starts.binary_search(&self.pc) {
+            starts.insert(idx, self.pc);
+        }
+        starts
+    }
+
+    /// The screen for `tui`, a line at a time, with ANSI escapes for emphasis.
+    pub fn tui_screen(&self, tui: &TuiState) -> Vec<String> {
+        let mut left = Vec::new();
+        left.push(format!("{}Registers{}", BOLD, RESET));
+        for (i, val) in self.regs.iter().enumerate() {
+            let text = format!("{:>5}: 0x{:016x} {:>20}", REG_NAMES[i], val, val);
+            let changed = tui.before.as_ref().is_some_and(|b| b.regs[i] != *val);
+            left.push(styled(if changed { CHANGED } else { "" }, &text, LEFT));
+        }
+        let flags_changed = tui.before.as_ref().is_some_and(|b| b.flags != self.flags);
+        left.push(styled(
+            if flags_changed { CHANGED } else { "" },
+            &self.flags.to_string().replace('\t', "  "),
+            LEFT,
+        ));
+        left.push(format!("{}  cycle {}", self.status, self.cycle));
+        left.push(format!("PC: {}", self.describe(self.pc)));
+
+        let mut right = Vec::new();
+        right.push(format!("{}Disassembly{}", BOLD, RESET));
+        let starts = self.instr_starts();
+        let at = starts
+            .iter()
+            .position(|&a| a == tui.cursor)
+            .unwrap_or_default();
+        let first = at.saturating_sub(DISASM_LINES / 2);
+        for &addr in starts.iter().skip(first).take(DISASM_LINES - 1) {
+            let instr = match self.disassemble(addr) {
+                Ok((instr, _)) => instr,
+                Err(_) => "??".to_string(),
+            };
+            let bp = if self.breakpoints.contains(&addr) {
+                '*'
+            } else {
+                ' '
+            };
+            let pc = if addr == self.pc { '>' } else { ' ' };
+            let label = match self.symbols.name_at(addr) {
+                Some(name) => format!("<{}>", name),
+                None => String::new(),
+            };
+            let text = format!("{}{} 0x{:04x} {:<12} {}", bp, pc, addr, label, instr);
+            right.push(match addr == tui.cursor {
+                true => styled(CURRENT, &text, 44),
+                false => text,
+            });
+        }
+
+        let mut lines = Vec::new();
+        for i in 0..left.len().max(right.len()) {
+            let l = left.get(i).map(String::as_str).unwrap_or("");
+            let pad = LEFT.saturating_sub(visible_len(l));
+            lines.push(format!(
+                "{}{:pad$} {}",
+                l,
+                "",
+                right.get(i).map(String::as_str).unwrap_or("")
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push(format!(
+            "{}{:<LEFT$}{} {}Memory 0x{:04x}..0x{:04x}{}",
+            BOLD, "Stack", RESET, BOLD, tui.view.range.start, tui.view.range.end, RESET
+        ));
+        let rsp = self.regs[RSP] as usize;
+        let mem = match self.view_mem(&tui.view) {
+            Ok(text) => text
+                .lines()
+                .filter(|l| !l.starts_with('<'))
+                .map(str::to_string)
+                .collect(),
+            Err(e) => vec![e.to_string()],
+        };
+        for i in 0..PANE_LINES {
+            // two words below %rsp, the rest at and above it, stopping at either end of memory
+            let addr = (rsp & !7)
+                .checked_add(i * 8)
+                .and_then(|a| a.checked_sub(16))
+                .filter(|&a| a < self.mem.len());
+            let stack = match addr.map(|a| (a, self.get_mem_word(a))) {
+                Some((addr, Ok(word))) => {
+                    let marker = if addr == rsp { "%rsp ->" } else { "" };
+                    format!("{:>7} 0x{:04x}: 0x{:016x}", marker, addr, word)
+                }
+                _ => String::new(),
+            };
+            let stack = match (tui.before.as_ref(), addr) {
+                (Some(b), Some(addr))
+                    if addr < b.mem.len().saturating_sub(7)
+                        && b.mem[addr..addr + 8] != self.mem[addr..addr + 8] =>
+                {
+                    styled(CHANGED, &stack, LEFT)
+                }
+                _ => styled("", &stack, LEFT),
+            };
+            lines.push(format!(
+                "{} {}",
+                stack,
+                mem.get(i).map(String::as_str).unwrap_or("")
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push(format!("{}Stages{}", BOLD, RESET));
+        match &tui.state {
+            Some(state) => {
+                lines.push(format!(
+                    "{}  icode:ifun = {}:{}  rA:rB = {:x}:{:x}  Cnd = {}",
+                    self.format_instr(state),
+                    self.op_name(state),
+                    state.fun,
+                    state.r_a,
+                    state.r_b,
+                    state.cnd
+                ));
+                lines.push(format!(
+                    "valC = 0x{:016x}  valP = 0x{:016x}  valA = 0x{:016x}",
+                    state.val_c, state.val_p, state.val_a
+                ));
+                lines.push(format!(
+                    "valB = 0x{:016x}  valE = 0x{:016x}  valM = 0x{:016x}",
+                    state.val_b, state.val_e, state.val_m
+                ));
+            }
+            None => lines.push("no cycle run yet".to_string()),
+        }
+
+        let mut calls = vec![self.describe(self.pc)];
+        calls.extend(self.frames.iter().rev().map(|f| self.describe(f.ret)));
+        lines.push(format!("Backtrace: {}", calls.join(" <- ")));
+
+        lines.push(String::new());
+        lines.push(match &tui.prompt {
+            Some(line) => format!("memory view: {}", line),
+            None => tui.message.clone(),
+        });
+        lines.push(format!("{}{}{}", BOLD, KEYS, RESET));
+        lines
+    }
+
+    fn tui_draw(&self, tui: &TuiState) -> Result<(), anyhow::Error> {
+        let frame = self.tui_screen(tui);
+        let mut out = io::stdout().lock();
+        write!(out, "\x1b[H\x1b[2J{}", frame.join("\r\n"))?;
+        out.flush()?;
+        Ok(())
+    }
+
+    fn tui_step(&mut self, tui: &mut TuiState, until: Until) {
+        if !self.is_running() {
+            tui.message = format!("{}, nothing left to run", self.status);
+            return;
+        }
+        let before = self.snapshot();
+        tui.message.clear();
+        match self.run_until(until) {
+            Ok(state) => {
+                tui.state = state;
+                if self.is_running() && self.breakpoints.contains(&self.pc) {
+                    tui.message = format!("breakpoint at {}", self.describe(self.pc));
+                }
+            }
+            Err(e) => tui.message = e.to_string().lines().next().unwrap_or_default().to_string(),
+        }
+        if let Some(warning) = self.take_warnings().pop() {
+            tui.message = format!("warning: {}", warning);
+        }
+        tui.before = Some(before);
+        tui.cursor = self.pc;
+    }
+
+    // a key while the memory view prompt is open
+    fn tui_prompt_key(&self, tui: &mut TuiState, key: TuiKey) {
+        let Some(line) = &mut tui.prompt else {
+            return;
+        };
+        match key {
+            TuiKey::Char(b'\r' | b'\n') => {
+                tui.message = match MemView::parse(line, &self.symbols) {
+                    Ok(mut view) => {
+                        view.range.end = view.range.start.saturating_add(PANE_LINES * 16);
+                        tui.view = view;
+                        String::new()
+                    }
+                    Err(e) => e,
+                };
+                tui.prompt = None;
+            }
+            TuiKey::Char(0x1b) => tui.prompt = None,
+            TuiKey::Char(0x7f | 0x08) => {
+                line.pop();
+            }
+            TuiKey::Char(c) if (0x20..0x7f).contains(&c) => line.push(c as char),
+            _ => (),
+        }
+    }
+
+    /// Handles a key press in the terminal interface. Returns false once it asks to quit.
+    pub fn tui_key(&mut self, tui: &mut TuiState, key: TuiKey) -> bool {
+        if tui.prompt.is_some() {
+            self.tui_prompt_key(tui, key);
+            return true;
+        }
+        let starts = self.instr_starts();
+        let at = starts
+            .iter()
+            .position(|&a| a == tui.cursor)
+            .unwrap_or_default();
+        match key {
+            TuiKey::Char(b's') | TuiKey::Char(b' ') => self.tui_step(tui, Until::Step),
+            TuiKey::Char(b'n') => self.tui_step(tui, self.until_over()),
+            TuiKey::Char(b'f') => self.tui_step(tui, self.until_finish()),
+            TuiKey::Char(b'c') => self.tui_step(tui, Until::Breakpoint),
+            TuiKey::Char(b'j') | TuiKey::Down => {
+                tui.cursor = starts.get(at + 1).copied().unwrap_or(tui.cursor)
+            }
+            TuiKey::Char(b'k') | TuiKey::Up => tui.cursor = starts[at.saturating_sub(1)],
+            TuiKey::Char(b'b') if !self.remove_breakpoint(tui.cursor) => {
+                self.add_breakpoint(tui.cursor);
+            }
+            TuiKey::Char(b'm') => tui.prompt = Some(String::new()),
+            TuiKey::Char(b']') if tui.view.range.end < self.mem.len() => {
+                tui.view.range = tui.view.range.start + 16..tui.view.range.end + 16;
+            }
+            TuiKey::Char(b'[') if tui.view.range.start >= 16 => {
+                tui.view.range = tui.view.range.start - 16..tui.view.range.end - 16;
+            }
+            TuiKey::Char(b'q') | TuiKey::Char(0x03) => return false,
+            _ => (),
+        }
+        true
+    }
+
+    /// Full screen terminal interface (`-t`), driven by single key commands.
+    pub(crate) fn tui(&mut self) -> Result<(), anyhow::Error> {
+        let mut tui = TuiState::new(self);
+        let raw = RawMode::enter()?;
+        if raw.saved.is_none() {
+            tui.message = "stty is not available: press Enter after each key".to_string();
+        }
+        loop {
+            self.tui_draw(&tui)?;
+            match read_key()? {
+                Some(key) if self.tui_key(&mut tui, key) => (),
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+fn visible_len(text: &str) -> usize {
+    let mut len = 0;
+    let mut escape = false;
+    for c in text.chars() {
+        match c {
+            '\x1b' => escape = true,
+            'm' if escape => escape = false,
+            _ if escape => (),
+            _ => len += 1,
+        }
+    }
+    len
+}
@@ -0,0 +1,114 @@
+use core::mem::size_of;
+use std::ops::Range;
+
+use crate::{CycleState, FunCode, Machine, OpCode, REG_NAMES, RSP};
+
+impl Machine {
+    /// Reports reads of registers and memory that nothing has written yet, neither the
+    /// loader nor the program, as warnings.
+    pub fn set_check_uninit(&mut self, check_uninit: bool) {
+        self.check_uninit = check_uninit;
+    }
+
+    /// Fills memory with a fixed pseudo-random pattern instead of zeros, so programs that
+    /// depend on uninitialized memory misbehave the same way on every run. Call it before
+    /// loading a program.
+    pub fn fill_garbage(&mut self, seed: u64) {
+        // xorshift64, which needs a non-zero state
+        let mut state = seed | 1;
+        for byte in self.mem.iter_mut() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            *byte = (state >> 56) as u8;
+        }
+    }
+
+    /// Whether the byte at `addr` has been written since the machine was created.
+    pub fn is_initialized(&self, addr: usize) -> bool {
+        self.mem_init.get(addr).copied().unwrap_or(false)
+    }
+
+    pub(crate) fn mark_mem(&mut self, range: Range<usize>) {
+        if let Some(bytes) = self.mem_init.get_mut(range) {
+            bytes.fill(true);
+        }
+    }
+
+    pub(crate) fn mark_reg(&mut self, reg: usize) {
+        if let Some(init) = self.reg_init.get_mut(reg) {
+            *init = true;
+        }
+    }
+
+    // the registers an instruction reads for its result; `xorq %rax, %rax` and friends
+    // do not depend on what they read
+    fn reg_reads(state: &CycleState) -> Vec<usize> {
+        match state.op {
+            OpCode::Opx
+                if state.r_a == state.r_b && matches!(state.fun, FunCode::Xor | FunCode::Sub) =>
+            {
+                Vec::new()
+            }
+            OpCode::Rmmov | OpCode::Opx => vec![state.r_a, state.r_b],
+            OpCode::Cmov => vec![state.r_a],
+            OpCode::Mrmov => vec![state.r_b],
+            OpCode::Call | OpCode::Ret | OpCode::Pop => vec![RSP],
+            OpCode::Push => vec![state.r_a, RSP],
+            _ => Vec::new(),
+        }
+    }
+
+    fn report_uninit(&mut self, what: String, state: &CycleState) {
+        // once per instruction, or loops would repeat it on every pass
+        if !self.uninit_reported.insert(self.pc) {
+            return;
+        }
+        self.warnings.push(format!(
+            "read of uninitialized {} by `{}` at {}",
+            what,
+            self.format_instr(state),
+            self.describe(self.pc)
+        ));
+    }
+
+    pub(crate) fn check_reg_reads(&mut self, state: &CycleState) {
+        if !self.check_uninit {
+            return;
+        }
+        let mut regs: Vec<&str> = Machine::reg_reads(state)
+            .into_iter()
+            .filter(|&reg| !self.reg_init.get(reg).copied().unwrap_or(true))
+            .map(|reg| REG_NAMES[reg])
+            .collect();
+        regs.dedup();
+        if !regs.is_empty() {
+            self.report_uninit(regs.join(", "), state);
+        }
+    }
+
+    pub(crate) fn check_mem_read(&mut self, addr: usize, state: &CycleState) {
+        let len = size_of::<usize>();
+        if !self.check_uninit || addr.checked_add(len).is_none() {
+            return;
+        }
+        if (addr..addr + len).any(|a| a < self.mem.len() && !self.is_initialized(a)) {
+            let what = format!("memory 0x{:04x}..0x{:04x}", addr, addr + len);
+            self.report_uninit(what, state);
+        }
+    }
+
+    pub(crate) fn mark_writeback(&mut self, state: &CycleState) {
+        match state.op {
+            OpCode::Irmov | OpCode::Opx => self.mark_reg(state.r_b),
+            OpCode::Cmov if state.cnd => self.mark_reg(state.r_b),
+            OpCode::Mrmov => self.mark_reg(state.r_a),
+            OpCode::Call | OpCode::Ret | OpCode::Push => self.mark_reg(RSP),
+            OpCode::Pop => {
+                self.mark_reg(RSP);
+                self.mark_reg(state.r_a);
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use y86_rs::{AsmError, Assembler, Program};
+
+fn assemble(asm: &Assembler, source: &str) -> Program {
+    match asm.assemble("main.ys", source) {
+        Ok(program) => program,
+        Err(errors) => panic!("{}", errors[0]),
+    }
+}
+
+fn errors(asm: &Assembler, source: &str) -> Vec<AsmError> {
+    match asm.assemble("main.ys", source) {
+        Ok(_) => panic!("assembled without errors"),
+        Err(errors) => errors,
+    }
+}
+
+// the memory image a program or listing describes, as (address, bytes) pairs
+fn image<'a>(lines: impl Iterator<Item = (usize, &'a [u8])>) -> Vec<u8> {
+    let mut image = Vec::new();
+    for (addr, bytes) in lines {
+        if image.len() < addr + bytes.len() {
+            image.resize(addr + bytes.len(), 0);
+        }
+        image[addr..addr + bytes.len()].copy_from_slice(bytes);
+    }
+    image
+}
+
+fn program_image(program: &Program) -> Vec<u8> {
+    image(
+        program
+            .lines
+            .iter()
+            .filter_map(|l| l.addr.map(|addr| (addr, l.bytes.as_slice()))),
+    )
+}
+
+fn quads(program: &Program) -> Vec<i64> {
+    program_image(program)
+        .chunks(8)
+        .map(|c| i64::from_le_bytes(c.try_into().unwrap()))
+        .collect()
+}
+
+fn symbol(program: &Program, name: &str) -> Option<usize> {
+    program
+        .symbols
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|&(_, addr)| addr)
+}
+
+#[test]
+fn operator_precedence_and_unary_minus() {
+    let program = assemble(
+        &Assembler::new(),
+        "    .quad 1+2*3, (1+2)*3, 7-2-1, 1<<2+1, 6&3|8, 5^1&3
+    .quad -2*-3, -(1-4), ~0, 17%5*2, -8/2
+",
+    );
+    assert_eq!(quads(&program), [7, 9, 4, 8, 10, 4, 6, 3, -1, 4, -4]);
+}
+
+#[test]
+fn equ_refers_forward() {
+    let program = assemble(
+        &Assembler::new(),
+        "    .equ SIZE, end-start
+    .equ WORDS, SIZE/8
+start:
+    .quad SIZE, WORDS
+end:
+",
+    );
+    assert_eq!(quads(&program), [16, 2]);
+
+    let e = errors(&Assembler::new(), "    .equ A, 1\n    .equ A, 2\n");
+    assert_eq!(e[0].to_string(), "main.ys:2:5: `A` is already defined");
+    let e = errors(&Assembler::new(), "    .equ A, B\n    .quad A\n");
+    assert_eq!(
+        e[0].to_string(),
+        "main.ys:1:5: cannot evaluate constant: undefined symbol `B`"
+    );
+}
+
+#[test]
+fn set_can_be_redefined() {
+    let program = assemble(
+        &Assembler::new(),
+        "    .set N, 1
+    .quad N
+    .set N, N+1
+    .quad N
+    .set N, N*10
+    .quad N
+",
+    );
+    assert_eq!(quads(&program), [1, 2, 20]);
+}
+
+#[test]
+fn macro_defaults_and_unique_labels() {
+    let program = assemble(
+        &Assembler::new(),
+        ".macro load r, v=5
+loop\\@:
+    irmovq $\\v, \\r
+    jmp loop\\@
+.endm
+    load %rax
+    load %rbx, 9
+",
+    );
+    assert_eq!(symbol(&program, "loop1"), Some(0));
+    assert_eq!(symbol(&program, "loop2"), Some(19));
+    let image = program_image(&program);
+    assert_eq!(image[..3], [0x30, 0xf0, 5]);
+    assert_eq!(image[10..12], [0x70, 0]);
+    assert_eq!(image[19..22], [0x30, 0xf3, 9]);
+    assert_eq!(image[29..31], [0x70, 19]);
+
+    let e = errors(&Assembler::new(), ".macro two a, b\n.endm\n    two 1\n");
+    assert_eq!(
+        e[0].to_string(),
+        "main.ys:3:5: macro `two` is missing argument `b`"
+    );
+}
+
+#[test]
+fn files_are_included_once() {
+    let mut asm = Assembler::new();
+    asm.add_file("a.ys", ".include \"b.ys\"\na:\n    .quad 1\n");
+    // includes back into a.ys and the top level file
+    asm.add_file(
+        "b.ys",
+        ".include \"a.ys\"\n.include \"main.ys\"\nb:\n    .quad 2\n",
+    );
+    let main = ".include \"a.ys\"\n.include \"b.ys\"\n.include \"a.ys\"\n    .quad a, b\n";
+    asm.add_file("main.ys", main);
+    let program = assemble(&asm, main);
+    assert_eq!(quads(&program), [2, 1, 8, 0]);
+}
+
+#[test]
+fn dollar_immediates_take_expressions() {
+    let program = assemble(
+        &Assembler::new(),
+        "    .equ LEN, 4
+    irmovq $LEN*8-1, %rax
+    irmovq $-LEN, %rbx
+    irmovq $(LEN+1)*-2, %rcx
+    rmmovq %rax, LEN*2(%rsp)
+",
+    );
+    let image = program_image(&program);
+    let imm = |at: usize| i64::from_le_bytes(image[at..at + 8].try_into().unwrap());
+    assert_eq!(imm(2), 31);
+    assert_eq!(imm(12), -4);
+    assert_eq!(imm(22), -10);
+    assert_eq!(image[30..32], [0x40, 0x04]);
+    assert_eq!(imm(32), 8);
+}
+
+#[test]
+fn errors_point_into_included_files() {
+    let mut asm = Assembler::new();
+    asm.add_file(
+        "lib.ys",
+        "    nop\n  done:  bogus %rax # comment\n\tstray\n    .include nothing\n",
+    );
+    let e = errors(&asm, "    nop\n    .include \"lib.ys\"\n    halt\n");
+    let found: Vec<(&str, usize, usize)> = e
+        .iter()
+        .map(|e| (e.file.as_str(), e.line, e.column))
+        .collect();
+    assert_eq!(
+        found,
+        [("lib.ys", 4, 5), ("lib.ys", 2, 10), ("lib.ys", 3, 2)]
+    );
+    assert_eq!(e[1].message, "unknown instruction `bogus`");
+    assert_eq!(e[2].to_string(), "lib.ys:3:2: unknown instruction `stray`");
+}
+
+// assembles each bundled .ys example and compares its bytes with the reference .yo
+#[test]
+fn examples_match_their_listings() {
+    for name in ["mov-op", "push-pop", "y86-64-translate-goto"] {
+        let path = format!("{}/yo-files/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let program = match Assembler::new().assemble_file(&format!("{}.ys", path)) {
+            Ok(program) => program,
+            Err(errors) => panic!("{}", errors[0]),
+        };
+        let listing = std::fs::read_to_string(format!("{}.yo", path)).unwrap();
+        let lines: Vec<(usize, Vec<u8>)> = listing
+            .lines()
+            .filter_map(|line| {
+                let (addr, bytes) = line.split('|').next()?.split_once(':')?;
+                let addr = usize::from_str_radix(addr.trim().trim_start_matches("0x"), 16);
+                let bytes = (0..bytes.trim().len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&bytes.trim()[i..i + 2], 16).unwrap())
+                    .collect();
+                Some((addr.unwrap(), bytes))
+            })
+            .collect();
+        let expected = image(lines.iter().map(|(addr, bytes)| (*addr, bytes.as_slice())));
+        assert_eq!(program_image(&program), expected, "{}", name);
+    }
+}
@@ -0,0 +1,158 @@
+use std::io;
+
+use y86_rs::{
+    Assembler, Cache, CacheConfig, Console, CycleState, InstructionExtension, Machine, StepMode,
+};
+
+fn cache(spec: &str) -> Cache {
+    Cache::new(CacheConfig::parse(spec).unwrap())
+}
+
+// the address of the block each access evicted, if any
+fn evictions(cache: &mut Cache, accesses: &[(usize, bool)]) -> Vec<Option<usize>> {
+    accesses
+        .iter()
+        .map(|&(addr, write)| cache.access(addr, write).evicted.map(|(addr, _)| addr))
+        .collect()
+}
+
+#[test]
+fn oversized_configs_are_rejected() {
+    assert_eq!(
+        CacheConfig::parse("18014398509481984k:1:8").unwrap_err(),
+        "size `18014398509481984k` is too large"
+    );
+    assert!(CacheConfig::parse("1k:9223372036854775808:2").is_err());
+    assert!(CacheConfig::parse("1k:3:8").is_err());
+    assert!(CacheConfig::parse("1k:2:24").is_err());
+}
+
+#[test]
+fn lru_evicts_the_least_recently_used() {
+    let mut lru = cache("16:2:8:lru");
+    let accesses = [(0, false), (8, false), (0, false), (16, false)];
+    assert_eq!(evictions(&mut lru, &accesses), [None, None, None, Some(8)]);
+    assert_eq!(lru.stats().hits, 1);
+}
+
+#[test]
+fn fifo_evicts_the_oldest_fill() {
+    let mut fifo = cache("16:2:8:fifo");
+    let accesses = [(0, false), (8, false), (0, false), (16, false)];
+    assert_eq!(evictions(&mut fifo, &accesses), [None, None, None, Some(0)]);
+    assert_eq!(fifo.stats().hits, 1);
+}
+
+#[test]
+fn write_through_does_not_allocate() {
+    let mut cache = cache("16:2:8:lru:wt");
+    assert!(!cache.access(0, true).hit);
+    assert!(!cache.access(0, false).hit);
+    assert!(cache.access(0, true).hit);
+    let stats = cache.stats();
+    assert_eq!((stats.writes, stats.memory_writes), (2, 2));
+    assert_eq!((stats.hits, stats.misses, stats.writebacks), (1, 2, 0));
+}
+
+#[test]
+fn dirty_lines_are_written_back() {
+    // direct mapped: 0 and 16 share set 0
+    let mut cache = cache("16:1:8:lru:wb");
+    assert_eq!(cache.access(0, true).evicted, None);
+    assert_eq!(cache.access(16, false).evicted, Some((0, true)));
+    assert_eq!(cache.access(0, false).evicted, Some((16, false)));
+    assert_eq!(cache.access(16, true).evicted, Some((0, false)));
+    let stats = cache.stats();
+    assert_eq!(
+        (stats.evictions, stats.writebacks, stats.memory_writes),
+        (3, 1, 0)
+    );
+}
+
+#[test]
+fn addresses_split_into_set_and_tag() {
+    let mut cache = cache("64:2:8");
+    assert_eq!(
+        cache.config().to_string(),
+        "64 bytes, 2-way, 4 sets of 8-byte blocks, LRU replacement, write-back"
+    );
+    // blocks 1, 5 and 9 all map to set 1, with tags 0, 1 and 2
+    for addr in [0x08, 0x28] {
+        assert_eq!(cache.access(addr, false).set, 1);
+    }
+    let outcome = cache.access(0x48, false);
+    assert_eq!(
+        (outcome.hit, outcome.set, outcome.evicted),
+        (false, 1, Some((0x08, false)))
+    );
+    // the rest of block 9, and a neighbour in set 2
+    assert!(cache.access(0x4f, false).hit);
+    assert_eq!(cache.access(0x50, false).set, 2);
+    assert!(cache.access(0x2f, false).hit);
+}
+
+// `swapm rA, D` (d0 rF D): swaps rA with the word at D, through the data cache
+struct Swap;
+
+impl InstructionExtension for Swap {
+    fn claims(&self, icode: u8, ifun: u8) -> bool {
+        icode == 0xd && ifun == 0
+    }
+
+    fn length(&self, _icode: u8, _ifun: u8) -> usize {
+        10
+    }
+
+    fn decode(&self, machine: &Machine, state: &mut CycleState) -> Result<(), anyhow::Error> {
+        state.val_a = machine.get_reg(state.r_a)?;
+        Ok(())
+    }
+
+    fn memory(&self, machine: &mut Machine, state: &mut CycleState) -> Result<(), anyhow::Error> {
+        let addr = state.val_c as usize;
+        state.val_m = machine.read_data(addr)?;
+        machine.write_data(addr, state.val_a)
+    }
+
+    fn writeback(
+        &self,
+        machine: &mut Machine,
+        state: &mut CycleState,
+    ) -> Result<(), anyhow::Error> {
+        machine.set_reg(state.r_a, state.val_m)
+    }
+}
+
+#[test]
+fn extension_and_trap_accesses_use_the_dcache() {
+    let program = Assembler::new()
+        .assemble(
+            "test.ys",
+            "    irmovq $0x41, %rax
+    .byte 0xd0, 0x0f
+    .quad value
+    .byte 0xc0, 0x0f # putc %rax
+    mrmovq value, %rbx
+    halt
+    .pos 0x40
+value:
+    .quad 7
+",
+        )
+        .unwrap();
+    let mut machine = Machine::with_extensions(
+        0x100,
+        StepMode::NoStep,
+        vec![Box::new(Swap), Box::new(Console::new(Box::new(io::sink())))],
+    );
+    machine.load_program(&program).unwrap();
+    machine.set_caches(None, Some(CacheConfig::parse("64:1:16").unwrap()));
+    machine.run().unwrap();
+    assert_eq!(machine.get_reg(0).unwrap(), 7);
+    assert_eq!(machine.get_reg(3).unwrap(), 0x41);
+
+    // the swap misses and then hits on its write, `mrmovq` hits and `putc` is not counted
+    let stats = machine.dcache().unwrap().stats();
+    assert_eq!((stats.reads, stats.writes), (2, 1));
+    assert_eq!((stats.hits, stats.misses), (2, 1));
+}
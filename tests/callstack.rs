@@ -0,0 +1,135 @@
+use y86_rs::{Assembler, CallCheck, Machine, StepMode};
+
+fn machine(source: &str) -> Machine {
+    let program = match Assembler::new().assemble("test.ys", source) {
+        Ok(program) => program,
+        Err(errors) => panic!("{}", errors[0]),
+    };
+    let mut machine = Machine::new(1 << 10, StepMode::NoStep);
+    machine.load_program(&program).unwrap();
+    machine
+}
+
+// every warning, in order, from running `machine` to the end
+fn warnings(machine: &mut Machine) -> Vec<String> {
+    let mut warnings = Vec::new();
+    while machine.is_running() {
+        machine.step().unwrap();
+        warnings.extend(machine.take_warnings());
+    }
+    warnings
+}
+
+#[test]
+fn mismatched_ret_is_reported() {
+    // f replaces its return address, so its ret lands in `elsewhere`
+    let mut machine = machine(
+        "main:
+    irmovq stack, %rsp
+    call f
+    halt
+f:
+    popq %rax
+    irmovq elsewhere, %rax
+    pushq %rax
+    ret
+elsewhere:
+    halt
+    .pos 0x200
+stack:
+",
+    );
+    assert_eq!(
+        warnings(&mut machine),
+        ["ret at 0x0022 <f+0xe> returns to 0x0023 <elsewhere>, but expected 0x0013 <main+0x13> (corrupted return address?)"]
+    );
+    assert!(machine.frames().is_empty());
+}
+
+#[test]
+fn faults_carry_a_backtrace() {
+    let mut machine = machine(
+        "main:
+    irmovq stack, %rsp
+    call f
+    halt
+f:
+    call g
+    ret
+g:
+    .byte 0xf0
+    .pos 0x200
+stack:
+",
+    );
+    let e = loop {
+        if let Err(e) = machine.step() {
+            break e.to_string();
+        }
+    };
+    assert!(
+        e.ends_with(
+            "backtrace:
+#0  0x001e <g>
+#1  0x001d <f+0x9>  (call at 0x0014)
+#2  0x0013 <main+0x13>  (call at 0x000a)
+"
+        ),
+        "{}",
+        e
+    );
+}
+
+const CLOBBER: &str = "main:
+    irmovq stack, %rsp
+    irmovq $1, %rbx
+    call f
+    halt
+f:
+    irmovq $2, %rbx
+    ret
+    .pos 0x200
+stack:
+";
+
+#[test]
+fn clobbered_callee_saved_register_is_flagged() {
+    let mut warn = machine(CLOBBER);
+    warn.set_call_check(CallCheck::Warn);
+    let violation = "0x001e <f> (called from 0x0014 <main+0x14>) did not preserve %rbx 0x1 -> 0x2";
+    // warnings leave the run going to the end
+    assert_eq!(warnings(&mut warn), [violation]);
+    assert_eq!(warn.get_reg(3).unwrap(), 2);
+
+    let mut stop = machine(CLOBBER);
+    stop.set_call_check(CallCheck::Stop);
+    let e = stop.run().unwrap_err().to_string();
+    assert!(
+        e.starts_with(&format!("{}\nat 0x0028:\n", violation)),
+        "{}",
+        e
+    );
+}
+
+#[test]
+fn saved_and_restored_registers_are_clean() {
+    let mut machine = machine(
+        "main:
+    irmovq stack, %rsp
+    irmovq $1, %rbx
+    call f
+    halt
+f:
+    pushq %rbx
+    irmovq $2, %rbx
+    addq %rbx, %rax
+    popq %rbx
+    ret
+    .pos 0x200
+stack:
+",
+    );
+    machine.set_call_check(CallCheck::Stop);
+    assert_eq!(warnings(&mut machine), Vec::<String>::new());
+    assert_eq!(machine.get_reg(3).unwrap(), 1);
+}
@@ -0,0 +1,74 @@
+use y86_rs::{Assembler, Coverage, Machine, StepMode};
+
+const PROGRAM: &str = "    irmovq $2, %rcx
+    irmovq $1, %rdx
+loop:
+    subq %rdx, %rcx
+    jne loop
+    je end
+    irmovq $9, %rax
+end:
+    .include \"lib.ys\"
+    .quad 5
+";
+
+// runs after `subq` left ZF set, so `cmovl` never moves
+const LIB: &str = "    cmovl %rdx, %rax
+    halt
+";
+
+fn run() -> (Machine, Coverage) {
+    let mut asm = Assembler::new();
+    asm.add_file("lib.ys", LIB);
+    let program = asm.assemble("main.ys", PROGRAM).unwrap();
+    let mut machine = Machine::new(1 << 10, StepMode::NoStep);
+    machine.load_program(&program).unwrap();
+    let mut coverage = Coverage::new();
+    machine
+        .run_with(|_, pc, state| coverage.record(pc, state))
+        .unwrap();
+    (machine, coverage)
+}
+
+#[test]
+fn report_lists_missed_lines_and_one_way_branches() {
+    let (machine, coverage) = run();
+    assert!(coverage.is_executed(0));
+    assert_eq!(
+        coverage.report(&machine),
+        "Coverage: 6/7 lines (85.71%), 4/6 branch outcomes (66.67%)
+not run:    7 |    irmovq $9, %rax
+partial:    6 | 0x001f <loop+0xb>: je end (condition never failed)
+partial:    9 | 0x0032 <end>: cmovl %rdx, %rax (condition never held)
+"
+    );
+}
+
+#[test]
+fn lcov_counts_included_code_on_the_include_line() {
+    let (machine, coverage) = run();
+    assert_eq!(
+        coverage.to_lcov(&machine, "main.ys"),
+        "TN:
+SF:main.ys
+BRDA:5,0,0,1
+BRDA:5,0,1,1
+BRDA:6,0,0,1
+BRDA:6,0,1,0
+BRDA:9,0,0,0
+BRDA:9,0,1,1
+BRF:6
+BRH:4
+DA:1,1
+DA:2,1
+DA:4,2
+DA:5,2
+DA:6,1
+DA:7,0
+DA:9,1
+LF:7
+LH:6
+end_of_record
+"
+    );
+}
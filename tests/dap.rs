@@ -0,0 +1,156 @@
+use std::io::Cursor;
+
+use y86_rs::DapServer;
+
+fn request(seq: usize, command: &str, arguments: &str) -> String {
+    let body = format!(
+        "{{\"seq\":{},\"type\":\"request\",\"command\":\"{}\",\"arguments\":{}}}",
+        seq, command, arguments
+    );
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+// runs a session over `requests`, each given as (command, arguments), and returns the
+// bodies of every message sent back
+fn session(requests: &[(&str, &str)]) -> Vec<String> {
+    let input: String = requests
+        .iter()
+        .enumerate()
+        .map(|(i, (command, args))| request(i + 1, command, args))
+        .collect();
+    serve(input).unwrap()
+}
+
+fn serve(input: String) -> Result<Vec<String>, String> {
+    let mut output = Vec::new();
+    DapServer::new(1 << 12)
+        .serve(&mut Cursor::new(input), &mut output)
+        .map_err(|e| e.to_string())?;
+    let mut output = String::from_utf8(output).unwrap();
+    let mut messages = Vec::new();
+    while let Some(rest) = output.strip_prefix("Content-Length: ") {
+        let (len, rest) = rest.split_once("\r\n\r\n").unwrap();
+        let (body, rest) = rest.split_at(len.parse().unwrap());
+        messages.push(body.to_string());
+        output = rest.to_string();
+    }
+    assert!(output.is_empty(), "{}", output);
+    Ok(messages)
+}
+
+// the response to the request numbered `seq`
+fn response(messages: &[String], seq: usize) -> &str {
+    let key = format!("\"request_seq\":{},", seq);
+    messages.iter().find(|m| m.contains(&key)).unwrap()
+}
+
+fn program(name: &str, source: &str) -> String {
+    let path = std::env::temp_dir().join(format!("y86-dap-{}-{}", std::process::id(), name));
+    std::fs::write(&path, source).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn launch(path: &str) -> String {
+    format!("{{\"program\":\"{}\",\"stopOnEntry\":true}}", path)
+}
+
+#[test]
+fn runs_to_a_breakpoint_and_exits() {
+    let path = program(
+        "run.ys",
+        "    irmovq $1, %rax
+    irmovq $2, %rbx
+    halt
+",
+    );
+    let breakpoints = format!(
+        "{{\"source\":{{\"path\":\"{}\"}},\"breakpoints\":[{{\"line\":2}}]}}",
+        path
+    );
+    let messages = session(&[
+        ("initialize", "{}"),
+        ("launch", &launch(&path)),
+        ("setBreakpoints", &breakpoints),
+        ("configurationDone", "{}"),
+        ("continue", "{}"),
+        ("variables", "{\"variablesReference\":1}"),
+        ("continue", "{}"),
+        ("continue", "{}"),
+    ]);
+    assert!(response(&messages, 1).contains("\"supportsConfigurationDoneRequest\":true"));
+    assert!(response(&messages, 2).contains("\"success\":true"));
+    assert!(response(&messages, 3).contains("\"verified\":true"));
+    let stops: Vec<&String> = messages
+        .iter()
+        .filter(|m| m.contains("\"event\":\"stopped\""))
+        .collect();
+    assert!(stops[0].contains("\"reason\":\"entry\""));
+    assert!(stops[1].contains("\"reason\":\"breakpoint\""));
+    assert!(response(&messages, 6).contains(
+        "{\"name\":\"%rax\",\"value\":\"0x0000000000000001 (1)\",\"variablesReference\":0}"
+    ));
+    assert!(messages.iter().any(|m| m.contains("\"event\":\"exited\"")));
+    assert!(response(&messages, 8).contains("\"message\":\"the program is not running\""));
+}
+
+#[test]
+fn pause_is_refused() {
+    let path = program("pause.ys", "    halt\n");
+    let messages = session(&[
+        ("launch", &launch(&path)),
+        ("configurationDone", "{}"),
+        ("pause", "{\"threadId\":1}"),
+    ]);
+    assert!(response(&messages, 3).contains("\"success\":false"));
+}
+
+#[test]
+fn stack_variables_stop_at_the_end_of_memory() {
+    let path = program(
+        "stack.ys",
+        "    irmovq $-8, %rsp
+    irmovq $0xff0, %rbx
+    rrmovq %rbx, %rsp
+    halt
+",
+    );
+    let messages = session(&[
+        ("launch", &launch(&path)),
+        ("configurationDone", "{}"),
+        ("next", "{}"),
+        ("variables", "{\"variablesReference\":3}"),
+        ("next", "{}"),
+        ("next", "{}"),
+        ("variables", "{\"variablesReference\":3}"),
+    ]);
+    // %rsp at the very top of the address space has nothing above it
+    assert!(response(&messages, 4).contains("\"variables\":[]"));
+    // two words left below the end of a 4k memory
+    let stack = response(&messages, 7);
+    assert!(stack.contains("%rsp+0x8 (0x0ff8)"));
+    assert!(!stack.contains("%rsp+0x10"));
+}
+
+#[test]
+fn malformed_messages_are_skipped() {
+    let input = format!(
+        "Content-Length: 9\r\n\r\n{{not json{}",
+        request(2, "initialize", "{}")
+    );
+    let messages = serve(input).unwrap();
+    assert_eq!(messages.len(), 1);
+    assert!(
+        messages[0].contains("\"request_seq\":2,"),
+        "{}",
+        messages[0]
+    );
+}
+
+#[test]
+fn oversized_messages_are_refused() {
+    let input = "Content-Length: 1000000000000\r\n\r\n{}".to_string();
+    assert_eq!(
+        serve(input).unwrap_err(),
+        "message of 1000000000000 bytes is too large"
+    );
+}
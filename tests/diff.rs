@@ -0,0 +1,73 @@
+use y86_rs::{Assembler, Machine, StepMode};
+
+const PROGRAM: &str = "    irmovq $0x2a, %rax
+    rmmovq %rax, value
+    subq %rax, %rax
+    halt
+    .pos 0x40
+value:
+    .quad 7
+";
+
+fn machine() -> Machine {
+    let program = Assembler::new().assemble("test.ys", PROGRAM).unwrap();
+    let mut machine = Machine::new(0x100, StepMode::NoStep);
+    machine.load_program(&program).unwrap();
+    machine
+}
+
+// the diff of every cycle to the end
+fn diffs(machine: &mut Machine, color: bool) -> Vec<String> {
+    let mut diffs = Vec::new();
+    while machine.is_running() {
+        let before = machine.snapshot();
+        machine.step().unwrap();
+        diffs.push(machine.diff(&before, color));
+    }
+    diffs
+}
+
+#[test]
+fn each_cycle_lists_what_it_changed() {
+    assert_eq!(
+        diffs(&mut machine(), false),
+        [
+            "cycle 1: 0x0000: irmovq $0x2a, %rax
+* %rax: 0x0000000000000000 -> 0x000000000000002a
+",
+            "cycle 2: 0x000a: rmmovq %rax, 0x40
+* 0x0040: 0700000000000000 -> 2a00000000000000 <value>
+",
+            "cycle 3: 0x0014: subq %rax, %rax
+* %rax: 0x000000000000002a -> 0x0000000000000000
+* flags: SF: 0\tZF: 0\tOF: 0 -> SF: 0\tZF: 1\tOF: 0
+",
+            "cycle 4: 0x0016: halt
+* STAT: HLT
+",
+        ]
+    );
+}
+
+#[test]
+fn diff_spans_several_cycles() {
+    let mut machine = machine();
+    let before = machine.snapshot();
+    machine.step().unwrap();
+    machine.step().unwrap();
+    // named after the first instruction since `before`, with everything changed since
+    assert_eq!(
+        machine.diff(&before, false),
+        "cycle 2: 0x0000: irmovq $0x2a, %rax
+* %rax: 0x0000000000000000 -> 0x000000000000002a
+* 0x0040: 0700000000000000 -> 2a00000000000000 <value>
+"
+    );
+    assert_eq!(
+        machine.diff(&before, true),
+        "cycle 2: 0x0000: irmovq $0x2a, %rax
+  %rax: \x1b[2m0x0000000000000000\x1b[0m -> \x1b[1;33m0x000000000000002a\x1b[0m
+  0x0040: \x1b[2m0700000000000000\x1b[0m -> \x1b[1;33m2a00000000000000\x1b[0m <value>
+"
+    );
+}
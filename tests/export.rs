@@ -0,0 +1,101 @@
+use y86_rs::{Assembler, DumpFormat, LoadMode, Machine, StepMode};
+
+const MEM: usize = 0x200;
+
+// a run of repeated rows for the hexdump to collapse, text for its ASCII column and a label
+// in the middle of a word
+const PROGRAM: &str = "    irmovq $0x4142434445464748, %rax
+    rmmovq %rax, 0x100
+    halt
+    .pos 0x40
+table:
+    .quad 1, 1, 1, 1, 1, 1, 1, 1
+    .byte 0x7f
+inside:
+    .byte 0x20, 0xff
+";
+
+fn run() -> Machine {
+    let program = Assembler::new().assemble("test.ys", PROGRAM).unwrap();
+    let mut machine = Machine::new(MEM, StepMode::NoStep);
+    machine.load_program(&program).unwrap();
+    machine.run().unwrap();
+    machine
+}
+
+fn memory(machine: &Machine) -> Vec<u8> {
+    machine.dump(0..MEM, DumpFormat::Binary).unwrap()
+}
+
+// reads a `hexdump -C` style dump back, repeating the line before each `*`
+fn parse_hexdump(text: &str) -> (usize, Vec<u8>) {
+    let mut lines: Vec<(usize, Vec<u8>)> = Vec::new();
+    let mut end = 0;
+    let mut repeat = false;
+    for line in text.lines() {
+        if line == "*" {
+            repeat = true;
+            continue;
+        }
+        let (addr, rest) = line.split_once("  ").unwrap_or((line, ""));
+        let addr = usize::from_str_radix(addr, 16).unwrap();
+        if repeat {
+            let (last, bytes) = lines.last().cloned().unwrap();
+            lines.extend((last + 16..addr).step_by(16).map(|a| (a, bytes.clone())));
+            repeat = false;
+        }
+        end = addr;
+        let hex = rest.split('|').next().unwrap();
+        let bytes = hex
+            .split_whitespace()
+            .map(|b| u8::from_str_radix(b, 16).unwrap())
+            .collect();
+        lines.push((addr, bytes));
+    }
+    let start = lines[0].0;
+    let bytes: Vec<u8> = lines.into_iter().flat_map(|(_, bytes)| bytes).collect();
+    assert_eq!(start + bytes.len(), end);
+    (start, bytes)
+}
+
+#[test]
+fn yo_round_trip() {
+    let machine = run();
+    let listing = machine.dump_yo(0..MEM).unwrap();
+    let mut reloaded = Machine::new(MEM, StepMode::NoStep);
+    assert_eq!(reloaded.load_with(&listing, LoadMode::Fail).unwrap(), []);
+    assert_eq!(memory(&reloaded), memory(&machine));
+    assert_eq!(reloaded.symbols().lookup("table"), Some(0x40));
+    assert_eq!(reloaded.symbols().lookup("inside"), Some(0x81));
+}
+
+#[test]
+fn binary_round_trip() {
+    let machine = run();
+    let mut reloaded = Machine::new(MEM, StepMode::NoStep);
+    reloaded
+        .load_binary(&machine.dump(0..MEM, DumpFormat::Binary).unwrap(), 0)
+        .unwrap();
+    assert_eq!(memory(&reloaded), memory(&machine));
+
+    // part of memory, loaded back where it came from
+    let mut part = Machine::new(MEM, StepMode::NoStep);
+    part.load_binary(
+        &machine.dump(0x40..0x108, DumpFormat::Binary).unwrap(),
+        0x40,
+    )
+    .unwrap();
+    assert_eq!(memory(&part)[0x40..0x108], memory(&machine)[0x40..0x108]);
+    assert_eq!(part.get_mem_word(0).unwrap(), 0);
+}
+
+#[test]
+fn hexdump_round_trip() {
+    let machine = run();
+    let dump = machine.hexdump(0..MEM).unwrap();
+    assert!(dump.contains("\n*\n00000080  7f 20 ff 00"));
+    let (start, bytes) = parse_hexdump(&dump);
+    let mut reloaded = Machine::new(MEM, StepMode::NoStep);
+    reloaded.load_binary(&bytes, start).unwrap();
+    assert_eq!(memory(&reloaded), memory(&machine));
+}
@@ -0,0 +1,70 @@
+use std::{cell::RefCell, rc::Rc};
+
+use y86_rs::{Assembler, CycleState, InstructionExtension, Machine, StepMode};
+
+const RAX: usize = 0;
+
+// `incq rB` (d0 Fr): adds one to rB, and disassembles its own address while executing
+struct Inc {
+    seen: Rc<RefCell<Vec<String>>>,
+}
+
+impl InstructionExtension for Inc {
+    fn claims(&self, icode: u8, ifun: u8) -> bool {
+        icode == 0xd && ifun == 0
+    }
+
+    fn length(&self, _icode: u8, _ifun: u8) -> usize {
+        2
+    }
+
+    fn mnemonic(&self, _icode: u8, _ifun: u8) -> String {
+        "incq".to_string()
+    }
+
+    fn decode(&self, machine: &Machine, state: &mut CycleState) -> Result<(), anyhow::Error> {
+        state.val_b = machine.get_reg(state.r_b)?;
+        Ok(())
+    }
+
+    fn execute(&self, machine: &mut Machine, state: &mut CycleState) -> Result<(), anyhow::Error> {
+        state.val_e = state.val_b + 1;
+        let (text, len) = machine.disassemble(0)?;
+        self.seen.borrow_mut().push(format!("{} {}", text, len));
+        Ok(())
+    }
+
+    fn writeback(
+        &self,
+        machine: &mut Machine,
+        state: &mut CycleState,
+    ) -> Result<(), anyhow::Error> {
+        machine.set_reg(state.r_b, state.val_e)
+    }
+}
+
+#[test]
+fn custom_instruction_runs_through_every_stage() {
+    let program = Assembler::new()
+        .assemble(
+            "test.ys",
+            "    .byte 0xd0, 0xf0
+    .byte 0xd0, 0xf0
+    halt
+",
+        )
+        .unwrap();
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let mut machine = Machine::with_extensions(
+        1 << 10,
+        StepMode::NoStep,
+        vec![Box::new(Inc { seen: seen.clone() })],
+    );
+    machine.load_program(&program).unwrap();
+
+    assert_eq!(machine.disassemble(0).unwrap(), ("incq".to_string(), 2));
+    machine.run().unwrap();
+    assert_eq!(machine.get_reg(RAX).unwrap(), 2);
+    // the extension could still decode itself while it was running
+    assert_eq!(*seen.borrow(), ["incq 2", "incq 2"]);
+}
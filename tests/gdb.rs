@@ -0,0 +1,122 @@
+use y86_rs::{Assembler, GdbStub, Machine, StepMode};
+
+const PROGRAM: &str = "    irmovq $5, %rax
+    irmovq $0x10, %rbx
+    halt
+";
+
+fn machine(source: &str) -> Machine {
+    let program = Assembler::new().assemble("test.ys", source).unwrap();
+    let mut machine = Machine::new(1 << 10, StepMode::NoStep);
+    machine.load_program(&program).unwrap();
+    machine
+}
+
+fn packet(data: &str) -> Vec<u8> {
+    let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    format!("${}#{:02x}", data, sum).into_bytes()
+}
+
+// everything the stub wrote in reply to `input`
+fn session(machine: &mut Machine, input: Vec<u8>) -> String {
+    let mut output = Vec::new();
+    GdbStub::new(machine)
+        .serve(std::io::Cursor::new(input), &mut output)
+        .unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+// the data of each reply packet, checking the acks and checksums along the way
+fn replies(machine: &mut Machine, packets: &[&str]) -> Vec<String> {
+    let input = packets.iter().flat_map(|p| packet(p)).collect();
+    let output = session(machine, input);
+    let mut replies = Vec::new();
+    for reply in output.split('+').skip(1) {
+        let (data, sum) = reply.strip_prefix('$').unwrap().split_once('#').unwrap();
+        assert_eq!(packet(data), reply.as_bytes(), "checksum of {:?}", sum);
+        replies.push(data.to_string());
+    }
+    assert_eq!(replies.len(), packets.len(), "{}", output);
+    replies
+}
+
+#[test]
+fn registers_and_stepping() {
+    let mut machine = machine(PROGRAM);
+    let replies = replies(&mut machine, &["?", "s", "g", "p10", "s", "p3"]);
+    assert_eq!(replies[0], "S05");
+    assert_eq!(replies[1], "S05");
+    // rax, then rcx through r14, pc and the flags
+    let regs = &replies[2];
+    assert_eq!(regs.len(), (15 * 8 + 8 + 4) * 2);
+    assert_eq!(&regs[..16], "0500000000000000");
+    assert_eq!(&regs[15 * 16..16 * 16], "0a00000000000000");
+    assert_eq!(replies[3], "00000000");
+    assert_eq!(replies[5], "1000000000000000");
+}
+
+#[test]
+fn memory_reads_and_writes() {
+    let mut machine = machine(PROGRAM);
+    let replies = replies(
+        &mut machine,
+        &["m0,2", "M100,2:abcd", "m100,2", "m3ff,2", "M3ff,2:0000"],
+    );
+    assert_eq!(replies, ["30f0", "OK", "abcd", "E01", "E01"]);
+    assert_eq!(machine.get_mem_word(0x100).unwrap(), 0xcdab);
+}
+
+#[test]
+fn breakpoints_and_continue() {
+    let mut machine = machine(PROGRAM);
+    let replies = replies(&mut machine, &["Z0,a,1", "c", "pf", "z0,a,1", "c", "?"]);
+    assert_eq!(replies[0], "OK");
+    assert_eq!(replies[1], "T05swbreak:;");
+    assert_eq!(replies[2], "0a00000000000000");
+    assert_eq!(replies[3], "OK");
+    assert_eq!(replies[4], "W00");
+    assert_eq!(replies[5], "W00");
+    assert_eq!(machine.get_reg(3).unwrap(), 0x10);
+}
+
+#[test]
+fn continue_stops_on_interrupt() {
+    let mut machine = machine("loop:\n    jmp loop\n");
+    let mut input = packet("c");
+    input.push(0x03);
+    input.extend(packet("pf"));
+    let output = session(&mut machine, input);
+    assert_eq!(output, "+$S02#b5+$0000000000000000#00");
+}
+
+#[test]
+fn bad_checksum_is_nacked() {
+    let mut machine = machine(PROGRAM);
+    let mut input = b"$s#00".to_vec();
+    input.extend(packet("?"));
+    let output = session(&mut machine, input);
+    assert_eq!(output, "-+$S05#b8");
+    assert_eq!(machine.pc(), 0);
+}
+
+#[test]
+fn target_description_in_pieces() {
+    let mut machine = machine(PROGRAM);
+    let replies = replies(
+        &mut machine,
+        &[
+            "qXfer:features:read:target.xml:0,10",
+            "qXfer:features:read:target.xml:10,ffffffffffffffff",
+            "qXfer:features:read:target.xml:ffffffffffffffff,10",
+            "qXfer:features:read:target.xml:0",
+        ],
+    );
+    // offsets and lengths are hex
+    assert_eq!(replies[0], "m<?xml version=\"1");
+    // the rest, however long a read is asked for
+    assert!(replies[1].starts_with("l.0\"?>"), "{}", replies[1]);
+    assert!(replies[1].ends_with("</target>"), "{}", replies[1]);
+    // past the end there is nothing left
+    assert_eq!(replies[2], "l");
+    assert_eq!(replies[3], "E00");
+}
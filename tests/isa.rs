@@ -0,0 +1,108 @@
+use y86_rs::{Assembler, Machine, StepMode};
+
+const RAX: usize = 0;
+const RCX: usize = 1;
+const RDX: usize = 2;
+const RSP: usize = 4;
+
+// runs `source` to its halt after seeding the registers in `regs`
+fn run(source: &str, regs: &[(usize, isize)]) -> Machine {
+    let program = match Assembler::new().assemble("test.ys", source) {
+        Ok(program) => program,
+        Err(errors) => panic!("{}", errors[0]),
+    };
+    let mut machine = Machine::new(1 << 12, StepMode::NoStep);
+    machine.load_program(&program).unwrap();
+    for &(reg, val) in regs {
+        machine.set_reg(reg, val).unwrap();
+    }
+    machine.run().unwrap();
+    machine
+}
+
+// (a, b) pairs for `a - b` on the boundaries of the signed comparisons: equal, one either
+// side, and the subtractions that overflow
+const COMPARES: [(isize, isize); 7] = [
+    (1, 1),
+    (0, 1),
+    (2, 1),
+    (-1, -1),
+    (isize::MIN, 1),
+    (isize::MAX, -1),
+    (isize::MIN, isize::MAX),
+];
+
+fn jump_taken(jump: &str, a: isize, b: isize) -> bool {
+    let source = format!(
+        "    subq %rdx, %rax
+    {jump} yes
+    halt
+yes:
+    irmovq $1, %rcx
+    halt
+"
+    );
+    run(&source, &[(RAX, a), (RDX, b)]).get_reg(RCX).unwrap() == 1
+}
+
+#[test]
+fn jge_on_boundaries() {
+    for (a, b) in COMPARES {
+        assert_eq!(jump_taken("jge", a, b), a >= b, "{a} >= {b}");
+    }
+}
+
+#[test]
+fn jg_on_boundaries() {
+    for (a, b) in COMPARES {
+        assert_eq!(jump_taken("jg", a, b), a > b, "{a} > {b}");
+    }
+}
+
+#[test]
+fn cmovge_on_boundaries() {
+    for (a, b) in COMPARES {
+        let machine = run(
+            "    subq %rdx, %rax
+    irmovq $1, %rdx
+    cmovge %rdx, %rcx
+    halt
+",
+            &[(RAX, a), (RDX, b)],
+        );
+        assert_eq!(machine.get_reg(RCX).unwrap() == 1, a >= b, "{a} >= {b}");
+    }
+}
+
+#[test]
+fn popq_loads_from_the_stack() {
+    let machine = run(
+        "    irmovq stack, %rsp
+    irmovq $0x1234, %rdx
+    pushq %rdx
+    popq %rax
+    halt
+    .pos 0x200
+stack:
+",
+        &[],
+    );
+    assert_eq!(machine.get_reg(RAX).unwrap(), 0x1234);
+    assert_eq!(machine.get_reg(RSP).unwrap(), 0x200);
+}
+
+#[test]
+fn popq_rsp_takes_the_popped_value() {
+    let machine = run(
+        "    irmovq stack, %rsp
+    irmovq $0x5678, %rdx
+    pushq %rdx
+    popq %rsp
+    halt
+    .pos 0x200
+stack:
+",
+        &[],
+    );
+    assert_eq!(machine.get_reg(RSP).unwrap(), 0x5678);
+}
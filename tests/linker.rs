@@ -0,0 +1,164 @@
+use y86_rs::{Linker, LoadErrorKind, Machine, Object, StepMode};
+
+fn object(name: &str, text: &str) -> Object {
+    match Object::parse(name, text) {
+        Ok(object) => object,
+        Err(e) => panic!("{}: {}", name, e),
+    }
+}
+
+fn link(objects: Vec<Object>, base: usize) -> Result<y86_rs::Image, String> {
+    let mut linker = Linker::new();
+    for object in objects {
+        linker.add(object);
+    }
+    linker.link(base).map_err(|e| e.to_string())
+}
+
+const MAIN: &str = ".section text
+.global main
+.extern f
+0x000:                      | main:
+0x000: 800000000000000000   | 	call f
+0x009: 00                   | 	halt
+.reloc 0x001 f
+";
+
+const F: &str = ".section text
+.global f
+0x000:                      | f:
+0x000: 90                   | 	ret
+";
+
+#[test]
+fn undeclared_external_is_rejected() {
+    let text = ".section text
+0x000: 800000000000000000   | 	call g
+.reloc 0x001 g
+";
+    let e = match Object::parse("a.yro", text) {
+        Ok(_) => panic!("reloc against an undeclared symbol was accepted"),
+        Err(e) => e,
+    };
+    assert_eq!(e.line, 3);
+    assert!(matches!(e.kind, LoadErrorKind::Malformed(_)));
+}
+
+#[test]
+fn undefined_symbol() {
+    let e = link(vec![object("main.yro", MAIN)], 0).err().unwrap();
+    assert_eq!(e, "main.yro: undefined symbol `f`");
+}
+
+#[test]
+fn duplicate_global() {
+    let e = link(
+        vec![
+            object("main.yro", MAIN),
+            object("f.yro", F),
+            object("g.yro", F),
+        ],
+        0,
+    )
+    .err()
+    .unwrap();
+    assert_eq!(e, "g.yro: symbol `f` is already exported by f.yro");
+}
+
+#[test]
+fn sections_are_grouped_and_aligned() {
+    let data = ".section data
+0x000:                      | value:
+0x000: 2a00000000000000     | 	.quad 42
+.section text
+.global g
+0x000:                      | g:
+0x000: 30f00000000000000000 | 	irmovq value, %rax
+0x00a: 90                   | 	ret
+.reloc 0x002 value
+";
+    let image = link(
+        vec![
+            object("main.yro", MAIN),
+            object("f.yro", F),
+            object("g.yro", data),
+        ],
+        0x100,
+    )
+    .unwrap();
+    let addr = |name: &str| {
+        image
+            .symbols
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|&(_, addr)| addr)
+            .unwrap()
+    };
+    // every text section first, in object order, each 8 byte aligned; then data
+    assert_eq!(addr("main"), 0x100);
+    assert_eq!(addr("f"), 0x110);
+    assert_eq!(addr("g"), 0x118);
+    assert_eq!(addr("value"), 0x128);
+
+    let mut machine = Machine::new(0x200, StepMode::NoStep);
+    machine.load_image(&image).unwrap();
+    assert_eq!(machine.get_mem_word(0x101).unwrap(), 0x110);
+    assert_eq!(machine.get_mem_word(0x11a).unwrap(), 0x128);
+    assert_eq!(machine.get_mem_word(0x128).unwrap(), 42);
+    assert_eq!(machine.symbols().lookup("g"), Some(0x118));
+    assert_eq!(
+        machine.source().line_at(0x118).map(|(_, t)| t),
+        Some(" \tirmovq value, %rax")
+    );
+}
+
+fn parse_error(text: &str) -> y86_rs::LoadError {
+    match Object::parse("a.yro", text) {
+        Ok(_) => panic!("malformed object was accepted"),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn malformed_objects_are_rejected() {
+    let e = parse_error(
+        ".section text
+.extern x
+0x000: 00                   | 	halt
+.reloc 0xfffffffffffffff9 x
+",
+    );
+    assert_eq!((e.line, e.column), (4, 8));
+    assert!(matches!(e.kind, LoadErrorKind::OutOfRange { .. }));
+
+    let e = parse_error(
+        ".section text
+.extern x
+0x000: 800000000000000000   | 	call x
+.reloc  0x002 x
+",
+    );
+    assert_eq!((e.line, e.column), (4, 9));
+
+    let e = parse_error(
+        ".section text
+  0xffffffffffffff: 00      | 	halt
+",
+    );
+    assert_eq!((e.line, e.column), (2, 3));
+    assert!(matches!(e.kind, LoadErrorKind::OutOfRange { .. }));
+}
+
+#[test]
+fn image_must_fit_above_base() {
+    let e = link(
+        vec![object("main.yro", MAIN), object("f.yro", F)],
+        0xfffffffffffffffe,
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        e,
+        "main.yro: the image does not fit in memory from base 0xfffffffffffffffe"
+    );
+}
@@ -0,0 +1,229 @@
+use y86_rs::{LoadError, LoadErrorKind, LoadMode, Machine, StepMode};
+
+const MEM: usize = 0x100;
+
+fn load(text: &str) -> Result<Vec<LoadError>, LoadError> {
+    Machine::new(MEM, StepMode::NoStep).load_with(text, LoadMode::Fail)
+}
+
+fn error(text: &str) -> (usize, usize, LoadErrorKind) {
+    let e = load(text).unwrap_err();
+    (e.line, e.column, e.kind)
+}
+
+#[test]
+fn loads_listing_bytes() {
+    let mut machine = Machine::new(MEM, StepMode::NoStep);
+    let text = "0x000: 30f20a00000000000000 | irmovq $10, %rdx
+0x00a:                      | loop:
+0x00a: 00                   | halt
+";
+    assert_eq!(machine.load_with(text, LoadMode::Fail).unwrap(), []);
+    assert_eq!(machine.get_mem_word(2).unwrap(), 10);
+    assert_eq!(machine.symbols().name_at(0xa), Some("loop"));
+}
+
+#[test]
+fn odd_nibbles() {
+    assert_eq!(error("0x000: 300 | x"), (1, 10, LoadErrorKind::OddNibbles));
+}
+
+#[test]
+fn invalid_hex() {
+    assert_eq!(
+        error("\n0x000: 3g | x"),
+        (2, 9, LoadErrorKind::InvalidHex('g'))
+    );
+    assert_eq!(
+        error("0x0z0: 00 | x"),
+        (1, 4, LoadErrorKind::InvalidHex('z'))
+    );
+}
+
+#[test]
+fn missing_colon() {
+    assert_eq!(
+        error("0x000 00 | x"),
+        (1, 10, LoadErrorKind::Malformed("missing ':'"))
+    );
+}
+
+#[test]
+fn out_of_range() {
+    assert_eq!(
+        error("0x0ff: 0010 | x"),
+        (
+            1,
+            10,
+            LoadErrorKind::OutOfRange {
+                addr: 0x100,
+                size: MEM
+            }
+        )
+    );
+}
+
+#[test]
+fn address_overflow_is_out_of_range() {
+    assert_eq!(
+        error("0xffffffffffffffff: 0011 | x"),
+        (
+            1,
+            21,
+            LoadErrorKind::OutOfRange {
+                addr: usize::MAX,
+                size: MEM
+            }
+        )
+    );
+}
+
+#[test]
+fn overlap() {
+    assert_eq!(
+        error("0x000: 1010\n0x001: 00"),
+        (2, 8, LoadErrorKind::Overlap(1))
+    );
+}
+
+#[test]
+fn warn_mode_collects_errors() {
+    let mut machine = Machine::new(MEM, StepMode::NoStep);
+    let text = "0x000: 10
+0x001: 1x
+0x002: 100
+0x0ff: 1010
+0x000: 00
+0x003: 6300
+";
+    let warnings = machine.load_with(text, LoadMode::Warn).unwrap();
+    let kinds: Vec<(usize, LoadErrorKind)> =
+        warnings.into_iter().map(|e| (e.line, e.kind)).collect();
+    assert_eq!(
+        kinds,
+        [
+            (2, LoadErrorKind::InvalidHex('x')),
+            (3, LoadErrorKind::OddNibbles),
+            (
+                4,
+                LoadErrorKind::OutOfRange {
+                    addr: 0x100,
+                    size: MEM
+                }
+            ),
+            (5, LoadErrorKind::Overlap(0)),
+        ]
+    );
+    // what could be loaded still was
+    assert_eq!(machine.get_mem_word(0).unwrap() & 0xffffffff, 0x63000000);
+    assert_eq!(machine.get_mem_word(0xf8).unwrap() >> 56, 0x10);
+}
+
+#[test]
+fn reload_maps_lines_of_the_new_listing() {
+    let mut machine = Machine::new(MEM, StepMode::NoStep);
+    let first = "0x000: 10 | nop
+0x001: 10 | nop
+0x002: 00 | halt
+";
+    let second = "0x000:    | # second
+0x000: 6300 | xorq %rax, %rax
+0x002: 00   | halt
+";
+    machine.load_with(first, LoadMode::Fail).unwrap();
+    machine.load_with(second, LoadMode::Warn).unwrap();
+    let source = machine.source();
+    assert_eq!(source.line_at(0), Some((2, " xorq %rax, %rax")));
+    assert_eq!(source.line_at(2), Some((3, " halt")));
+    assert_eq!(source.line_at(1), None);
+    assert_eq!(source.lines().count(), 3);
+}
+
+// an Intel HEX record with its checksum
+fn record(kind: u8, addr: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8, (addr >> 8) as u8, addr as u8, kind];
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+    bytes.push(sum.wrapping_neg());
+    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!(":{}\n", hex)
+}
+
+const EOF_RECORD: &str = ":00000001FF\n";
+
+#[test]
+fn ihex_checksum_mismatch() {
+    let mut machine = Machine::new(MEM, StepMode::NoStep);
+    let e = machine
+        .load_ihex(":0100000010EE\n", LoadMode::Fail)
+        .unwrap_err();
+    assert_eq!((e.line, e.column), (1, 12));
+    assert_eq!(
+        e.kind,
+        LoadErrorKind::Checksum {
+            expected: 0xef,
+            found: 0xee
+        }
+    );
+}
+
+#[test]
+fn ihex_extended_segment_address() {
+    let mut machine = Machine::new(0x200, StepMode::NoStep);
+    let text = record(0x02, 0, &[0x00, 0x10]) + &record(0x00, 0x0008, &[0x2a]) + EOF_RECORD;
+    machine.load_ihex(&text, LoadMode::Fail).unwrap();
+    assert_eq!(machine.get_mem_word(0x108).unwrap(), 0x2a);
+}
+
+#[test]
+fn ihex_extended_linear_address() {
+    let mut machine = Machine::new(0x20000, StepMode::NoStep);
+    let text = record(0x04, 0, &[0x00, 0x01]) + &record(0x00, 0x0010, &[0x2b]) + EOF_RECORD;
+    machine.load_ihex(&text, LoadMode::Fail).unwrap();
+    assert_eq!(machine.get_mem_word(0x10010).unwrap(), 0x2b);
+
+    // past the end of memory
+    let text = record(0x04, 0, &[0x00, 0x02]) + &record(0x00, 0, &[0x2b]);
+    let e = machine.load_ihex(&text, LoadMode::Fail).unwrap_err();
+    assert_eq!(
+        e.kind,
+        LoadErrorKind::OutOfRange {
+            addr: 0x20000,
+            size: 0x20000
+        }
+    );
+}
+
+#[test]
+fn ihex_start_segment_address() {
+    let mut machine = Machine::new(MEM, StepMode::NoStep);
+    let text = record(0x03, 0, &[0x00, 0x01, 0x00, 0x04]) + EOF_RECORD;
+    machine.load_ihex(&text, LoadMode::Fail).unwrap();
+    assert_eq!(machine.pc(), 0x14);
+}
+
+#[test]
+fn ihex_start_linear_address() {
+    let mut machine = Machine::new(MEM, StepMode::NoStep);
+    let text = record(0x05, 0, &[0x00, 0x00, 0x00, 0x42]) + EOF_RECORD;
+    machine.load_ihex(&text, LoadMode::Fail).unwrap();
+    assert_eq!(machine.pc(), 0x42);
+}
+
+#[test]
+fn binary_runs_from_base() {
+    let mut machine = Machine::new(MEM, StepMode::NoStep);
+    // irmovq $7, %rax; halt
+    let image = [0x30, 0xf0, 7, 0, 0, 0, 0, 0, 0, 0, 0x00];
+    machine.load_binary(&image, 0x40).unwrap();
+    assert_eq!(machine.pc(), 0x40);
+    machine.run().unwrap();
+    assert_eq!(machine.get_reg(0).unwrap(), 7);
+}
+
+#[test]
+fn binary_base_past_memory() {
+    let mut machine = Machine::new(MEM, StepMode::NoStep);
+    assert!(machine.load_binary(&[0; 16], usize::MAX - 4).is_err());
+    assert!(machine.load_binary(&[0; 16], MEM - 8).is_err());
+}
@@ -0,0 +1,144 @@
+use std::io::Cursor;
+
+use y86_rs::LanguageServer;
+
+const URI: &str = "file:///tmp/y86-lsp-test.ys";
+
+fn frame(body: &str) -> String {
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn request(id: usize, method: &str, params: &str) -> String {
+    frame(&format!(
+        "{{\"jsonrpc\":\"2.0\",\"id\":{},\"method\":\"{}\",\"params\":{}}}",
+        id, method, params
+    ))
+}
+
+fn notification(method: &str, params: &str) -> String {
+    frame(&format!(
+        "{{\"jsonrpc\":\"2.0\",\"method\":\"{}\",\"params\":{}}}",
+        method, params
+    ))
+}
+
+fn did_open(text: &str) -> String {
+    let text = text
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    notification(
+        "textDocument/didOpen",
+        &format!(
+            "{{\"textDocument\":{{\"uri\":\"{}\",\"languageId\":\"y86\",\"version\":1,\"text\":\"{}\"}}}}",
+            URI, text
+        ),
+    )
+}
+
+fn position(line: usize, character: usize) -> String {
+    format!(
+        "{{\"textDocument\":{{\"uri\":\"{}\"}},\"position\":{{\"line\":{},\"character\":{}}}}}",
+        URI, line, character
+    )
+}
+
+// the bodies of every message the server sent in reply to `input`
+fn session(input: &[String]) -> Vec<String> {
+    let mut output = Vec::new();
+    LanguageServer::new()
+        .serve(&mut Cursor::new(input.concat()), &mut output)
+        .unwrap();
+    let mut output = String::from_utf8(output).unwrap();
+    let mut messages = Vec::new();
+    while let Some(rest) = output.strip_prefix("Content-Length: ") {
+        let (len, rest) = rest.split_once("\r\n\r\n").unwrap();
+        let (body, rest) = rest.split_at(len.parse().unwrap());
+        messages.push(body.to_string());
+        output = rest.to_string();
+    }
+    assert!(output.is_empty(), "{}", output);
+    messages
+}
+
+#[test]
+fn initialize_reports_capabilities() {
+    let messages = session(&[request(1, "initialize", "{}")]);
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].starts_with("{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\"capabilities\":"));
+    assert!(messages[0].contains("\"definitionProvider\":true"));
+}
+
+#[test]
+fn malformed_messages_are_skipped() {
+    let messages = session(&[frame("{not json"), request(2, "initialize", "{}")]);
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].contains("\"id\":2"));
+}
+
+#[test]
+fn diagnostics_use_utf16_columns() {
+    let messages = session(&[did_open("    nop\n\tbogus %rax # ééé😀\n")]);
+    assert_eq!(
+        messages,
+        [format!(
+            "{{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\"params\":{{\"uri\":\"{}\",\"diagnostics\":[{{\"range\":{{\"start\":{{\"line\":1,\"character\":1}},\"end\":{{\"line\":1,\"character\":19}}}},\"severity\":1,\"source\":\"y86\",\"message\":\"unknown instruction `bogus`\"}}]}}}}",
+            URI
+        )]
+    );
+
+    let messages = session(&[did_open("    halt\n")]);
+    assert!(messages[0].contains("\"diagnostics\":[]"));
+}
+
+#[test]
+fn definition_after_surrogate_pairs() {
+    // the second `end` starts at char 19, but at UTF-16 unit 23
+    let text = "    jmp end # 😀😀😀😀 end of line\nend:\n    halt\n";
+    let messages = session(&[
+        did_open(text),
+        request(1, "textDocument/definition", &position(0, 23)),
+        request(2, "textDocument/definition", &position(0, 19)),
+    ]);
+    assert_eq!(
+        messages[1],
+        format!(
+            "{{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{{\"uri\":\"{}\",\"range\":{{\"start\":{{\"line\":1,\"character\":0}},\"end\":{{\"line\":1,\"character\":3}}}}}}}}",
+            URI
+        )
+    );
+    // a char offset lands inside the emoji, not on a word
+    assert_eq!(
+        messages[2],
+        "{\"jsonrpc\":\"2.0\",\"id\":2,\"result\":null}"
+    );
+}
+
+#[test]
+fn oversized_messages_are_refused() {
+    let mut input = Cursor::new("Content-Length: 99999999999\r\n\r\n{}");
+    let e = LanguageServer::new()
+        .serve(&mut input, &mut Vec::new())
+        .unwrap_err();
+    assert_eq!(e.to_string(), "message of 99999999999 bytes is too large");
+}
+
+#[test]
+fn definition_of_a_name_inside_another() {
+    // `loop` first appears inside `myloop`, on both the label and the `.equ` line
+    let text =
+        "myloop: loop: jmp loop\n    .equ myloopn, 1\n  .equ loopn, 2\n    irmovq $loopn, %rax\n";
+    let messages = session(&[
+        did_open(text),
+        request(1, "textDocument/definition", &position(0, 19)),
+        request(2, "textDocument/definition", &position(3, 13)),
+    ]);
+    let range = |line, start, end| {
+        format!(
+            "\"range\":{{\"start\":{{\"line\":{},\"character\":{}}},\"end\":{{\"line\":{},\"character\":{}}}}}",
+            line, start, line, end
+        )
+    };
+    assert!(messages[1].contains(&range(0, 8, 12)), "{}", messages[1]);
+    assert!(messages[2].contains(&range(2, 7, 12)), "{}", messages[2]);
+}
@@ -0,0 +1,89 @@
+use y86_rs::{Assembler, Machine, MemView, Radix, StepMode, Unit};
+
+const DATA: &str = "    .pos 0x10
+text:
+    .byte 0x48, 0x69, 0x21, 0x7f, 0xff, 0xfe, 0, 0
+    .quad -2
+buf:
+";
+
+fn machine() -> Machine {
+    let program = Assembler::new().assemble("test.ys", DATA).unwrap();
+    let mut machine = Machine::new(0x100, StepMode::NoStep);
+    machine.load_program(&program).unwrap();
+    machine
+}
+
+fn view(spec: &str) -> String {
+    let machine = machine();
+    let view = MemView::parse(spec, machine.symbols()).unwrap();
+    match machine.view_mem(&view) {
+        Ok(out) => out,
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn parse_ranges_and_options() {
+    let machine = machine();
+    let parse = |spec| MemView::parse(spec, machine.symbols());
+    let view = parse("text:buf,bda").unwrap();
+    assert_eq!(view.range, 0x10..0x20);
+    assert!(view.unit == Unit::Byte && view.radix == Radix::Signed && view.ascii);
+    assert_eq!(parse("buf-8").unwrap().range, 0x18..0x58);
+    let top = parse("0x7fffffffffffffff").unwrap();
+    assert_eq!(top.range, 0x7fffffffffffffff..0x800000000000003f);
+    assert_eq!(
+        parse("text-0x20").err().unwrap(),
+        "`text-0x20` is a negative address"
+    );
+    assert_eq!(
+        parse("text,z").err().unwrap(),
+        "unknown view option 'z', expected b w l q x d u a"
+    );
+    assert!(parse("nowhere").is_err());
+}
+
+#[test]
+fn renders_values_and_ascii() {
+    assert_eq!(
+        view("text:buf,ba"),
+        "<text>:
+0x0010: 48 69 21 7f ff fe 00 00 fe ff ff ff ff ff ff ff  |Hi!.............|
+"
+    );
+    assert_eq!(
+        view("text:buf,wd"),
+        "<text>:
+0x0010:  26952  32545   -257      0     -2     -1     -1     -1
+"
+    );
+    assert_eq!(
+        view("text:buf,lua"),
+        "<text>:
+0x0010: 2132896072      65279 4294967294 4294967295  |Hi!.............|
+"
+    );
+    // a short last line keeps the ASCII column aligned
+    assert_eq!(
+        view("8:text+4,la"),
+        "0x0008: 00000000 00000000 7f216948           |........Hi!.|
+"
+    );
+}
+
+#[test]
+fn bad_views_are_errors() {
+    assert_eq!(
+        view("0x7fffffffffffffff"),
+        "range 0x7fffffffffffffff..0x800000000000003f is empty or outside memory (size 0x100)"
+    );
+    assert_eq!(
+        view("buf:text"),
+        "range 0x0020..0x0010 is empty or outside memory (size 0x100)"
+    );
+    assert_eq!(
+        view("0x11:0x14,w"),
+        "range of 0x3 bytes is not a whole number of 2 byte values"
+    );
+}
@@ -0,0 +1,186 @@
+use y86_rs::{Assembler, Hazards, Machine, Pipeline, Stall, StepMode};
+
+fn run(source: &str, hazards: Hazards) -> Pipeline {
+    let program = match Assembler::new().assemble("test.ys", source) {
+        Ok(program) => program,
+        Err(errors) => panic!("{}", errors[0]),
+    };
+    let mut machine = Machine::new(1 << 12, StepMode::NoStep);
+    machine.load_program(&program).unwrap();
+    let mut pipeline = Pipeline::new(hazards);
+    machine
+        .run_with(|machine, pc, state| pipeline.record(machine, pc, state))
+        .unwrap();
+    pipeline
+}
+
+// the textbook's prog1 to prog4: %rax is written by the second irmovq and read by addq
+fn dependent_add(nops: usize) -> String {
+    format!(
+        "    irmovq $10, %rdx
+    irmovq $3, %rax
+{}    addq %rdx, %rax
+    halt
+",
+        "    nop\n".repeat(nops)
+    )
+}
+
+#[test]
+fn data_hazards_without_forwarding() {
+    for hazards in [Hazards::Stall, Hazards::PipeMinus] {
+        for (nops, bubbles) in [(3, 0), (2, 1), (1, 2), (0, 3)] {
+            let pipeline = run(&dependent_add(nops), hazards);
+            assert_eq!(pipeline.stall_cycles(Stall::Data), bubbles, "{nops} nops");
+            // 4 cycles to fill the pipeline, then one per instruction or bubble
+            assert_eq!(pipeline.cycles(), 4 + 4 + nops + bubbles, "{nops} nops");
+        }
+    }
+}
+
+#[test]
+fn forwarding_removes_data_hazards() {
+    for nops in 0..=3 {
+        let pipeline = run(&dependent_add(nops), Hazards::Forward);
+        assert_eq!(pipeline.stall_cycles(Stall::Data), 0);
+        assert_eq!(pipeline.cycles(), 4 + 4 + nops);
+    }
+}
+
+const LOAD_ADD: &str = "    irmovq data, %rdx
+    mrmovq 0(%rdx), %rax
+    addq %rax, %rbx
+    halt
+    .align 8
+data:
+    .quad 1
+";
+
+const LOAD_STORE: &str = "    irmovq data, %rdx
+    mrmovq 0(%rdx), %rax
+    rmmovq %rax, 8(%rdx)
+    halt
+    .align 8
+data:
+    .quad 1, 0
+";
+
+#[test]
+fn load_use_takes_one_bubble() {
+    for hazards in [Hazards::Forward, Hazards::LoadForward] {
+        let pipeline = run(LOAD_ADD, hazards);
+        assert_eq!(pipeline.stall_cycles(Stall::LoadUse), 1);
+        assert_eq!(pipeline.cycles(), 4 + 4 + 1);
+    }
+    let pipeline = run(LOAD_STORE, Hazards::Forward);
+    assert_eq!(pipeline.stall_cycles(Stall::LoadUse), 1);
+}
+
+#[test]
+fn load_forwarding_feeds_a_store_without_a_bubble() {
+    let pipeline = run(LOAD_STORE, Hazards::LoadForward);
+    assert_eq!(pipeline.stall_cycles(Stall::LoadUse), 0);
+    assert_eq!(pipeline.cycles(), 4 + 4);
+}
+
+#[test]
+fn ret_takes_three_bubbles() {
+    let pipeline = run(
+        "    irmovq stack, %rsp
+    call f
+    halt
+f:
+    ret
+    .pos 0x100
+stack:
+",
+        Hazards::Forward,
+    );
+    assert_eq!(pipeline.stall_cycles(Stall::Return), 3);
+    assert_eq!(pipeline.cycles(), 4 + 4 + 3);
+    let halt = &pipeline.timings()[3];
+    let ret = &pipeline.timings()[2];
+    assert_eq!(halt.fetch, ret.memory() + 1);
+}
+
+#[test]
+fn mispredicted_jump_squashes_two_instructions() {
+    let pipeline = run(
+        "    xorq %rax, %rax
+    jne target
+    irmovq $1, %rbx
+    halt
+target:
+    irmovq $2, %rcx
+    halt
+",
+        Hazards::Forward,
+    );
+    assert_eq!(pipeline.stall_cycles(Stall::Mispredict), 2);
+    let squashed: Vec<(usize, Option<usize>)> = pipeline
+        .squashed()
+        .iter()
+        .map(|s| (s.pc, s.decode))
+        .collect();
+    let jump = &pipeline.timings()[1];
+    assert_eq!(
+        squashed,
+        [(0x16, Some(jump.execute)), (0x20, None)],
+        "{:?}",
+        pipeline.squashed()
+    );
+    // the fall through instruction is fetched once the jump has executed
+    assert_eq!(pipeline.timings()[2].fetch, jump.execute + 1);
+    assert_eq!(pipeline.cycles(), 4 + 4 + 2);
+}
+
+#[test]
+fn stall_mode_waits_for_every_conditional_jump() {
+    let source = "    xorq %rax, %rax
+    je next
+next:
+    halt
+";
+    let pipeline = run(source, Hazards::Stall);
+    assert_eq!(pipeline.stall_cycles(Stall::Branch), 2);
+    assert!(pipeline.squashed().is_empty());
+    // a correctly predicted jump costs nothing with prediction
+    let pipeline = run(source, Hazards::PipeMinus);
+    assert_eq!(pipeline.cycles(), 4 + 3);
+}
+
+#[test]
+fn diagram_shows_stalls_squashes_and_bubbles() {
+    let pipeline = run(
+        "    irmovq data, %rdx
+    mrmovq 0(%rdx), %rax
+    addq %rax, %rbx
+    je skip
+    halt
+skip:
+    irmovq $1, %rcx
+    halt
+    .align 8
+data:
+    .quad 1
+",
+        Hazards::Forward,
+    );
+    // addq waits a cycle in decode for the load, holding je up in fetch; je is not taken,
+    // so the two instructions fetched from skip are squashed and bubbles take their place
+    assert_eq!(
+        pipeline.diagram(1..=12),
+        "                                      1  2  3  4  5  6  7  8  9 10 11 12
+0x0000: irmovq $0x30, %rdx            F  D  E  M  W
+0x000a: mrmovq 0x0(%rdx), %rax           F  D  E  M  W
+bubble                                            E  M  W
+0x0014: addq %rax, %rbx                     F  D  d  E  M  W
+0x0016: je skip                                F  f  D  E  M  W
+0x0020: irmovq $0x1, %rcx (squashed)                 F  D
+0x002a: halt (squashed)                                 F
+bubble                                                     E  M  W
+bubble                                                        E  M  W
+0x001f: halt                                               F  D  E  M  W
+"
+    );
+}
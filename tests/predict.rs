@@ -0,0 +1,67 @@
+use y86_rs::{predictor, Assembler, BranchStats, Machine, StepMode};
+
+// four passes: `jne loop` goes back three times, and the forward `je` in f never jumps
+const LOOP: &str = "    irmovq stack, %rsp
+    irmovq $4, %rcx
+    irmovq $1, %rdx
+loop:
+    call f
+    subq %rdx, %rcx
+    jne loop
+    halt
+f:
+    andq %rcx, %rcx
+    je skip
+skip:
+    ret
+    .pos 0x200
+stack:
+";
+
+fn run(name: &str) -> (Machine, BranchStats) {
+    let program = Assembler::new().assemble("test.ys", LOOP).unwrap();
+    let mut machine = Machine::new(1 << 10, StepMode::NoStep);
+    machine.load_program(&program).unwrap();
+    let mut stats = BranchStats::new(predictor(name).unwrap());
+    machine
+        .run_with(|_, pc, state| stats.record(pc, state))
+        .unwrap();
+    (machine, stats)
+}
+
+#[test]
+fn predictors_on_a_small_loop() {
+    // mispredicted jumps of the 8 conditional ones
+    let cases = [
+        ("taken", 5),
+        ("not-taken", 3),
+        ("btfnt", 1),
+        ("1bit", 2),
+        ("2bit", 2),
+    ];
+    for (name, misses) in cases {
+        // every one of the 4 returns is a miss without a return stack, and none with one
+        assert_eq!(run(name).1.mispredicted(), misses + 4, "{}", name);
+        let ras = format!("{}+ras", name);
+        assert_eq!(run(&ras).1.mispredicted(), misses, "{}", ras);
+    }
+    assert!(predictor("3bit").is_none());
+}
+
+#[test]
+fn report_lists_each_branch() {
+    let (machine, stats) = run("2bit");
+    assert_eq!(
+        stats.report(&machine),
+        "Branch prediction: 2-bit saturating counters
+  conditional jumps      8  mispredicted      2   75.00% correct
+  returns                4  mispredicted      4    0.00% correct
+  0x0029 <loop+0xb>              jne loop                 taken 3/4      mispredicted     1  75.00% correct
+  0x0035 <f+0x2>                 je skip                  taken 0/4      mispredicted     1  75.00% correct
+  0x003e <skip>                  ret                      ran 4          mispredicted     4   0.00% correct
+"
+    );
+    let (machine, stats) = run("2bit+ras");
+    let report = stats.report(&machine);
+    assert!(report.contains("\n  returns                4  mispredicted      0  100.00% correct\n"));
+}
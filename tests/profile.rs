@@ -0,0 +1,58 @@
+use y86_rs::{Assembler, Machine, Profile, StepMode};
+
+// sum(n) recurses n times and calls leaf on the way back out; the program halts in leaf
+const RECURSIVE: &str = "    irmovq stack, %rsp
+    irmovq $3, %rdi
+    call sum
+    call leaf
+    halt
+sum:
+    andq %rdi, %rdi
+    je done
+    pushq %rdi
+    irmovq $1, %rax
+    subq %rax, %rdi
+    call sum
+    popq %rdi
+    addq %rdi, %rax
+    ret
+done:
+    xorq %rax, %rax
+    ret
+leaf:
+    halt
+    .pos 0x400
+stack:
+";
+
+#[test]
+fn recursive_routines_count_each_instruction_once() {
+    let program = Assembler::new().assemble("test.ys", RECURSIVE).unwrap();
+    let mut machine = Machine::new(1 << 12, StepMode::NoStep);
+    machine.load_program(&program).unwrap();
+    let mut profile = Profile::new(&machine);
+    machine
+        .run_with(|machine, pc, state| profile.record(machine, pc, state))
+        .unwrap();
+
+    // main runs 4 instructions and leaf 1; each of the 4 sum frames tests and branches,
+    // then 3 run 7 more and the innermost 2
+    assert_eq!(profile.total(), 4 + 4 * 2 + 3 * 7 + 2 + 1);
+    assert_eq!(profile.count(machine.symbols().lookup("sum").unwrap()), 4);
+    let report = machine.profile_report(&profile, 3);
+    let functions: Vec<&str> = report
+        .lines()
+        .skip_while(|l| *l != "Functions:")
+        .skip(2)
+        .take_while(|l| !l.is_empty())
+        .collect();
+    assert_eq!(
+        functions,
+        [
+            "         4        31  86.11%        31  86.11% | 0x0027 <sum>",
+            "         0         4  11.11%        36 100.00% | 0x0000",
+            "         1         1   2.78%         1   2.78% | 0x0051 <leaf>",
+        ]
+    );
+    assert!(report.contains("         4  11.11% | 6:2 andq\n"));
+}
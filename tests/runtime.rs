@@ -0,0 +1,203 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use y86_rs::{Assembler, Console, Machine, StepMode};
+
+const RAX: usize = 0;
+const RDX: usize = 2;
+const RBX: usize = 3;
+const RBP: usize = 5;
+
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// `body` runs with a stack set up and callee-saved registers seeded, then halts.
+// `data` is placed at 0x800.
+fn run(include: &str, body: &str, data: &str) -> (Machine, String) {
+    let source = format!(
+        "    irmovq stack, %rsp
+    irmovq $0x3b3b, %rbx
+    irmovq $0x5b5b, %rbp
+{body}
+    halt
+.include \"y86/{include}.ys\"
+    .pos 0x800
+data:
+{data}
+    .pos 0x1800
+stack:
+"
+    );
+    let program = match Assembler::new().assemble("test.ys", &source) {
+        Ok(program) => program,
+        Err(errors) => panic!("{}", errors[0]),
+    };
+
+    let out = Output::default();
+    let mut machine = Machine::with_extensions(
+        1 << 13,
+        StepMode::NoStep,
+        vec![Box::new(Console::new(Box::new(out.clone())))],
+    );
+    machine.load_program(&program).unwrap();
+    machine.run().unwrap();
+
+    assert_eq!(machine.get_reg(RBX).unwrap(), 0x3b3b);
+    assert_eq!(machine.get_reg(RBP).unwrap(), 0x5b5b);
+    let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+    (machine, text)
+}
+
+fn mem_bytes(machine: &Machine, addr: usize, len: usize) -> Vec<u8> {
+    (addr..addr + len)
+        .step_by(8)
+        .flat_map(|a| machine.get_mem_word(a).unwrap().to_le_bytes())
+        .take(len)
+        .collect()
+}
+
+#[test]
+fn memcpy_copies_bytes() {
+    let (machine, _) = run(
+        "memcpy",
+        "    irmovq data, %rsi
+    irmovq $0x823, %rdi
+    irmovq $11, %rdx
+    call memcpy",
+        "    .quad 0x0807060504030201
+    .quad 0x100f0e0d0c0b0a09
+    .quad 0
+    .quad 0
+    .quad 0
+    .quad 0",
+    );
+    assert_eq!(machine.get_reg(RAX).unwrap(), 0x823);
+    assert_eq!(
+        mem_bytes(&machine, 0x820, 16),
+        [0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 0, 0]
+    );
+}
+
+#[test]
+fn memcpy_zero_length() {
+    let (machine, _) = run(
+        "memcpy",
+        "    irmovq data, %rsi
+    irmovq $0x808, %rdi
+    xorq %rdx, %rdx
+    call memcpy",
+        "    .quad -1
+    .quad 0",
+    );
+    assert_eq!(machine.get_mem_word(0x808).unwrap(), 0);
+}
+
+#[test]
+fn memset_fills_bytes() {
+    let (machine, _) = run(
+        "memset",
+        "    irmovq $0x801, %rdi
+    irmovq $0x1aa, %rsi
+    irmovq $5, %rdx
+    call memset",
+        "    .quad 0x1111111111111111
+    .quad 0",
+    );
+    assert_eq!(machine.get_reg(RAX).unwrap(), 0x801);
+    assert_eq!(machine.get_mem_word(0x800).unwrap(), 0x1111aaaaaaaaaa11);
+}
+
+#[test]
+fn strlen_counts_to_nul() {
+    let (machine, _) = run(
+        "strlen",
+        "    irmovq data, %rdi
+    call strlen",
+        "    .byte 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x79, 0x38, 0x36, 0
+    .quad 0",
+    );
+    assert_eq!(machine.get_reg(RAX).unwrap(), 9);
+
+    let (machine, _) = run(
+        "strlen",
+        "    irmovq data, %rdi
+    call strlen",
+        "    .quad 0",
+    );
+    assert_eq!(machine.get_reg(RAX).unwrap(), 0);
+}
+
+#[test]
+fn mul_multiplies() {
+    for (a, b) in [(6, 7), (-3, 5), (-4, -9), (0, 123), (1 << 40, 3)] {
+        let (machine, _) = run(
+            "mul",
+            &format!(
+                "    irmovq ${a}, %rdi
+    irmovq ${b}, %rsi
+    call mul"
+            ),
+            "",
+        );
+        assert_eq!(machine.get_reg(RAX).unwrap(), a * b, "{a} * {b}");
+    }
+}
+
+#[test]
+fn div_quotient_and_remainder() {
+    for (a, b) in [(100, 7), (6, 3), (5, 9), (0, 1), (0x7fffffffffffffff, 10)] {
+        let (machine, _) = run(
+            "div",
+            &format!(
+                "    irmovq ${a}, %rdi
+    irmovq ${b}, %rsi
+    call div"
+            ),
+            "",
+        );
+        assert_eq!(machine.get_reg(RAX).unwrap(), a / b, "{a} / {b}");
+        assert_eq!(machine.get_reg(RDX).unwrap(), a % b, "{a} % {b}");
+    }
+}
+
+#[test]
+fn print_int_writes_decimal() {
+    for x in [0, 7, 1234567890, -42, isize::MIN] {
+        let (_, text) = run(
+            "print_int",
+            &format!(
+                "    irmovq ${x}, %rdi
+    call print_int"
+            ),
+            "",
+        );
+        assert_eq!(text, x.to_string());
+    }
+}
+
+#[test]
+fn print_int_and_putc_include_once() {
+    let (_, text) = run(
+        "print_int",
+        "    irmovq $-5, %rdi
+    call print_int
+    irmovq $10, %rdi
+    call putc",
+        ".include \"y86/console.ys\"",
+    );
+    assert_eq!(text, "-5\n");
+}
@@ -0,0 +1,116 @@
+use y86_rs::{Assembler, DecodeMode, Machine, StepMode};
+
+const RAX: usize = 0;
+
+fn machine(source: &str, decode_mode: DecodeMode) -> Machine {
+    let program = match Assembler::new().assemble("test.ys", source) {
+        Ok(program) => program,
+        Err(errors) => panic!("{}", errors[0]),
+    };
+    let mut machine = Machine::new(1 << 10, StepMode::NoStep);
+    machine.set_decode_mode(decode_mode);
+    machine.load_program(&program).unwrap();
+    machine
+}
+
+fn violation(bytes: &str) -> String {
+    let mut machine = machine(&format!("    .byte {}\n", bytes), DecodeMode::Strict);
+    match machine.step() {
+        Ok(_) => panic!("{} decoded in strict mode", bytes),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn strict_mode_reports_violations() {
+    let cases = [
+        (
+            "0x01",
+            "INS fault at 0x0000: halt must have ifun 0, found 1",
+        ),
+        ("0x11", "INS fault at 0x0000: nop must have ifun 0, found 1"),
+        (
+            "0x30, 0x00, 0, 0, 0, 0, 0, 0, 0, 0",
+            "INS fault at 0x0000: irmovq rA must be F, found 0",
+        ),
+        (
+            "0x60, 0x0f",
+            "INS fault at 0x0000: OPq rB must name a register, found F",
+        ),
+        (
+            "0x20, 0xf0",
+            "INS fault at 0x0000: rrmovq/cmovXX rA must name a register, found F",
+        ),
+        (
+            "0xa0, 0x00",
+            "INS fault at 0x0000: pushq rB must be F, found 0",
+        ),
+        (
+            "0xb0, 0xf0",
+            "INS fault at 0x0000: popq rA must name a register, found F",
+        ),
+        (
+            "0x81, 0, 0, 0, 0, 0, 0, 0, 0",
+            "INS fault at 0x0000: call must have ifun 0, found 1",
+        ),
+    ];
+    for (bytes, message) in cases {
+        assert!(
+            violation(bytes).starts_with(message),
+            "{}: {}",
+            bytes,
+            violation(bytes)
+        );
+    }
+}
+
+#[test]
+fn strict_mode_accepts_stock_encodings() {
+    let mut machine = machine(
+        "    irmovq $5, %rax
+    addq %rax, %rax
+    rrmovq %rax, %rbx
+    halt
+",
+        DecodeMode::Strict,
+    );
+    machine.run().unwrap();
+    assert_eq!(machine.get_reg(RAX).unwrap(), 10);
+}
+
+const ABSOLUTE: &str = "    irmovq $7, %rcx
+    rmmovq %rcx, 0x100
+    mrmovq 0x100, %rax
+    halt
+";
+
+#[test]
+fn absolute_addressing_in_both_modes() {
+    for mode in [DecodeMode::Strict, DecodeMode::Lenient] {
+        let mut machine = machine(ABSOLUTE, mode);
+        machine.run().unwrap();
+        assert_eq!(machine.get_reg(RAX).unwrap(), 7, "{:?}", mode);
+        assert_eq!(machine.get_mem_word(0x100).unwrap(), 7, "{:?}", mode);
+    }
+}
+
+#[test]
+fn unknown_codes_are_ins_faults_in_strict_mode() {
+    let cases = [
+        ("0xc0", "INS fault at 0x0000: unknown icode c"),
+        ("0x64, 0x01", "INS fault at 0x0000: icode 6 has no ifun 4"),
+        ("0x27, 0x01", "INS fault at 0x0000: icode 2 has no ifun 7"),
+        (
+            "0x7f, 0, 0, 0, 0, 0, 0, 0, 0",
+            "INS fault at 0x0000: icode 7 has no ifun f",
+        ),
+    ];
+    for (bytes, message) in cases {
+        assert!(
+            violation(bytes).starts_with(message),
+            "{}: {}",
+            bytes,
+            violation(bytes)
+        );
+    }
+}
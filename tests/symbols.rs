@@ -0,0 +1,53 @@
+use y86_rs::{Assembler, Machine, StepMode, SymbolTable};
+
+#[test]
+fn nearest_symbol_at_boundaries() {
+    let mut symbols = SymbolTable::new();
+    symbols.insert("start", 0x10);
+    symbols.insert("loop", 0x20);
+    // the first label at an address names it, though both can be looked up
+    symbols.insert("again", 0x20);
+
+    assert_eq!(symbols.nearest(0xf), None);
+    assert_eq!(symbols.nearest(0x10), Some(("start", 0)));
+    assert_eq!(symbols.nearest(0x1f), Some(("start", 0xf)));
+    assert_eq!(symbols.nearest(0x20), Some(("loop", 0)));
+    assert_eq!(
+        symbols.nearest(usize::MAX),
+        Some(("loop", usize::MAX - 0x20))
+    );
+    assert_eq!(symbols.lookup("again"), Some(0x20));
+
+    assert_eq!(symbols.symbolize(0xf), "0x000f");
+    assert_eq!(symbols.symbolize(0x10), "start");
+    assert_eq!(symbols.symbolize(0x1f), "start+0xf");
+    assert_eq!(symbols.symbolize(0x20), "loop");
+}
+
+#[test]
+fn disassembly_names_jump_and_call_targets() {
+    let source = "    jmp 0x2
+    call f+1
+    jne f
+    je end+0x10
+f:
+    nop
+    ret
+end:
+";
+    let program = match Assembler::new().assemble("test.ys", source) {
+        Ok(program) => program,
+        Err(errors) => panic!("{}", errors[0]),
+    };
+    let mut machine = Machine::new(0x100, StepMode::NoStep);
+    machine.load_program(&program).unwrap();
+    let mut addr = 0;
+    let mut lines = Vec::new();
+    for _ in 0..4 {
+        let (instr, len) = machine.disassemble(addr).unwrap();
+        lines.push(instr);
+        addr += len;
+    }
+    // 0x2 comes before the first label, and `end+0x10` is past the last
+    assert_eq!(lines, ["jmp 0x0002", "call f+0x1", "jne f", "je end+0x10"]);
+}
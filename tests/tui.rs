@@ -0,0 +1,136 @@
+use y86_rs::{Assembler, Machine, StepMode, TuiKey, TuiState};
+
+const PROGRAM: &str = "main:
+    irmovq stack, %rsp
+    irmovq $5, %rax
+    pushq %rax
+    halt
+    .pos 0x40
+data:
+    .quad 0x41
+    .pos 0x80
+stack:
+";
+
+fn machine() -> Machine {
+    let program = Assembler::new().assemble("test.ys", PROGRAM).unwrap();
+    let mut machine = Machine::new(0x100, StepMode::NoStep);
+    machine.load_program(&program).unwrap();
+    machine
+}
+
+// the screen without its escapes, with trailing spaces trimmed
+fn plain(machine: &Machine, tui: &TuiState) -> String {
+    let mut out = String::new();
+    for line in machine.tui_screen(tui) {
+        let mut escape = false;
+        let text: String = line
+            .chars()
+            .filter(|&c| match c {
+                '\x1b' => {
+                    escape = true;
+                    false
+                }
+                'm' if escape => {
+                    escape = false;
+                    false
+                }
+                _ => !escape,
+            })
+            .collect();
+        out.push_str(text.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+const STEPPED: &str = "Registers                                      Disassembly
+ %rax: 0x0000000000000005                    5    0x0000 <main>       irmovq $0x80, %rsp
+ %rcx: 0x0000000000000000                    0    0x000a              irmovq $0x5, %rax
+ %rdx: 0x0000000000000000                    0    0x0014              pushq %rax
+ %rbx: 0x0000000000000000                    0  > 0x0016              halt
+ %rsp: 0x0000000000000078                  120    0x0040 <data>       rmmovq %rax, 0x0(%rax)
+ %rbp: 0x0000000000000000                    0
+ %rsi: 0x0000000000000000                    0
+ %rdi: 0x0000000000000000                    0
+ %r08: 0x0000000000000000                    0
+ %r09: 0x0000000000000000                    0
+ %r10: 0x0000000000000000                    0
+ %r11: 0x0000000000000000                    0
+ %r12: 0x0000000000000000                    0
+ %r13: 0x0000000000000000                    0
+ %r14: 0x0000000000000000                    0
+SF: 0  ZF: 0  OF: 0
+STAT: AOK  cycle 3
+PC: 0x0016 <main+0x16>
+
+Stack                                          Memory 0x0040..0x00d0
+        0x0068: 0x0000000000000000             0x0040: 0000000000000041 0000000000000000
+        0x0070: 0x0000000000000000             0x0050: 0000000000000000 0000000000000000
+%rsp -> 0x0078: 0x0000000000000005             0x0060: 0000000000000000 0000000000000000
+        0x0080: 0x0000000000000000             0x0070: 0000000000000000 0000000000000005
+        0x0088: 0x0000000000000000             0x0080: 0000000000000000 0000000000000000
+        0x0090: 0x0000000000000000             0x0090: 0000000000000000 0000000000000000
+        0x0098: 0x0000000000000000             0x00a0: 0000000000000000 0000000000000000
+        0x00a0: 0x0000000000000000             0x00b0: 0000000000000000 0000000000000000
+        0x00a8: 0x0000000000000000             0x00c0: 0000000000000000 0000000000000000
+
+Stages
+pushq %rax  icode:ifun = push:none  rA:rB = 0:f  Cnd = false
+valC = 0x0000000000000000  valP = 0x0000000000000016  valA = 0x0000000000000005
+valB = 0x0000000000000080  valE = 0x0000000000000078  valM = 0x0000000000000000
+Backtrace: 0x0016 <main+0x16>
+
+
+s step  n over  f finish  c continue  j/k move  b breakpoint  m memory  [/] scroll  q quit
+";
+
+// the line above the key help
+fn message(machine: &Machine, tui: &TuiState) -> String {
+    let screen = machine.tui_screen(tui);
+    screen[screen.len() - 2].clone()
+}
+
+fn keys(machine: &mut Machine, tui: &mut TuiState, keys: &[u8]) {
+    for &key in keys {
+        assert!(machine.tui_key(tui, TuiKey::Char(key)));
+    }
+}
+
+#[test]
+fn screen_after_steps() {
+    let mut machine = machine();
+    let mut tui = TuiState::new(&machine);
+    let screen = plain(&machine, &tui);
+    assert!(screen.contains("\n %rax: 0x0000000000000000                    0  > 0x0000 <main>"));
+    assert!(screen.contains("\nStages\nno cycle run yet\nBacktrace: 0x0000 <main>\n"));
+
+    keys(&mut machine, &mut tui, b"sss");
+    assert_eq!(plain(&machine, &tui), STEPPED);
+    // what the last step changed is highlighted, and so is the cursor
+    let screen = machine.tui_screen(&tui);
+    assert!(screen[5].starts_with("\x1b[1;33m %rsp: 0x0000000000000078"));
+    assert!(screen[1].starts_with(" %rax"));
+    assert!(screen[4].contains("\x1b[7m > 0x0016"));
+    assert!(screen[23].starts_with("\x1b[1;33m%rsp -> 0x0078"));
+}
+
+#[test]
+fn memory_prompt_and_keys() {
+    let mut machine = machine();
+    let mut tui = TuiState::new(&machine);
+    keys(&mut machine, &mut tui, b"mdata+8");
+    assert_eq!(message(&machine, &tui), "memory view: data+8");
+    keys(&mut machine, &mut tui, b"\r]");
+    let screen = plain(&machine, &tui);
+    assert!(screen.contains("Memory 0x0058..0x00e8\n"), "{}", screen);
+
+    keys(&mut machine, &mut tui, b"mnowhere\r");
+    assert_eq!(message(&machine, &tui), "undefined symbol `nowhere`");
+
+    // a breakpoint at the cursor, moved down one instruction, and `c` stops there
+    keys(&mut machine, &mut tui, b"jbc");
+    assert_eq!(machine.pc(), 0xa);
+    assert!(machine.tui_screen(&tui)[2].contains("*> 0x000a"));
+    assert!(!machine.tui_key(&mut tui, TuiKey::Char(b'q')));
+}
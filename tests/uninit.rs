@@ -0,0 +1,105 @@
+use y86_rs::{Assembler, Machine, StepMode};
+
+const SEED: u64 = 0x1234_5678;
+
+fn machine(source: &str, garbage: Option<u64>) -> Machine {
+    let program = match Assembler::new().assemble("test.ys", source) {
+        Ok(program) => program,
+        Err(errors) => panic!("{}", errors[0]),
+    };
+    let mut machine = Machine::new(1 << 10, StepMode::NoStep);
+    if let Some(seed) = garbage {
+        machine.fill_garbage(seed);
+    }
+    machine.set_check_uninit(true);
+    machine.load_program(&program).unwrap();
+    machine
+}
+
+// every warning, in order, from running `machine` to the end
+fn warnings(machine: &mut Machine) -> Vec<String> {
+    let mut warnings = Vec::new();
+    while machine.is_running() {
+        machine.step().unwrap();
+        warnings.extend(machine.take_warnings());
+    }
+    warnings
+}
+
+#[test]
+fn reads_before_writes_are_reported_once() {
+    let mut machine = machine(
+        "    irmovq $0x100, %rdx
+    irmovq $2, %rcx
+    irmovq $1, %rdi
+loop:
+    mrmovq 8(%rdx), %rax
+    addq %rbx, %rax
+    rmmovq %rax, 8(%rdx)
+    subq %rdi, %rcx
+    jne loop
+    halt
+",
+        None,
+    );
+    // the second pass reads what the first wrote, and each instruction is reported once
+    assert_eq!(
+        warnings(&mut machine),
+        [
+            "read of uninitialized memory 0x0108..0x0110 by `mrmovq 0x8(%rdx), %rax` at 0x001e <loop>",
+            "read of uninitialized %rbx by `addq %rbx, %rax` at 0x0028 <loop+0xa>",
+        ]
+    );
+}
+
+const STORE: &str = "    irmovq $0x2a, %rax
+    rmmovq %rax, 0x100
+    halt
+    .pos 0x20
+value:
+    .quad 0
+";
+
+#[test]
+fn garbage_fill_is_deterministic() {
+    let words = |seed| {
+        let machine = machine(STORE, Some(seed));
+        (0x28..0x400)
+            .step_by(8)
+            .map(|addr| machine.get_mem_word(addr).unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(words(SEED), words(SEED));
+    assert_ne!(words(SEED), words(SEED * 3));
+    // the program and its zeroed data overwrite the garbage
+    let machine = machine(STORE, Some(SEED));
+    assert_eq!(machine.get_mem_word(0x20).unwrap(), 0);
+    assert!(machine.is_initialized(0x27));
+    assert!(!machine.is_initialized(0x28));
+    assert_ne!(machine.get_mem_word(0x100).unwrap(), 0);
+}
+
+#[test]
+fn memory_listing_leaves_out_unwritten_words() {
+    let mut machine = machine(STORE, Some(SEED));
+    machine.run().unwrap();
+    // the word holding `halt` shows the garbage after it; the zero at `value` is left out
+    // like any zero word
+    assert_eq!(
+        machine.to_string(),
+        "
+Cycle Count: 3
+
+0x0000: 30f02a0000000000
+0x0008: 0000400f00010000
+0x0010: 0000000000d4b55e
+0x0100: 2a00000000000000
+
+%rax: 0x000000000000002a
+
+SF: 0\tZF: 0\tOF: 0
+STAT: HLT
+PC: 0x0015
+"
+    );
+}